@@ -0,0 +1,203 @@
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Emitter};
+
+use crate::cnc::{ChannelKey, ToolData};
+use crate::logger::HistoryLogger;
+use crate::worker::{CncBackend, FocasWorker};
+
+// 백그라운드 루프에서 UI로 알람 이벤트를 보내기 위한 핸들 (setup에서 한 번만 설정)
+pub static APP_HANDLE: OnceLock<AppHandle> = OnceLock::new();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AlarmLevel {
+    Warning,
+    Critical,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AlarmKind {
+    ToolLife,
+    DriftLimit,
+    StepLimit,
+    ApprovalRequired,
+    SpcViolation,
+    RateSuggestion,
+}
+
+impl AlarmLevel {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AlarmLevel::Warning => "warning",
+            AlarmLevel::Critical => "critical",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "warning" => Some(AlarmLevel::Warning),
+            "critical" => Some(AlarmLevel::Critical),
+            _ => None,
+        }
+    }
+}
+
+impl AlarmKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AlarmKind::ToolLife => "tool_life",
+            AlarmKind::DriftLimit => "drift_limit",
+            AlarmKind::StepLimit => "step_limit",
+            AlarmKind::ApprovalRequired => "approval_required",
+            AlarmKind::SpcViolation => "spc_violation",
+            AlarmKind::RateSuggestion => "rate_suggestion",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "tool_life" => Some(AlarmKind::ToolLife),
+            "drift_limit" => Some(AlarmKind::DriftLimit),
+            "step_limit" => Some(AlarmKind::StepLimit),
+            "approval_required" => Some(AlarmKind::ApprovalRequired),
+            "spc_violation" => Some(AlarmKind::SpcViolation),
+            "rate_suggestion" => Some(AlarmKind::RateSuggestion),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AlarmLog {
+    pub timestamp: DateTime<Utc>,
+    pub machine_id: u16,
+    pub path: i16,
+    pub tool_num: i16,
+    pub kind: AlarmKind,
+    pub level: AlarmLevel,
+    pub message: String,
+}
+
+/// PMC 영역에 1바이트를 쓰는 정지 신호 (예: R영역 adr_type=5)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PmcSignal {
+    pub adr_type: i16,
+    pub address: u16,
+    pub value: u8,
+}
+
+impl PmcSignal {
+    pub fn write(&self, client: &dyn CncBackend) -> anyhow::Result<()> {
+        client.write_pmc(self.adr_type, self.address, &[self.value])
+    }
+}
+
+/// 알람을 기록하고 UI에 `tool-alarm` 이벤트로 알린다.
+pub fn raise_alarm(logger: &HistoryLogger, alarm: AlarmLog) {
+    eprintln!(
+        "[ALARM] machine {}, tool P{} T{} ({} / {}): {}",
+        alarm.machine_id,
+        alarm.path,
+        alarm.tool_num,
+        alarm.kind.as_str(),
+        alarm.level.as_str(),
+        alarm.message
+    );
+    if let Some(app) = APP_HANDLE.get() {
+        if let Err(e) = app.emit("tool-alarm", &alarm) {
+            eprintln!("Failed to emit alarm event: {}", e);
+        }
+    }
+    logger.log_alarm(alarm);
+}
+
+/// rdlife/rdcount 값으로 공구 수명 단계를 추적한다.
+/// 단계가 올라갈 때만 알람을 내고, 카운트가 리셋되어 단계가 내려가면 상태를 지운다.
+#[derive(Default)]
+pub struct ToolLifeMonitor {
+    levels: HashMap<(u16, ChannelKey), AlarmLevel>,
+}
+
+impl ToolLifeMonitor {
+    pub fn level(tool: &ToolData, life: i32, count: i32) -> Option<AlarmLevel> {
+        if life <= 0 || count < 0 {
+            return None;
+        }
+        let ratio = count as f64 / life as f64;
+        if ratio >= tool.life_limit {
+            Some(AlarmLevel::Critical)
+        } else if ratio >= tool.life_warning {
+            Some(AlarmLevel::Warning)
+        } else {
+            None
+        }
+    }
+
+    /// 새로 넘은 단계가 있으면 반환
+    pub fn check(&mut self, tool: &ToolData, life: i32, count: i32) -> Option<AlarmLevel> {
+        // 읽기 실패(-1)는 단계 변화로 보지 않음
+        if life <= 0 || count < 0 {
+            return None;
+        }
+        let key = (tool.machine_id, tool.key());
+        let level = Self::level(tool, life, count);
+        let previous = self.levels.get(&key).copied();
+        match level {
+            Some(level) => {
+                self.levels.insert(key, level);
+                if previous.is_none_or(|p| level > p) {
+                    Some(level)
+                } else {
+                    None
+                }
+            }
+            None => {
+                self.levels.remove(&key);
+                None
+            }
+        }
+    }
+
+    /// 새로 넘은 단계가 있으면 알람을 내고, 한계 도달 시 정지 신호가 설정되어 있으면 PMC에 쓴다.
+    pub async fn check_and_raise(
+        &mut self,
+        logger: &HistoryLogger,
+        worker: &FocasWorker,
+        tool: &ToolData,
+        life: i32,
+        count: i32,
+    ) {
+        let Some(level) = self.check(tool, life, count) else {
+            return;
+        };
+        raise_alarm(
+            logger,
+            AlarmLog {
+                timestamp: Utc::now(),
+                machine_id: tool.machine_id,
+                path: tool.path,
+                tool_num: tool.tool_num,
+                kind: AlarmKind::ToolLife,
+                level,
+                message: format!("Tool life {} / {} EA", count, life),
+            },
+        );
+        if level == AlarmLevel::Critical {
+            if let Some(signal) = tool.life_stop_signal.clone() {
+                if let Err(e) = worker.call(move |client| signal.write(client)).await {
+                    eprintln!(
+                        "Failed to write PMC stop signal for machine {}, tool {}: {}",
+                        tool.machine_id,
+                        tool.key(),
+                        e
+                    );
+                }
+            }
+        }
+    }
+}
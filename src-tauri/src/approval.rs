@@ -0,0 +1,202 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::cnc::{ChannelKey, OffsetRequest};
+use crate::OffsetDecision;
+
+/// 작업자 승인을 기다리는 자동 보정
+#[derive(Debug, Clone, Serialize)]
+pub struct PendingCorrection {
+    pub id: u64,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    pub machine_id: u16,
+    pub path: i16,
+    pub tool_num: i16,
+    pub offset_diff: i32,      // 승인 시 쓸 보정량 (µm)
+    pub requested_change: i32, // 한계 적용 전 계산값 (µm)
+    pub avg_gauge: Option<f64>,
+    pub rationale: String,
+}
+
+impl PendingCorrection {
+    pub fn key(&self) -> ChannelKey {
+        ChannelKey::new(self.path, self.tool_num)
+    }
+
+    /// 승인/거부/만료 처리에 쓸 요청. 계산값과 근거를 그대로 넘긴다.
+    pub fn into_request(self, decision: OffsetDecision) -> OffsetRequest {
+        let key = self.key();
+        OffsetRequest {
+            requested_change: self.requested_change,
+            rationale: Some(self.rationale),
+            ..OffsetRequest::new(self.machine_id, key, self.offset_diff, decision)
+        }
+    }
+}
+
+#[derive(Default)]
+struct QueueState {
+    next_id: u64,
+    items: Vec<PendingCorrection>,
+}
+
+/// 승인 대기열. 공구마다 최신 제안 하나만 유지하고, ttl이 지나면 만료된다.
+#[derive(Clone)]
+pub struct ApprovalQueue {
+    state: Arc<Mutex<QueueState>>,
+    ttl: Duration,
+}
+
+impl ApprovalQueue {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(QueueState::default())),
+            ttl,
+        }
+    }
+
+    /// 제안을 추가한다. 같은 공구에 대기 중인 이전 제안이 있으면 대체하고 반환한다.
+    pub fn propose(
+        &self,
+        machine_id: u16,
+        key: ChannelKey,
+        offset_diff: i32,
+        requested_change: i32,
+        avg_gauge: Option<f64>,
+        rationale: String,
+    ) -> (PendingCorrection, Option<PendingCorrection>) {
+        let mut state = self.state.lock().unwrap();
+        let superseded = state
+            .items
+            .iter()
+            .position(|p| p.machine_id == machine_id && p.key() == key)
+            .map(|i| state.items.remove(i));
+        state.next_id += 1;
+        let now = Utc::now();
+        let pending = PendingCorrection {
+            id: state.next_id,
+            created_at: now,
+            expires_at: now + chrono::Duration::seconds(self.ttl.as_secs() as i64),
+            machine_id,
+            path: key.path,
+            tool_num: key.tool_num,
+            offset_diff,
+            requested_change,
+            avg_gauge,
+            rationale,
+        };
+        state.items.push(pending.clone());
+        (pending, superseded)
+    }
+
+    pub fn list(&self) -> Vec<PendingCorrection> {
+        self.state.lock().unwrap().items.clone()
+    }
+
+    /// 승인/거부할 제안을 꺼낸다. 다른 채널의 제안이거나 이미 만료된 제안은 꺼내지 않는다.
+    pub fn take(&self, machine_id: u16, key: ChannelKey, id: u64) -> Option<PendingCorrection> {
+        let mut state = self.state.lock().unwrap();
+        let index = state.items.iter().position(|p| {
+            p.id == id && p.machine_id == machine_id && p.key() == key && p.expires_at > Utc::now()
+        })?;
+        Some(state.items.remove(index))
+    }
+
+    /// 한 기계의 대기 중인 제안을 모두 꺼낸다 (레시피 교체 시).
+    pub fn take_machine(&self, machine_id: u16) -> Vec<PendingCorrection> {
+        let mut state = self.state.lock().unwrap();
+        let (taken, pending) = std::mem::take(&mut state.items)
+            .into_iter()
+            .partition(|p| p.machine_id == machine_id);
+        state.items = pending;
+        taken
+    }
+
+    pub fn take_expired(&self) -> Vec<PendingCorrection> {
+        let now = Utc::now();
+        let mut state = self.state.lock().unwrap();
+        let (expired, pending) = std::mem::take(&mut state.items)
+            .into_iter()
+            .partition(|p| p.expires_at <= now);
+        state.items = pending;
+        expired
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn propose(queue: &ApprovalQueue, machine_id: u16, key: ChannelKey, offset_diff: i32) -> u64 {
+        let (pending, _) = queue.propose(
+            machine_id,
+            key,
+            offset_diff,
+            offset_diff,
+            None,
+            String::new(),
+        );
+        pending.id
+    }
+
+    #[test]
+    fn test_propose_supersedes_same_channel() {
+        let queue = ApprovalQueue::new(Duration::from_secs(60));
+        let key = ChannelKey::new(1, 11);
+        let first = propose(&queue, 0, key, 10);
+        // 다른 계통의 같은 공구 번호는 별개 채널
+        propose(&queue, 0, ChannelKey::new(2, 11), 20);
+        let (pending, superseded) = queue.propose(0, key, 30, 40, None, String::new());
+        assert_eq!(superseded.map(|p| p.id), Some(first));
+        assert_eq!(pending.offset_diff, 30);
+        assert_eq!(pending.requested_change, 40);
+        assert_eq!(queue.list().len(), 2);
+        assert!(queue.take(0, key, first).is_none());
+        // 승인/거부는 제안한 채널로만 꺼낼 수 있다
+        assert!(queue.take(0, ChannelKey::new(2, 11), pending.id).is_none());
+        assert!(queue.take(1, key, pending.id).is_none());
+        assert_eq!(queue.take(0, key, pending.id).map(|p| p.key()), Some(key));
+    }
+
+    #[test]
+    fn test_take_machine() {
+        let queue = ApprovalQueue::new(Duration::from_secs(60));
+        propose(&queue, 0, ChannelKey::new(1, 11), 10);
+        propose(&queue, 0, ChannelKey::new(1, 12), 10);
+        let other = propose(&queue, 1, ChannelKey::new(1, 11), 10);
+        let taken = queue.take_machine(0);
+        assert_eq!(taken.len(), 2);
+        assert!(taken.iter().all(|p| p.machine_id == 0));
+        assert_eq!(
+            queue.list().iter().map(|p| p.id).collect::<Vec<_>>(),
+            vec![other]
+        );
+        assert!(queue.take_machine(0).is_empty());
+    }
+
+    #[test]
+    fn test_take_expired() {
+        let expired = ApprovalQueue::new(Duration::ZERO);
+        let id = propose(&expired, 0, ChannelKey::new(1, 11), 10);
+        // 만료된 제안은 승인/거부로 꺼낼 수 없고 만료 처리로만 나온다
+        assert!(expired.take(0, ChannelKey::new(1, 11), id).is_none());
+        assert_eq!(
+            expired
+                .take_expired()
+                .iter()
+                .map(|p| p.id)
+                .collect::<Vec<_>>(),
+            vec![id]
+        );
+        assert!(expired.list().is_empty());
+
+        let live = ApprovalQueue::new(Duration::from_secs(60));
+        propose(&live, 0, ChannelKey::new(1, 11), 10);
+        assert!(live.take_expired().is_empty());
+        assert_eq!(live.list().len(), 1);
+    }
+}
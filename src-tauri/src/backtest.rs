@@ -0,0 +1,306 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::cnc::ToolData;
+use crate::logger::GaugePoint;
+use crate::outlier::OutlierRule;
+use crate::stats;
+use crate::strategy::{StrategyConfig, StrategyState};
+use crate::wear::WearModel;
+use crate::{OffsetDecision, OffsetLog};
+
+/// 백테스트할 후보 설정. 비운 항목은 현재 공구 설정을 그대로 쓴다.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct BacktestCandidate {
+    pub offset_rate: Option<f64>,
+    pub batch_size: Option<usize>,
+    pub outlier_rule: Option<OutlierRule>,
+    pub strategy: Option<StrategyConfig>,
+    pub deadband: Option<f64>,
+    pub deadband_sigma: Option<f64>,
+    pub parts_in_transit: Option<u32>, // 후보 보정이 측정에 나타나기까지의 부품 수
+}
+
+/// 후보 설정이 냈을 보정
+#[derive(Debug, Serialize)]
+pub struct BacktestCorrection {
+    pub timestamp: String, // 배치의 마지막 측정 시각
+    pub reading: usize,    // 배치의 마지막 측정 순번
+    pub avg_gauge: Option<f64>,
+    pub requested_change: i32, // µm
+    pub change_amount: i32,    // µm (쓰지 않았으면 0)
+    pub decision: OffsetDecision,
+    pub rationale: String,
+}
+
+/// 기준 치수(basic_size)와의 편차 통계 (mm)
+#[derive(Debug, Serialize)]
+pub struct DeviationStats {
+    pub mean: Option<f64>,
+    pub std_dev: Option<f64>,
+    pub max_abs: Option<f64>,
+    pub out_of_tolerance: Option<usize>, // spc.tolerance가 있을 때만
+}
+
+#[derive(Debug, Serialize)]
+pub struct BacktestPoint {
+    pub timestamp: String,
+    pub actual: f64,    // 실제 측정 (mm)
+    pub predicted: f64, // 후보 설정이었다면의 추정 치수 (mm)
+}
+
+#[derive(Debug, Serialize)]
+pub struct BacktestReport {
+    pub readings: usize,
+    pub excluded: usize,           // 쿨다운으로 배치에서 뺀 측정 수
+    pub actual_corrections: usize, // 실제 옵셋 변경 수 (외부 변경, 되돌리기 포함)
+    pub corrections: Vec<BacktestCorrection>,
+    pub actual: DeviationStats,
+    pub predicted: DeviationStats,
+    pub points: Vec<BacktestPoint>,
+}
+
+// 게이지 원시값 단위 (1/10000mm)
+const GAUGE_SCALE: f64 = 10000.0;
+
+/// 측정값에서 실제로 쓴 보정의 영향을 빼 보정이 없었을 때의 치수를 추정하고,
+/// 후보 설정으로 같은 보정 로직(이송 중 보정, 이상치, 전략, 불감대, 1회 한계)을
+/// 다시 돌려 그 보정이 쓰였을 때의 치수를 예측한다.
+///
+/// 보정은 쓴 시점 이후 이송 중 부품 수만큼 뒤의 측정부터 나타난다고 본다. 실제 기록에는
+/// 기계에 설정된 machine_transit을, 후보 보정에는 후보 값(비우면 같은 값)을 쓴다.
+/// 승인 대기는 승인된 것으로 보고, 워밍업, 시간 쿨다운, 누적 한계, 예측 보정은
+/// 반영하지 않는다.
+pub fn run(
+    tool: &ToolData,
+    batch_size: usize,
+    machine_transit: usize,
+    candidate: &BacktestCandidate,
+    points: &[GaugePoint],
+    offsets: &[OffsetLog],
+) -> BacktestReport {
+    let mut tool = tool.clone();
+    if let Some(rate) = candidate.offset_rate {
+        tool.offset_rate = rate;
+    }
+    if let Some(rule) = &candidate.outlier_rule {
+        tool.outlier_rule = rule.clone();
+    }
+    if let Some(strategy) = &candidate.strategy {
+        tool.strategy = strategy.clone();
+    }
+    if let Some(deadband) = candidate.deadband {
+        tool.deadband = deadband;
+    }
+    if let Some(sigma) = candidate.deadband_sigma {
+        tool.deadband_sigma = sigma;
+    }
+    tool.manual_offset = 0.0;
+    tool.drift = 0.0;
+    tool.cooldown_parts_remaining = 0;
+    tool.strategy_state = StrategyState::default();
+    tool.wear = WearModel::default();
+    tool.in_transit.clear();
+    let batch_size = candidate.batch_size.unwrap_or(batch_size).max(1);
+    let transit = candidate
+        .parts_in_transit
+        .map_or(machine_transit, |parts| parts as usize);
+
+    // 실제 보정이 처음 나타나는 측정 순번: 쓴 시각까지 측정된 수 + 이송 중 부품 수
+    let times: Vec<Option<DateTime<Utc>>> = points.iter().map(GaugePoint::time).collect();
+    let actual_applied: Vec<(usize, i32)> = offsets
+        .iter()
+        .map(|log| {
+            let measured = times
+                .iter()
+                .take_while(|t| t.is_some_and(|t| t <= log.timestamp))
+                .count();
+            (measured + machine_transit, log.change_amount)
+        })
+        .collect();
+
+    let mut simulated_applied: Vec<(usize, i32)> = Vec::new();
+    let mut batch = Vec::with_capacity(batch_size);
+    let mut corrections = Vec::new();
+    let mut excluded = 0;
+    let mut predicted = Vec::with_capacity(points.len());
+    for (i, point) in points.iter().enumerate() {
+        let visible = |applied: &[(usize, i32)]| -> i32 {
+            applied
+                .iter()
+                .filter(|&&(from, _)| from <= i)
+                .map(|&(_, change)| change * 10)
+                .sum()
+        };
+        let raw = (point.value * GAUGE_SCALE).round() as i32;
+        let value = raw - visible(&actual_applied) + visible(&simulated_applied);
+        predicted.push(value as f64 / GAUGE_SCALE);
+
+        if tool.cooldown_parts_remaining > 0 {
+            tool.cooldown_parts_remaining -= 1;
+            excluded += 1;
+            continue;
+        }
+        // 측정 순번을 부품 번호로 써서 이송 중 보정을 추적한다.
+        batch.push((i as i32, value, Some(i as i64)));
+        if batch.len() < batch_size {
+            continue;
+        }
+        let (correction, _) = tool.evaluate_batch(&std::mem::take(&mut batch));
+        let Some(correction) = correction else {
+            continue;
+        };
+        let (offset_diff, decision) = tool.decide_step(correction.offset_diff, correction.deadband);
+        let written = matches!(
+            decision,
+            OffsetDecision::Applied | OffsetDecision::Clamped | OffsetDecision::AwaitingApproval
+        ) && offset_diff != 0;
+        if written {
+            tool.on_offset_written(offset_diff, decision, Some((i + transit) as i64));
+            simulated_applied.push((i + 1 + transit, offset_diff));
+        }
+        corrections.push(BacktestCorrection {
+            timestamp: point.timestamp.clone(),
+            reading: i,
+            avg_gauge: tool.avg_gauge,
+            requested_change: correction.offset_diff,
+            change_amount: if written { offset_diff } else { 0 },
+            decision,
+            rationale: correction.rationale,
+        });
+    }
+
+    let actual: Vec<f64> = points.iter().map(|p| p.value).collect();
+    BacktestReport {
+        readings: points.len(),
+        excluded,
+        actual_corrections: offsets.len(),
+        corrections,
+        actual: deviation_stats(&tool, &actual),
+        predicted: deviation_stats(&tool, &predicted),
+        points: points
+            .iter()
+            .zip(&predicted)
+            .map(|(p, &predicted)| BacktestPoint {
+                timestamp: p.timestamp.clone(),
+                actual: p.value,
+                predicted,
+            })
+            .collect(),
+    }
+}
+
+fn deviation_stats(tool: &ToolData, values: &[f64]) -> DeviationStats {
+    let deviations: Vec<f64> = values.iter().map(|v| v - tool.basic_size).collect();
+    DeviationStats {
+        mean: stats::mean(&deviations),
+        std_dev: stats::std_dev(&deviations),
+        max_abs: deviations.iter().map(|d| d.abs()).reduce(f64::max),
+        out_of_tolerance: tool.spc.tolerance.as_ref().map(|t| {
+            values
+                .iter()
+                .filter(|&&v| v < t.lower || v > t.upper)
+                .count()
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::AppConfig;
+
+    fn base() -> DateTime<Utc> {
+        use chrono::TimeZone;
+        Utc.with_ymd_and_hms(2026, 1, 1, 0, 0, 0).unwrap()
+    }
+
+    // i번째 측정 (기준 치수 48.0mm와의 편차 mm)
+    fn point(i: usize, deviation: f64) -> GaugePoint {
+        GaugePoint {
+            id: i as i64,
+            timestamp: (base() + chrono::Duration::seconds(i as i64))
+                .format("%Y-%m-%d %H:%M:%S")
+                .to_string(),
+            value: 48.0 + deviation,
+        }
+    }
+
+    // i번째 측정 직후의 옵셋 변경 (µm)
+    fn change(i: usize, amount: i32, decision: OffsetDecision) -> OffsetLog {
+        OffsetLog {
+            id: None,
+            timestamp: base() + chrono::Duration::seconds(i as i64),
+            machine_id: 0,
+            path: 1,
+            tool_num: 11,
+            old_value: 0,
+            change_amount: amount,
+            new_value: amount,
+            success: true,
+            verified: true,
+            decision: Some(decision),
+            requested_change: None,
+            undo_of: None,
+            rationale: None,
+            recipe: None,
+        }
+    }
+
+    fn tool() -> ToolData {
+        AppConfig::default().mapping.tool_data[&0][0].clone()
+    }
+
+    #[test]
+    fn test_candidate_corrections() {
+        // 보정 없이 +0.004mm로 가공된 이력
+        let points: Vec<GaugePoint> = (0..10).map(|i| point(i, 0.004)).collect();
+        let report = run(&tool(), 5, 0, &BacktestCandidate::default(), &points, &[]);
+        assert_eq!(report.readings, 10);
+        assert_eq!(report.actual_corrections, 0);
+        // 첫 배치에서 -0.004mm를 쓰고, 그 뒤 배치는 기준 치수라 쓰지 않는다
+        assert_eq!(report.corrections[0].reading, 4);
+        assert_eq!(report.corrections[0].change_amount, -4);
+        assert!(report.corrections[1..].iter().all(|c| c.change_amount == 0));
+        for p in &report.points[5..] {
+            assert!((p.predicted - 48.0).abs() < 1e-9);
+        }
+        assert!((report.actual.mean.unwrap() - 0.004).abs() < 1e-9);
+        assert!((report.predicted.mean.unwrap() - 0.002).abs() < 1e-9);
+        assert!((report.predicted.max_abs.unwrap() - 0.004).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_actual_history_with_machine_transit() {
+        // 4번 측정 직후 쓴 -0.004mm 보정이 이송 중 부품 2개 뒤(7번)부터 나타나고,
+        // 5번 직후 조작반에서 바꾼 +0.002mm는 8번부터 나타난다.
+        // 6번 직후에는 보정을 썼다가 되돌렸다
+        let actual = [
+            0.004, 0.004, 0.004, 0.004, 0.004, 0.004, 0.004, 0.0, 0.002, 0.002,
+        ];
+        let points: Vec<GaugePoint> = actual
+            .iter()
+            .enumerate()
+            .map(|(i, &d)| point(i, d))
+            .collect();
+        let offsets = [
+            change(4, -4, OffsetDecision::Applied),
+            change(5, 2, OffsetDecision::External),
+            change(6, -3, OffsetDecision::Clamped),
+            change(6, 3, OffsetDecision::Undo),
+        ];
+        // 후보는 보정하지 않도록 불감대를 넓게 잡는다
+        let candidate = BacktestCandidate {
+            deadband: Some(0.01),
+            ..Default::default()
+        };
+        let report = run(&tool(), 5, 2, &candidate, &points, &offsets);
+        assert_eq!(report.actual_corrections, 4);
+        assert!(report.corrections.iter().all(|c| c.change_amount == 0));
+        // 실제 보정을 모두 빼면 보정 없는 이력은 +0.004mm로 일정하다
+        for p in &report.points {
+            assert!((p.predicted - 48.004).abs() < 1e-9, "{:?}", p);
+        }
+        assert!(report.predicted.std_dev.unwrap() < 1e-9);
+    }
+}
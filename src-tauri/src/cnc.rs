@@ -2,22 +2,37 @@ use std::sync::Arc;
 use std::{collections::HashMap, sync::Mutex};
 
 use anyhow::anyhow;
-use focas_rs::FocasClient;
+use chrono::{DateTime, Utc};
 use futures::future::join_all;
 use serde::{Deserialize, Serialize};
 
+use crate::alarm::{raise_alarm, AlarmKind, AlarmLevel, AlarmLog, PmcSignal};
+use crate::approval::ApprovalQueue;
+use crate::config::MachineConfig;
 use crate::logger::HistoryLogger;
-use crate::OffsetLog;
+use crate::outlier::OutlierRule;
+use crate::spc::SpcConfig;
+use crate::stats;
+use crate::strategy::{StrategyConfig, StrategyState};
+use crate::tuning::RateSuggestion;
+use crate::wear::WearModel;
+use crate::worker::{CncBackend, FocasWorker};
+use crate::{OffsetDecision, OffsetLog};
 
 pub struct GaugeBatches {
     logger: HistoryLogger,
-    tool_data: Arc<Mutex<HashMap<u16, (ToolData, ToolData)>>>, // machine_id -> (ToolDataUpper , ToolDataLower)
-    handle_table: Arc<HashMap<u16, FocasClient>>,
+    tool_data: Arc<Mutex<HashMap<u16, Vec<ToolData>>>>, // machine_id -> 보정 채널 목록
+    handle_table: Arc<HashMap<u16, FocasWorker>>,
     batch_size: Arc<Mutex<HashMap<u16, usize>>>, // machine_id -> batch_size
+    tool_counts: HashMap<(u16, ChannelKey), i64>, // 예측 보정을 마지막으로 확인한 공구 카운트
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ToolData {
+    #[serde(default)]
+    pub name: String, // 채널 표시 이름 (예: 황삭, 정삭)
+    #[serde(default)]
+    pub feature: i32, // 이 채널이 따르는 게이지 측정값 번호 (gauge_raw_logs.tool_type, 1: Value1, 2: Value2)
     pub machine_id: u16,
     pub tool_num: i16,
     pub basic_size: f64,
@@ -28,21 +43,582 @@ pub struct ToolData {
     pub final_offset: Option<f64>,
     pub max_limit: f64,
     pub min_limit: f64,
+    #[serde(default)]
+    pub target: OffsetTarget,
+    #[serde(default = "default_path")]
+    pub path: i16, // CNC 계통 (2계통 선반: 1 또는 2)
+    #[serde(default)]
+    pub verify_policy: VerifyPolicy,
+    #[serde(default = "default_life_warning")]
+    pub life_warning: f64, // 수명 경고 비율 (count / life)
+    #[serde(default = "default_life_limit")]
+    pub life_limit: f64, // 수명 한계 비율
+    #[serde(default)]
+    pub life_stop_signal: Option<PmcSignal>, // 수명 한계 도달 시 쓸 PMC 신호
+    #[serde(default = "default_tool_change_offset")]
+    pub tool_change_offset: f64, // 외부에서 이 크기(mm) 이상 옵셋이 바뀌면 공구 교체로 판단 (0: 사용 안 함)
+    #[serde(default)]
+    pub warmup_parts: u32, // 공구 교체 후 배치에서 제외할 측정 수
+    #[serde(default)]
+    pub warmup_remaining: u32,
+    #[serde(default)]
+    pub cooldown_parts: u32, // 보정 후 다음 배치에서 제외할 측정 수 (이전 옵셋으로 가공된 부품)
+    #[serde(default)]
+    pub cooldown_secs: u64, // 보정 후 이 시간 동안 들어온 측정은 제외
+    #[serde(default)]
+    pub cooldown_parts_remaining: u32,
+    #[serde(default)]
+    pub cooldown_until: Option<DateTime<Utc>>,
+    #[serde(default = "default_max_drift")]
+    pub max_drift: f64, // 공구 교체/리셋 이후 누적 보정량 한계 (mm, 0: 사용 안 함)
+    #[serde(default)]
+    pub drift: f64, // 공구 교체/리셋 이후 누적 보정량 (mm)
+    #[serde(default)]
+    pub drift_locked: bool, // 누적 한계 도달로 자동 보정 중지됨
+    #[serde(default)]
+    pub step_policy: StepPolicy, // 자동 보정량이 max_limit/min_limit를 벗어날 때
+    #[serde(default)]
+    pub approval_threshold: f64, // 이 크기(mm)를 넘는 자동 보정은 승인 대기열로 (0: 사용 안 함)
+    #[serde(default)]
+    pub shadow: bool, // 모의 운전: 계산한 보정을 CNC에 쓰지 않고 shadow_history에만 기록
+    #[serde(default)]
+    pub deadband: f64, // 이보다 작은 자동 보정(mm)은 쓰지 않음 (0: 사용 안 함)
+    #[serde(default)]
+    pub deadband_sigma: f64, // 배치 평균의 표준오차(σ/√n)에 곱해 불감대로 씀 (0: 사용 안 함)
+    #[serde(default)]
+    pub outlier_rule: OutlierRule, // 배치에서 이상치를 제외하는 규칙
+    #[serde(default)]
+    pub strategy: StrategyConfig, // 배치로 보정량을 계산하는 방식
+    #[serde(default)]
+    pub spc: SpcConfig, // 관리도/공정능력 설정
+    #[serde(default)]
+    pub predictive: bool, // 마모율 회귀로 배치 사이에 예측 보정
+    #[serde(default = "default_wear_window")]
+    pub wear_window: usize, // 마모율 회귀에 쓸 최근 배치 수
+    #[serde(default = "default_feedforward_step")]
+    pub feedforward_step: f64, // 예측 보정을 쓰는 최소 크기 (mm)
+    #[serde(skip)]
+    pub wear: WearModel,
+    #[serde(skip)]
+    pub tool_count: Option<i64>, // 마지막으로 읽은 공구 카운트 (rdcount, 공구 교체 시 다시 시작)
+    #[serde(skip)]
+    pub strategy_state: StrategyState,
+    #[serde(skip)]
+    pub in_transit: Vec<InTransit>,
+    #[serde(skip)]
+    pub skip_through: Option<i32>, // 다른 채널과 공유하는 측정 항목에서 이 채널만 제외할 마지막 측정 id
+    #[serde(default)]
+    pub auto_tune: bool, // 보정 응답으로 보정률을 추정해 제안 (적용은 관리자 승인)
+    #[serde(skip)]
+    pub rate_suggestion: Option<RateSuggestion>,
+}
+
+/// 배치의 측정값 하나: (gauge_raw_logs id, 측정값 1/10000mm, 부품 번호)
+pub type BatchEntry = (i32, i32, Option<i64>);
+
+/// 기계 안에서 보정 채널을 구분하는 키. 2계통 선반은 계통마다 같은 공구 번호를 쓸 수 있으므로
+/// 공구 번호만으로 찾지 않는다. 한 기계 안에서 중복되면 설정 오류다.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ChannelKey {
+    pub path: i16,
+    pub tool_num: i16,
+}
+
+impl ChannelKey {
+    pub fn new(path: i16, tool_num: i16) -> Self {
+        Self { path, tool_num }
+    }
+}
+
+impl std::fmt::Display for ChannelKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "P{} T{}", self.path, self.tool_num)
+    }
+}
+
+/// 옵셋에 썼지만 아직 측정에 나타나지 않은 보정.
+/// after_part 이하 번호의 부품은 이 보정 전에 가공된 것이다.
+#[derive(Debug, Clone, Copy)]
+pub struct InTransit {
+    pub after_part: i64,
+    pub offset: i32, // µm
+}
+
+fn default_tool_change_offset() -> f64 {
+    0.1
+}
+
+fn default_max_drift() -> f64 {
+    0.1
+}
+
+fn default_wear_window() -> usize {
+    10
+}
+
+fn default_feedforward_step() -> f64 {
+    0.002
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ToolChangeReason {
+    CountReset, // rdcount 값이 줄어듦
+    OffsetJump, // 외부에서 큰 옵셋 변경
+    Operator,   // 작업자가 직접 교체 처리
+}
+
+impl ToolChangeReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ToolChangeReason::CountReset => "count_reset",
+            ToolChangeReason::OffsetJump => "offset_jump",
+            ToolChangeReason::Operator => "operator",
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ToolChangeLog {
+    pub timestamp: DateTime<Utc>,
+    pub machine_id: u16,
+    pub path: i16,
+    pub tool_num: i16,
+    pub reason: String,
+    pub discarded: usize, // 버린 대기중 측정 수
+}
+
+/// 모의 운전에서 쓰지 않고 기록만 한 보정
+#[derive(Debug, Serialize)]
+pub struct ShadowLog {
+    pub timestamp: DateTime<Utc>,
+    pub machine_id: u16,
+    pub path: i16,
+    pub tool_num: i16,
+    pub avg_gauge: Option<f64>,
+    pub current_value: i32,    // 당시 CNC 옵셋 (µm)
+    pub change_amount: i32,    // 썼을 보정량 (µm)
+    pub requested_change: i32, // 한계 적용 전 계산값 (µm)
+    pub decision: OffsetDecision,
+    pub rationale: Option<String>,
+}
+
+/// rdcount 값이 이전보다 줄어들면 공구 교체(카운트 리셋)로 본다.
+#[derive(Default)]
+pub struct ToolCountTracker {
+    last_counts: HashMap<(u16, ChannelKey), i32>,
+}
+
+impl ToolCountTracker {
+    pub fn is_reset(&mut self, tool: &ToolData, count: i32) -> bool {
+        if count < 0 {
+            return false;
+        }
+        let key = (tool.machine_id, tool.key());
+        let last = self.last_counts.insert(key, count);
+        last.is_some_and(|last| count < last)
+    }
+}
+
+/// 공구 교체 처리: 해당 공구의 대기중 측정값을 버리고, 평균을 초기화하고,
+/// 워밍업 수만큼 다음 측정값을 제외하도록 설정한 뒤 이벤트를 기록한다.
+/// 다른 채널과 공유하는 측정 항목이면 측정값은 그대로 두고 이 채널에서만 제외한다.
+pub fn handle_tool_change(
+    logger: &HistoryLogger,
+    tool_data: &Mutex<HashMap<u16, Vec<ToolData>>>,
+    machine_id: u16,
+    key: ChannelKey,
+    reason: ToolChangeReason,
+) -> anyhow::Result<()> {
+    let discarded = {
+        let mut tool_data_map = tool_data.lock().unwrap();
+        let tools = tool_data_map
+            .get_mut(&machine_id)
+            .ok_or_else(|| anyhow!("Tool {} not found for machine {}", key, machine_id))?;
+        let shared = tools
+            .iter()
+            .find(|tool| tool.key() == key)
+            .is_some_and(|tool| shares_feature(tools, tool.feature));
+        let tool = tools
+            .iter_mut()
+            .find(|tool| tool.key() == key)
+            .ok_or_else(|| anyhow!("Tool {} not found for machine {}", key, machine_id))?;
+        tool.reset_state();
+        if shared {
+            let pending = logger.last_pending(machine_id, tool.feature);
+            tool.skip_through = pending.map(|(last, _)| last);
+            pending.map_or(0, |(_, count)| count)
+        } else {
+            logger.discard_pending(machine_id, tool.feature)
+        }
+    };
+    println!(
+        "Tool change detected for machine {}, tool {} ({}): discarded {} pending measurements",
+        machine_id,
+        key,
+        reason.as_str(),
+        discarded
+    );
+    logger.log_tool_change(ToolChangeLog {
+        timestamp: Utc::now(),
+        machine_id,
+        path: key.path,
+        tool_num: key.tool_num,
+        reason: reason.as_str().to_string(),
+        discarded,
+    });
+    Ok(())
+}
+
+fn default_life_warning() -> f64 {
+    0.9
+}
+
+fn default_life_limit() -> f64 {
+    1.0
+}
+
+fn default_path() -> i16 {
+    1
+}
+
+/// 쓰기 후 읽어온 값이 기대값과 다를 때의 처리 방식
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum VerifyPolicy {
+    /// 현재 값을 기준으로 변화량을 다시 적용 (최대 attempts회)
+    Retry { attempts: u32 },
+    /// 쓰기 전 값으로 되돌림
+    Restore,
+}
+
+impl Default for VerifyPolicy {
+    fn default() -> Self {
+        VerifyPolicy::Retry { attempts: 2 }
+    }
+}
+
+/// 자동 보정 1회분이 max_limit/min_limit를 벗어날 때의 처리 방식
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StepPolicy {
+    /// 한계값으로 잘라서 씀
+    #[default]
+    Clamp,
+    /// 쓰지 않고 알람
+    SkipAndAlarm,
+    /// 쓰지 않고 작업자 승인을 기다림
+    RequireApproval,
+}
+
+/// 보정값을 쓰는 대상. 기본은 공구 마모 옵셋이고, 파트 프로그램이
+/// 매크로 변수(#500~#599 등)로 보정하는 경우 해당 변수 번호를 지정한다.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum OffsetTarget {
+    #[default]
+    ToolOffset,
+    MacroVariable {
+        number: i16,
+    },
+}
+
+// 매크로 변수는 µm 단위 정수(소수점 3자리)로 기록
+const MACRO_DEC_VAL: i16 = 3;
+
+pub(crate) fn macro_to_micron(mcr_val: i32, dec_val: i16) -> i32 {
+    let shift = MACRO_DEC_VAL - dec_val;
+    if shift >= 0 {
+        mcr_val * 10i32.pow(shift as u32)
+    } else {
+        (mcr_val as f64 / 10f64.powi(-shift as i32)).round() as i32
+    }
 }
 
 impl ToolData {
-    fn get_final_offset(&self) -> Option<f64> {
-        if let Some(avg_gauge) = self.avg_gauge {
-            let offset_diff = (self.basic_size - avg_gauge + self.manual_offset) * self.offset_rate;
-            Some(offset_diff)
+    pub fn key(&self) -> ChannelKey {
+        ChannelKey::new(self.path, self.tool_num)
+    }
+
+    /// 이 공구가 속한 계통을 선택한다. 모든 FOCAS 읽기/쓰기 전에 호출해야 하며,
+    /// 다른 호출과 섞이지 않도록 계통 선택과 읽기/쓰기를 같은 워커 작업 안에서 실행해야 한다.
+    fn select_path(&self, client: &dyn CncBackend) -> anyhow::Result<()> {
+        client.set_path(self.path)
+    }
+
+    /// 현재 보정 대상 값을 µm 단위로 읽는다.
+    pub fn read_offset(&self, client: &dyn CncBackend) -> anyhow::Result<i32> {
+        self.select_path(client)?;
+        match self.target {
+            OffsetTarget::ToolOffset => client.read_tool_offset(self.tool_num),
+            OffsetTarget::MacroVariable { number } => {
+                let (mcr_val, dec_val) = client.read_macro(number)?;
+                Ok(macro_to_micron(mcr_val, dec_val))
+            }
+        }
+    }
+
+    /// 보정 대상에 µm 단위 값을 쓴다.
+    pub fn write_offset(&self, client: &dyn CncBackend, value: i32) -> anyhow::Result<()> {
+        self.select_path(client)?;
+        match self.target {
+            OffsetTarget::ToolOffset => client.write_tool_offset(self.tool_num, value)?,
+            OffsetTarget::MacroVariable { number } => {
+                client.write_macro(number, value, MACRO_DEC_VAL)?
+            }
+        }
+        Ok(())
+    }
+
+    pub fn read_life(&self, client: &dyn CncBackend) -> anyhow::Result<i32> {
+        self.select_path(client)?;
+        client.read_tool_life(self.tool_num)
+    }
+
+    pub fn read_count(&self, client: &dyn CncBackend) -> anyhow::Result<i32> {
+        self.select_path(client)?;
+        client.read_tool_count(self.tool_num)
+    }
+
+    /// 옵셋 쓰기 성공 후 호출. 수동 보정값을 비우고 누적량과 쿨다운을 갱신한다.
+    /// 예측 보정은 작은 보정을 자주 쓰므로 쿨다운을 시작하지 않는다.
+    /// part_count는 쓴 시점의 가공 수량 (모르면 이송 중 보정을 추적하지 않음)
+    pub(crate) fn on_offset_written(
+        &mut self,
+        offset_diff: i32,
+        decision: OffsetDecision,
+        part_count: Option<i64>,
+    ) {
+        self.manual_offset = 0.0;
+        if let Some(after_part) = part_count {
+            self.in_transit.push(InTransit {
+                after_part,
+                offset: offset_diff,
+            });
+        }
+        self.drift += offset_diff as f64 / 1000.0;
+        self.wear.on_offset_written(offset_diff as f64 / 1000.0);
+        if decision != OffsetDecision::Predictive {
+            self.start_cooldown();
+        }
+    }
+
+    /// 보정량(µm)을 max_limit/min_limit 범위로 자른 값. 범위 안이면 None
+    fn limit_step(&self, offset_diff: i32) -> Option<i32> {
+        let max = (self.max_limit * 1000.0).round() as i32;
+        let min = (self.min_limit * 1000.0).round() as i32;
+        let limited = offset_diff.min(max).max(min);
+        (limited != offset_diff).then_some(limited)
+    }
+
+    /// 이 보정량(µm)이 승인 기준을 넘는지
+    fn needs_approval(&self, offset_diff: i32) -> bool {
+        self.approval_threshold > 0.0
+            && (offset_diff.abs() as f64 / 1000.0) > self.approval_threshold
+    }
+
+    /// 이번 보정을 적용하면 누적 한계를 넘는지
+    fn exceeds_drift(&self, offset_diff: i32) -> bool {
+        self.max_drift > 0.0 && (self.drift + offset_diff as f64 / 1000.0).abs() > self.max_drift
+    }
+
+    /// 옵셋을 쓴 직후 호출. 설정된 부품 수/시간 동안 새 측정을 배치에서 제외한다.
+    fn start_cooldown(&mut self) {
+        self.cooldown_parts_remaining = self.cooldown_parts;
+        self.cooldown_until = (self.cooldown_secs > 0)
+            .then(|| Utc::now() + chrono::Duration::seconds(self.cooldown_secs as i64));
+    }
+
+    /// 공구 교체/부품 교체 직후 상태로: 평균, 쿨다운, 누적 보정량, 전략/마모 상태를 비우고
+    /// 워밍업을 다시 시작한다.
+    pub fn reset_state(&mut self) {
+        self.avg_gauge = None;
+        self.final_offset = None;
+        self.warmup_remaining = self.warmup_parts;
+        self.cooldown_parts_remaining = 0;
+        self.cooldown_until = None;
+        self.drift = 0.0;
+        self.drift_locked = false;
+        self.strategy_state = StrategyState::default();
+        self.wear = WearModel::default();
+        self.in_transit.clear();
+        self.skip_through = None;
+    }
+
+    /// 다른 채널과 공유하는 측정 항목의 배치에서 이 채널만 제외할 측정값을 뺀다.
+    /// 공유 항목은 DB에서 건너뛰면 다른 채널의 배치까지 사라지므로 채널 안에서 거른다.
+    /// 반환: (남은 측정값, 제외된 측정값 (id, 사유))
+    pub(crate) fn exclude_from_shared(
+        &mut self,
+        batch: &[BatchEntry],
+    ) -> (Vec<BatchEntry>, Vec<(i32, String)>) {
+        let mut kept = Vec::with_capacity(batch.len());
+        let mut excluded = Vec::new();
+        for &entry in batch {
+            let (id, _, _) = entry;
+            let reason = if self.skip_through.is_some_and(|last| id <= last) {
+                Some("measured before tool change or during cooldown")
+            } else if self.warmup_remaining > 0 {
+                self.warmup_remaining -= 1;
+                Some("warmup")
+            } else if self.cooldown_parts_remaining > 0 {
+                self.cooldown_parts_remaining -= 1;
+                Some("cooldown")
+            } else {
+                None
+            };
+            match reason {
+                Some(reason) => excluded.push((id, format!("{}: {}", self.name, reason))),
+                None => kept.push(entry),
+            }
+        }
+        (kept, excluded)
+    }
+
+    pub fn in_cooldown(&self) -> bool {
+        self.cooldown_parts_remaining > 0 || self.cooldown_until.is_some_and(|t| t > Utc::now())
+    }
+
+    /// 보정 전에 가공된 부품의 측정값에 아직 나타나지 않은 보정량을 더해
+    /// 현재 옵셋 기준 치수로 맞춘다. 반환: (보정한 값, 보정한 측정 수)
+    /// 배치의 마지막 부품보다 앞선 보정은 이제 측정에 나타나므로 목록에서 뺀다.
+    fn compensate_transit(&mut self, batch: &[BatchEntry]) -> (Vec<i32>, usize) {
+        let mut adjusted = 0;
+        let values = batch
+            .iter()
+            .map(|&(_, value, part)| {
+                let Some(part) = part else {
+                    return value;
+                };
+                // 게이지 원시값은 1/10000mm, 보정량은 µm
+                let pending: i32 = self
+                    .in_transit
+                    .iter()
+                    .filter(|t| part <= t.after_part)
+                    .map(|t| t.offset * 10)
+                    .sum();
+                if pending != 0 {
+                    adjusted += 1;
+                }
+                value + pending
+            })
+            .collect();
+        if let Some(last) = batch.iter().filter_map(|&(_, _, part)| part).max() {
+            self.in_transit.retain(|t| t.after_part >= last);
+        }
+        (values, adjusted)
+    }
+
+    /// 배치 하나를 이 채널의 이송 중 보정, 이상치 규칙, 보정 전략으로 평가한다.
+    /// 반환: (계산된 보정, 이상치로 제외된 측정값 (id, 사유))
+    pub(crate) fn evaluate_batch(
+        &mut self,
+        batch: &[BatchEntry],
+    ) -> (Option<AutoCorrection>, Vec<(i32, String)>) {
+        // 이송 중 보정은 채널(공구 옵셋)마다 다르므로 채널별로 반영한다.
+        let (values, in_transit) = self.compensate_transit(batch);
+        let reasons = self.outlier_rule.reject(&values);
+        let mut rejected = Vec::new();
+        let mut kept = Vec::with_capacity(values.len());
+        for ((&(id, _, _), &value), reason) in batch.iter().zip(&values).zip(reasons) {
+            match reason {
+                Some(reason) => rejected.push((id, format!("{}: {}", self.name, reason))),
+                None => kept.push(value),
+            }
+        }
+        let mut state = std::mem::take(&mut self.strategy_state);
+        let correction = self.strategy.strategy().correct(self, &kept, &mut state);
+        self.strategy_state = state;
+        let Some(correction) = correction else {
+            return (None, rejected);
+        };
+        self.avg_gauge = Some(correction.avg_gauge);
+        self.final_offset = Some(correction.offset);
+        if self.predictive {
+            self.wear.on_batch(correction.avg_gauge);
+        }
+        let deadband = self.deadband_for(&kept);
+        let correction = AutoCorrection {
+            machine_id: self.machine_id,
+            key: self.key(),
+            offset_diff: (correction.offset * 1000.0).round() as i32,
+            deadband,
+            predictive: false,
+            rationale: format!(
+                "{}{} rejected {}/{}; {}; deadband {:.4}",
+                if in_transit > 0 {
+                    format!("in-transit corrections added to {} values; ", in_transit)
+                } else {
+                    String::new()
+                },
+                self.outlier_rule.as_str(),
+                values.len() - kept.len(),
+                values.len(),
+                correction.rationale,
+                deadband
+            ),
+        };
+        (Some(correction), rejected)
+    }
+
+    /// 공구 카운트가 바뀔 때 호출. 마모율로 예측한 보정이 feedforward_step 이상이면
+    /// 게이지 보정과 같은 경로(한계, 승인, 모의 운전)로 처리할 자동 보정으로 반환한다.
+    fn feedforward_correction(&mut self, count: i64) -> Option<AutoCorrection> {
+        if !self.predictive {
+            return None;
+        }
+        let ff = self
+            .wear
+            .feedforward(count, self.wear_window, self.feedforward_step)?;
+        // 자동 보정을 쓰지 않는 상태에서도 기준 수량은 옮겨서 쌓인 보정을 한꺼번에 쓰지 않게 한다.
+        if !self.active {
+            return None;
+        }
+        Some(AutoCorrection {
+            machine_id: self.machine_id,
+            key: self.key(),
+            offset_diff: (ff.correction * 1000.0).round() as i32,
+            deadband: 0.0, // 크기는 feedforward_step으로 이미 걸렀다
+            predictive: true,
+            rationale: format!(
+                "wear rate {:.3}um/part x {} parts",
+                ff.rate * 1000.0,
+                ff.parts
+            ),
+        })
+    }
+
+    /// 계산된 보정(µm)에 불감대, 1회 한계, 승인 기준을 적용한 결과
+    pub(crate) fn decide_step(&self, requested: i32, deadband: f64) -> (i32, OffsetDecision) {
+        let (offset_diff, decision) = if requested.abs() < (deadband * 1000.0).round() as i32 {
+            (0, OffsetDecision::WithinDeadband)
         } else {
-            None
+            match self.limit_step(requested) {
+                None => (requested, OffsetDecision::Applied),
+                Some(limited) => match self.step_policy {
+                    StepPolicy::Clamp => (limited, OffsetDecision::Clamped),
+                    StepPolicy::SkipAndAlarm => (requested, OffsetDecision::Skipped),
+                    StepPolicy::RequireApproval => (requested, OffsetDecision::AwaitingApproval),
+                },
+            }
+        };
+        if decision != OffsetDecision::Skipped && self.needs_approval(offset_diff) {
+            (offset_diff, OffsetDecision::AwaitingApproval)
+        } else {
+            (offset_diff, decision)
         }
     }
 
-    fn get_final_offset_as_i32(&self) -> Option<i32> {
-        self.get_final_offset()
-            .map(|offset| (offset * 1000.0).round() as i32)
+    /// 이번 배치에 적용할 불감대 (mm). 고정값과 측정 산포로 구한 값 중 큰 쪽
+    fn deadband_for(&self, batch: &[i32]) -> f64 {
+        if self.deadband_sigma <= 0.0 {
+            return self.deadband;
+        }
+        let values: Vec<f64> = batch.iter().map(|&v| v as f64 / 10000.0).collect();
+        let sigma_band = stats::std_dev(&values)
+            .map(|s| self.deadband_sigma * s / (values.len() as f64).sqrt())
+            .unwrap_or(0.0);
+        self.deadband.max(sigma_band)
     }
 }
 
@@ -50,84 +626,157 @@ impl GaugeBatches {
     pub fn new(
         logger: HistoryLogger,
         batch_size: Arc<Mutex<HashMap<u16, usize>>>,
-        tool_data: Arc<Mutex<HashMap<u16, (ToolData, ToolData)>>>,
-        handle_table: Arc<HashMap<u16, FocasClient>>,
+        tool_data: Arc<Mutex<HashMap<u16, Vec<ToolData>>>>,
+        handle_table: Arc<HashMap<u16, FocasWorker>>,
     ) -> Self {
         Self {
             logger,
             tool_data,
             handle_table,
             batch_size,
+            tool_counts: HashMap::new(),
         }
     }
 
-    pub fn extract_all(&mut self) -> anyhow::Result<Vec<(u16, i16, i32)>> {
+    pub fn extract_all(&mut self) -> anyhow::Result<Vec<AutoCorrection>> {
         //
         let keys = self.handle_table.keys().cloned().collect::<Vec<u16>>();
         keys.into_iter().try_fold(Vec::new(), |mut acc, key| {
-            let mut extracted = self.check_and_extract(key)?;
-            if let Some(upper) = extracted.0.take() {
-                acc.push(upper);
-            }
-            if let Some(lower) = extracted.1.take() {
-                acc.push(lower);
-            }
+            acc.extend(self.check_and_extract(key)?);
+            acc.extend(self.check_feedforward(key));
             Ok(acc)
         })
     }
 
-    pub fn check_and_extract(
-        &mut self,
-        key: u16,
-    ) -> anyhow::Result<(Option<(u16, i16, i32)>, Option<(u16, i16, i32)>)> {
+    /// 예측 보정: 공구 카운트가 늘어난 만큼 추정 마모량을 배치 사이에 미리 보정한다.
+    /// 남은 오차는 다음 배치의 측정 평균이 보정한다. 카운트가 바뀐 채널만 계산한다.
+    fn check_feedforward(&mut self, key: u16) -> Vec<AutoCorrection> {
+        let mut tool_data_map = self.tool_data.lock().unwrap();
+        let mut corrections = Vec::new();
+        for tool in tool_data_map.get_mut(&key).into_iter().flatten() {
+            let Some(count) = tool.tool_count else {
+                continue;
+            };
+            if self.tool_counts.insert((key, tool.key()), count) == Some(count) {
+                continue;
+            }
+            corrections.extend(tool.feedforward_correction(count));
+        }
+        corrections
+    }
+
+    // 공구 교체 직후(워밍업)와 보정 직후(쿨다운)의 측정값은 배치에 넣지 않고 제외 처리.
+    // 다른 채널과 공유하는 측정 항목은 DB에서 건너뛰지 않고 채널별로 배치에서 뺀다.
+    fn skip_excluded(&self, key: u16) {
+        let mut tool_data_map = self.tool_data.lock().unwrap();
+        if let Some(tools) = tool_data_map.get_mut(&key) {
+            let features: Vec<i32> = tools.iter().map(|tool| tool.feature).collect();
+            for tool in tools.iter_mut() {
+                let tool_type = tool.feature;
+                let shared = features.iter().filter(|&&f| f == tool_type).count() > 1;
+                if shared {
+                    match tool.cooldown_until {
+                        Some(until) if until > Utc::now() => {
+                            if let Some((last, _)) = self.logger.last_pending(key, tool_type) {
+                                tool.skip_through = tool.skip_through.max(Some(last));
+                            }
+                        }
+                        Some(_) => tool.cooldown_until = None,
+                        None => {}
+                    }
+                    continue;
+                }
+                if tool.warmup_remaining > 0 {
+                    let skipped =
+                        self.logger
+                            .skip_pending(key, tool_type, tool.warmup_remaining as usize);
+                    tool.warmup_remaining = tool.warmup_remaining.saturating_sub(skipped as u32);
+                }
+                if tool.cooldown_parts_remaining > 0 {
+                    let skipped = self.logger.skip_pending(
+                        key,
+                        tool_type,
+                        tool.cooldown_parts_remaining as usize,
+                    );
+                    tool.cooldown_parts_remaining =
+                        tool.cooldown_parts_remaining.saturating_sub(skipped as u32);
+                }
+                match tool.cooldown_until {
+                    Some(until) if until > Utc::now() => {
+                        self.logger.discard_pending(key, tool_type);
+                    }
+                    Some(_) => tool.cooldown_until = None,
+                    None => {}
+                }
+            }
+        }
+    }
+
+    /// 측정 항목(feature)마다 따로 배치를 모으고, 그 항목을 따르는 채널마다
+    /// 공구의 이상치 규칙으로 측정값을 거른 뒤 보정 전략으로 보정량을 계산한다.
+    /// 제외된 측정값은 사유와 함께 gauge_raw_logs에 표시한다.
+    pub fn check_and_extract(&mut self, key: u16) -> anyhow::Result<Vec<AutoCorrection>> {
+        self.skip_excluded(key);
         let batch_size = *self.batch_size.lock().unwrap().get(&key).unwrap_or(&5);
-        let log_batches = self.logger.fetch_and_process_batch(key, batch_size * 2);
-        if let Some(batches) = log_batches {
-            let avg_point = if batches.len() > 4 {
-                let mut sorted = batches.clone();
-                sorted.sort_unstable();
-                let sum: f64 = sorted[2..sorted.len() - 2].iter().sum::<i32>() as f64;
-                sum / (sorted.len() - 2) as f64
-            } else {
-                let sum: f64 = batches.iter().sum::<i32>() as f64;
-                sum / batches.len() as f64
+        let mut features: Vec<i32> = match self.tool_data.lock().unwrap().get(&key) {
+            Some(tools) => tools.iter().map(|tool| tool.feature).collect(),
+            None => return Err(anyhow!("No tool data found for machine {}", key)),
+        };
+        features.sort_unstable();
+        features.dedup();
+
+        let mut results = Vec::new();
+        let mut rejected = Vec::new();
+        for feature in features {
+            let Some(batch) = self
+                .logger
+                .fetch_and_process_batch(key, feature, batch_size)
+            else {
+                continue;
             };
-            let avg_point = avg_point.round() / 10000.0;
-            match self.tool_data.lock().unwrap().get_mut(&key) {
-                Some((tool_upper, tool_lower)) => {
-                    tool_upper.avg_gauge = Some(avg_point);
-                    tool_lower.avg_gauge = Some(avg_point);
-                    tool_upper.final_offset = tool_upper.get_final_offset();
-                    tool_lower.final_offset = tool_lower.get_final_offset();
-                    let upper = if tool_upper.active {
-                        tool_upper
-                            .get_final_offset_as_i32()
-                            .map(|offset| (tool_upper.machine_id, tool_upper.tool_num, offset))
+            if let Some(tools) = self.tool_data.lock().unwrap().get_mut(&key) {
+                let shared = shares_feature(tools, feature);
+                for tool in tools.iter_mut().filter(|tool| tool.feature == feature) {
+                    // 같은 측정 항목을 여러 채널이 따르면 채널마다 자기 규칙을 적용한다.
+                    let batch = if shared {
+                        let (kept, mut excluded) = tool.exclude_from_shared(&batch);
+                        rejected.append(&mut excluded);
+                        kept
                     } else {
-                        None
+                        batch.clone()
                     };
-                    let lower = if tool_lower.active {
-                        tool_lower
-                            .get_final_offset_as_i32()
-                            .map(|offset| (tool_lower.machine_id, tool_lower.tool_num, offset))
-                    } else {
-                        None
-                    };
-                    Ok((upper, lower))
+                    if batch.is_empty() {
+                        continue;
+                    }
+                    let (correction, mut reasons) = tool.evaluate_batch(&batch);
+                    rejected.append(&mut reasons);
+                    if let Some(correction) = correction.filter(|_| tool.active) {
+                        results.push(correction);
+                    }
                 }
-                None => Err(anyhow!("No tool data found for machine {}", key)),
             }
-        } else {
-            Ok((None, None))
         }
+        self.logger.mark_rejected(rejected);
+        Ok(results)
     }
 }
 
+/// 배치 하나로 계산된 자동 보정 (µm)과 그 근거
+pub struct AutoCorrection {
+    pub machine_id: u16,
+    pub key: ChannelKey,
+    pub offset_diff: i32,
+    pub deadband: f64,    // mm
+    pub predictive: bool, // 게이지 배치가 아니라 마모율 예측으로 계산한 보정
+    pub rationale: String,
+}
+
 pub fn spawn_cnc_loop(
-    handle_table: Arc<HashMap<u16, FocasClient>>,
-    tool_data: Arc<Mutex<HashMap<u16, (ToolData, ToolData)>>>,
+    handle_table: Arc<HashMap<u16, FocasWorker>>,
+    tool_data: Arc<Mutex<HashMap<u16, Vec<ToolData>>>>,
     batch_size: Arc<Mutex<HashMap<u16, usize>>>,
     logger: HistoryLogger,
+    approvals: ApprovalQueue,
 ) -> anyhow::Result<()> {
     let mut gauge_batches =
         GaugeBatches::new(logger, batch_size, tool_data, Arc::clone(&handle_table));
@@ -135,6 +784,13 @@ pub fn spawn_cnc_loop(
         let mut interval = tokio::time::interval(tokio::time::Duration::from_millis(500));
         loop {
             interval.tick().await;
+            expire_approvals(
+                &gauge_batches.handle_table,
+                &gauge_batches.logger,
+                &gauge_batches.tool_data,
+                &approvals,
+            )
+            .await;
             let results = gauge_batches.extract_all().unwrap_or_else(|e| {
                 eprintln!("Batch extraction error: {}", e);
                 Vec::new()
@@ -142,19 +798,20 @@ pub fn spawn_cnc_loop(
             let handle_table_clone = Arc::clone(&gauge_batches.handle_table);
             let logger_clone = gauge_batches.logger.clone();
             let tool_data_clone = Arc::clone(&gauge_batches.tool_data);
+            let approvals_clone = approvals.clone();
             tokio::spawn(async move {
-                let iter = results.into_iter().map(|(machine_id, tool_num, offset)| {
+                let iter = results.into_iter().map(|correction| {
                     let handle_table = Arc::clone(&handle_table_clone);
                     let logger = logger_clone.clone();
                     let tool_data = Arc::clone(&tool_data_clone);
+                    let approvals = approvals_clone.clone();
                     async move {
-                        write_offset_to_cnc(
+                        apply_auto_correction(
                             handle_table,
                             logger,
                             tool_data,
-                            machine_id,
-                            tool_num,
-                            offset,
+                            approvals,
+                            correction,
                         )
                         .await
                     }
@@ -170,71 +827,464 @@ pub fn spawn_cnc_loop(
     Ok(())
 }
 
+/// 자동 보정 경로. 불감대 이내의 보정은 쓰지 않는다. 공구별 step_policy로 1회 보정량
+/// 한계를 적용하고, 승인 기준을 넘으면 승인 대기열에 넣는다. 나머지는 누적 한계를 확인한 뒤 CNC에 쓴다.
+/// 쓰지 않은 경우도 결정과 계산값을 기록한다.
+async fn apply_auto_correction(
+    handle_table: Arc<HashMap<u16, FocasWorker>>,
+    logger: HistoryLogger,
+    tool_data: Arc<Mutex<HashMap<u16, Vec<ToolData>>>>,
+    approvals: ApprovalQueue,
+    correction: AutoCorrection,
+) -> anyhow::Result<()> {
+    let AutoCorrection {
+        machine_id,
+        key,
+        offset_diff: requested,
+        deadband,
+        predictive,
+        rationale,
+    } = correction;
+    let tool = find_tool(&tool_data, machine_id, key)
+        .ok_or_else(|| anyhow!("No tool {} found for machine {}", key, machine_id))?;
+    let (offset_diff, decision) = tool.decide_step(requested, deadband);
+    // 예측 보정은 쿨다운 없이 쓰도록 Predictive로 기록한다 (한계로 자른 경우 근거에 남김)
+    let (decision, rationale) = match decision {
+        OffsetDecision::Applied if predictive => (OffsetDecision::Predictive, rationale),
+        OffsetDecision::Clamped if predictive => (
+            OffsetDecision::Predictive,
+            format!(
+                "{}; clamped to {:.3}mm",
+                rationale,
+                offset_diff as f64 / 1000.0
+            ),
+        ),
+        _ => (decision, rationale),
+    };
+    if tool.shadow {
+        let would_write = if decision == OffsetDecision::Skipped {
+            0
+        } else {
+            offset_diff
+        };
+        return log_shadow_correction(
+            &handle_table,
+            &logger,
+            tool,
+            would_write,
+            requested,
+            decision,
+            rationale,
+        )
+        .await;
+    }
+    match decision {
+        OffsetDecision::WithinDeadband => {
+            return log_unwritten_correction(
+                &handle_table,
+                &logger,
+                &tool_data,
+                OffsetRequest {
+                    requested_change: requested,
+                    rationale: Some(rationale),
+                    ..OffsetRequest::new(machine_id, key, 0, decision)
+                },
+            )
+            .await;
+        }
+        OffsetDecision::Skipped => {
+            raise_alarm(
+                &logger,
+                AlarmLog {
+                    timestamp: Utc::now(),
+                    machine_id,
+                    path: key.path,
+                    tool_num: key.tool_num,
+                    kind: AlarmKind::StepLimit,
+                    level: AlarmLevel::Warning,
+                    message: format!(
+                        "Correction {:.3}mm outside step limit ({:.3}mm to {:.3}mm), skipped",
+                        requested as f64 / 1000.0,
+                        tool.min_limit,
+                        tool.max_limit
+                    ),
+                },
+            );
+            return log_unwritten_correction(
+                &handle_table,
+                &logger,
+                &tool_data,
+                OffsetRequest {
+                    requested_change: requested,
+                    rationale: Some(rationale),
+                    ..OffsetRequest::new(machine_id, key, 0, decision)
+                },
+            )
+            .await;
+        }
+        OffsetDecision::AwaitingApproval => {
+            // 누적 한계에 걸리는 보정은 승인 요청도 하지 않는다
+            if !check_drift_limit(&logger, &tool_data, machine_id, key, offset_diff) {
+                return Ok(());
+            }
+            let (pending, superseded) = approvals.propose(
+                machine_id,
+                key,
+                offset_diff,
+                requested,
+                tool.avg_gauge,
+                rationale,
+            );
+            raise_alarm(
+                &logger,
+                AlarmLog {
+                    timestamp: Utc::now(),
+                    machine_id,
+                    path: key.path,
+                    tool_num: key.tool_num,
+                    kind: AlarmKind::ApprovalRequired,
+                    level: AlarmLevel::Warning,
+                    message: format!(
+                        "Correction {:.3}mm (#{}) awaiting operator approval",
+                        offset_diff as f64 / 1000.0,
+                        pending.id
+                    ),
+                },
+            );
+            // 새 제안으로 대체된 이전 제안은 만료로 기록
+            if let Some(old) = superseded {
+                return log_unwritten_correction(
+                    &handle_table,
+                    &logger,
+                    &tool_data,
+                    old.into_request(OffsetDecision::Expired),
+                )
+                .await;
+            }
+            return Ok(());
+        }
+        _ => {}
+    }
+    if !check_drift_limit(&logger, &tool_data, machine_id, key, offset_diff) {
+        return Ok(());
+    }
+    write_offset_to_cnc(
+        handle_table,
+        logger,
+        tool_data,
+        OffsetRequest {
+            machine_id,
+            key,
+            offset_diff,
+            requested_change: requested,
+            decision,
+            expected_current: None,
+            undo_of: None,
+            rationale: Some(rationale),
+        },
+    )
+    .await
+}
+
+/// 만료된 승인 대기 보정을 offset_history에 기록한다.
+async fn expire_approvals(
+    handle_table: &HashMap<u16, FocasWorker>,
+    logger: &HistoryLogger,
+    tool_data: &Mutex<HashMap<u16, Vec<ToolData>>>,
+    approvals: &ApprovalQueue,
+) {
+    for pending in approvals.take_expired() {
+        let id = pending.id;
+        println!(
+            "Approval #{} expired for machine {}, tool {}",
+            id,
+            pending.machine_id,
+            pending.key()
+        );
+        if let Err(e) = log_unwritten_correction(
+            handle_table,
+            logger,
+            tool_data,
+            pending.into_request(OffsetDecision::Expired),
+        )
+        .await
+        {
+            eprintln!("Failed to record expired approval #{}: {}", id, e);
+        }
+    }
+}
+
+/// 모의 운전 중인 공구의 보정 결정을 현재 CNC 값과 함께 shadow_history에 남긴다.
+/// CNC 옵셋과 공구 상태(누적량, 쿨다운)는 바꾸지 않는다.
+async fn log_shadow_correction(
+    handle_table: &HashMap<u16, FocasWorker>,
+    logger: &HistoryLogger,
+    tool: ToolData,
+    offset_diff: i32,
+    requested: i32,
+    decision: OffsetDecision,
+    rationale: String,
+) -> anyhow::Result<()> {
+    let worker = handle_table
+        .get(&tool.machine_id)
+        .ok_or_else(|| anyhow!("No FOCAS worker found for machine {}", tool.machine_id))?;
+    let (machine_id, key, avg_gauge) = (tool.machine_id, tool.key(), tool.avg_gauge);
+    let current = worker.call(move |client| tool.read_offset(client)).await?;
+    println!(
+        "[SHADOW] machine {}, tool {}: would write {} ({}) to {}",
+        machine_id,
+        key,
+        offset_diff,
+        decision.as_str(),
+        current
+    );
+    logger.log_shadow(ShadowLog {
+        timestamp: Utc::now(),
+        machine_id,
+        path: key.path,
+        tool_num: key.tool_num,
+        avg_gauge,
+        current_value: current,
+        change_amount: offset_diff,
+        requested_change: requested,
+        decision,
+        rationale: Some(rationale),
+    });
+    Ok(())
+}
+
+/// 쓰지 않은 보정을 현재 CNC 값과 함께 offset_history에 남긴다.
+/// request의 requested_change, decision, rationale을 기록하고 offset_diff는 무시한다.
+pub async fn log_unwritten_correction(
+    handle_table: &HashMap<u16, FocasWorker>,
+    logger: &HistoryLogger,
+    tool_data: &Mutex<HashMap<u16, Vec<ToolData>>>,
+    request: OffsetRequest,
+) -> anyhow::Result<()> {
+    let OffsetRequest {
+        machine_id,
+        key,
+        requested_change,
+        decision,
+        rationale,
+        ..
+    } = request;
+    let tool = find_tool(tool_data, machine_id, key)
+        .ok_or_else(|| anyhow!("No tool {} found for machine {}", key, machine_id))?;
+    let worker = handle_table
+        .get(&machine_id)
+        .ok_or_else(|| anyhow!("No FOCAS worker found for machine {}", machine_id))?;
+    let current = worker.call(move |client| tool.read_offset(client)).await?;
+    logger.log_offset(OffsetLog {
+        id: None,
+        timestamp: Utc::now(),
+        machine_id,
+        path: key.path,
+        tool_num: key.tool_num,
+        old_value: current,
+        change_amount: 0,
+        new_value: current,
+        success: false,
+        verified: false,
+        decision: Some(decision),
+        requested_change: Some(requested_change),
+        undo_of: None,
+        rationale,
+        recipe: None,
+    });
+    Ok(())
+}
+
+/// 자동 보정 전에 누적 보정량 한계를 확인한다. 한계를 넘으면 해당 공구의 자동 보정을
+/// 잠그고 점검 요청 알람을 낸다. 잠금은 공구 교체나 작업자 리셋으로만 풀린다.
+pub fn check_drift_limit(
+    logger: &HistoryLogger,
+    tool_data: &Mutex<HashMap<u16, Vec<ToolData>>>,
+    machine_id: u16,
+    key: ChannelKey,
+    offset_diff: i32,
+) -> bool {
+    let mut tool_data_map = tool_data.lock().unwrap();
+    let Some(tool) = find_tool_mut(&mut tool_data_map, machine_id, key) else {
+        return false;
+    };
+    if tool.drift_locked {
+        return false;
+    }
+    if !tool.exceeds_drift(offset_diff) {
+        return true;
+    }
+    tool.drift_locked = true;
+    let message = format!(
+        "Cumulative offset {:.3}mm + {:.3}mm exceeds limit {:.3}mm, automatic compensation stopped. Inspect tool",
+        tool.drift,
+        offset_diff as f64 / 1000.0,
+        tool.max_drift
+    );
+    drop(tool_data_map);
+    raise_alarm(
+        logger,
+        AlarmLog {
+            timestamp: Utc::now(),
+            machine_id,
+            path: key.path,
+            tool_num: key.tool_num,
+            kind: AlarmKind::DriftLimit,
+            level: AlarmLevel::Critical,
+            message,
+        },
+    );
+    false
+}
+
+/// 관리자가 공구 점검 후 누적 보정량을 0으로 되돌리고 자동 보정 잠금을 푼다.
+pub fn reset_drift(
+    tool_data: &Mutex<HashMap<u16, Vec<ToolData>>>,
+    machine_id: u16,
+    key: ChannelKey,
+) -> anyhow::Result<()> {
+    let mut tool_data_map = tool_data.lock().unwrap();
+    let tool = find_tool_mut(&mut tool_data_map, machine_id, key)
+        .ok_or_else(|| anyhow!("No tool {} found for machine {}", key, machine_id))?;
+    println!(
+        "Drift reset for machine {}, tool {}: {:.3}mm (locked: {})",
+        machine_id, key, tool.drift, tool.drift_locked
+    );
+    tool.drift = 0.0;
+    tool.drift_locked = false;
+    Ok(())
+}
+
+/// 가공 수량(#3901)을 읽는다. 계통 선택을 포함하므로 워커 작업 안에서 호출
+pub fn read_part_count(client: &dyn CncBackend, path: i16, number: i16) -> anyhow::Result<i64> {
+    client.set_path(path)?;
+    let (mcr_val, dec_val) = client.read_macro(number)?;
+    Ok((mcr_val as f64 / 10f64.powi(dec_val as i32)).round() as i64)
+}
+
+/// 각 기계의 가공 수량을 주기적으로 읽어 사이클 종료(수량 변화)를 기록한다.
+/// 게이지 측정값은 마지막 사이클 기록의 gauge_part로 부품 번호가 매겨진다.
+pub async fn spawn_part_counter(
+    logger: HistoryLogger,
+    handle_table: Arc<HashMap<u16, FocasWorker>>,
+    machines: Vec<MachineConfig>,
+) {
+    let mut last_counts: HashMap<u16, i64> = HashMap::new();
+    loop {
+        for machine in &machines {
+            let machine_id = machine.id as u16;
+            let Some(worker) = handle_table.get(&machine_id) else {
+                continue;
+            };
+            let (path, number) = (machine.part_count_path, machine.part_count_macro);
+            let count = worker
+                .call(move |client| read_part_count(client, path, number))
+                .await;
+            let count = match count {
+                Ok(count) => count,
+                Err(e) => {
+                    eprintln!(
+                        "Failed to read part count for machine {} - {}",
+                        machine_id, e
+                    );
+                    continue;
+                }
+            };
+            if last_counts.insert(machine_id, count) != Some(count) {
+                let gauge_part = count - machine.parts_in_transit as i64;
+                logger.log_part_cycle(machine_id, count, gauge_part);
+            }
+        }
+        tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
+    }
+}
+
 pub async fn update_offset_logs(
     logger: HistoryLogger,
-    handle_table: Arc<HashMap<u16, FocasClient>>,
-    tool_data: Arc<Mutex<HashMap<u16, (ToolData, ToolData)>>>,
+    handle_table: Arc<HashMap<u16, FocasWorker>>,
+    tool_data: Arc<Mutex<HashMap<u16, Vec<ToolData>>>>,
 ) {
-    let mut last_offsets: HashMap<(u16, i16), i32> = HashMap::new();
+    let mut last_offsets: HashMap<(u16, ChannelKey), i32> = HashMap::new();
     loop {
         // Mutex 범위 최소화: 스냅샷만 뽑고 즉시 해제
-        let snapshot: Vec<(u16, ToolData, ToolData)> = {
+        let snapshot: Vec<(u16, Vec<ToolData>)> = {
             tool_data
                 .lock()
                 .unwrap()
                 .iter()
-                .map(|(&id, (u, l))| (id, u.clone(), l.clone()))
+                .map(|(&id, tools)| (id, tools.clone()))
                 .collect()
         };
 
-        for (machine_id, tool_upper, tool_lower) in snapshot {
-            if let Some(client) = handle_table.get(&machine_id) {
+        for (machine_id, tools) in snapshot {
+            if let Some(worker) = handle_table.get(&machine_id) {
                 println!("Checking offsets for machine {}...", machine_id);
-                if let Ok(current_upper) = client.rdtofs(tool_upper.tool_num, 0) {
-                    let current_upper_value = current_upper.data as i32;
-                    let last_upper_value = last_offsets
-                        .get(&(machine_id, tool_upper.tool_num))
-                        .cloned()
-                        .unwrap_or(current_upper_value);
-                    if current_upper_value != last_upper_value {
+                let jobs = tools.clone();
+                let written = worker.written_offsets();
+                // 읽기와 같은 작업 안에서 앱이 쓴 값을 가져와야 그 사이의 쓰기를 놓치지 않는다
+                let (current, written) = worker
+                    .call(move |client| {
+                        let current = jobs
+                            .iter()
+                            .map(|tool| tool.read_offset(client).ok())
+                            .collect::<Vec<_>>();
+                        Ok((current, std::mem::take(&mut *written.lock().unwrap())))
+                    })
+                    .await
+                    .unwrap_or_else(|e| {
+                        eprintln!("Failed to read offsets for machine {} - {}", machine_id, e);
+                        (vec![None; tools.len()], HashMap::new())
+                    });
+                for (tool, current) in tools.iter().zip(current) {
+                    let written = written.get(&tool.key()).copied();
+                    let Some(current_value) = current else {
+                        if let Some(value) = written {
+                            last_offsets.insert((machine_id, tool.key()), value);
+                        }
+                        continue;
+                    };
+                    // 앱이 쓴 값은 이미 기록됐으므로 그 이후의 변경만 외부 변경으로 본다
+                    let last_value = written
+                        .or_else(|| last_offsets.get(&(machine_id, tool.key())).cloned())
+                        .unwrap_or(current_value);
+                    if current_value != last_value {
                         println!(
                             "Offset change detected for machine {}, tool {}: {} -> {}",
-                            machine_id, tool_upper.tool_num, last_upper_value, current_upper_value
-                        );
-                        logger.log_offset(OffsetLog {
-                            timestamp: chrono::Utc::now(),
                             machine_id,
-                            tool_num: tool_upper.tool_num,
-                            old_value: last_upper_value,
-                            change_amount: current_upper_value - last_upper_value,
-                            new_value: current_upper_value,
-                            success: true,
-                        });
-                    }
-                    last_offsets.insert((machine_id, tool_upper.tool_num), current_upper_value);
-                }
-                if let Ok(current_lower) = client.rdtofs(tool_lower.tool_num, 0) {
-                    let current_lower_value = current_lower.data as i32;
-                    let last_lower_value = last_offsets
-                        .get(&(machine_id, tool_lower.tool_num))
-                        .cloned()
-                        .unwrap_or(current_lower_value);
-                    if current_lower_value != last_lower_value {
-                        println!(
-                            "Offset change detected for machine {}, tool {}: {} -> {}",
-                            machine_id, tool_lower.tool_num, last_lower_value, current_lower_value
+                            tool.key(),
+                            last_value,
+                            current_value
                         );
                         logger.log_offset(OffsetLog {
+                            id: None,
                             timestamp: chrono::Utc::now(),
                             machine_id,
-                            tool_num: tool_lower.tool_num,
-                            old_value: last_lower_value,
-                            change_amount: current_lower_value - last_lower_value,
-                            new_value: current_lower_value,
+                            path: tool.path,
+                            tool_num: tool.tool_num,
+                            old_value: last_value,
+                            change_amount: current_value - last_value,
+                            new_value: current_value,
                             success: true,
+                            verified: true,
+                            decision: Some(OffsetDecision::External),
+                            requested_change: None,
+                            undo_of: None,
+                            rationale: None,
+                            recipe: None,
                         });
+                        if is_offset_jump(tool, current_value - last_value) {
+                            if let Err(e) = handle_tool_change(
+                                &logger,
+                                &tool_data,
+                                machine_id,
+                                tool.key(),
+                                ToolChangeReason::OffsetJump,
+                            ) {
+                                eprintln!("Tool change handling failed: {}", e);
+                            }
+                        }
                     }
-                    last_offsets.insert((machine_id, tool_lower.tool_num), current_lower_value);
+                    last_offsets.insert((machine_id, tool.key()), current_value);
                 }
             }
         }
@@ -242,43 +1292,591 @@ pub async fn update_offset_logs(
     }
 }
 
+/// 옵셋 쓰기 요청. requested_change는 한계 적용 전 계산값 (수동 쓰기는 offset_diff와 같음)
+pub struct OffsetRequest {
+    pub machine_id: u16,
+    pub key: ChannelKey,
+    pub offset_diff: i32,
+    pub requested_change: i32,
+    pub decision: OffsetDecision,
+    pub expected_current: Option<i32>, // 지정 시 CNC 값이 이 값일 때만 씀
+    pub undo_of: Option<i64>,          // 되돌리는 offset_history 기록 id
+    pub rationale: Option<String>,     // 자동 보정의 계산 근거
+}
+
+impl OffsetRequest {
+    pub fn new(
+        machine_id: u16,
+        key: ChannelKey,
+        offset_diff: i32,
+        decision: OffsetDecision,
+    ) -> Self {
+        Self {
+            machine_id,
+            key,
+            offset_diff,
+            requested_change: offset_diff,
+            decision,
+            expected_current: None,
+            undo_of: None,
+            rationale: None,
+        }
+    }
+
+    /// 기록된 변경을 되돌리는 요청. CNC가 아직 그 기록의 new_value를 가지고 있어야 한다.
+    pub fn undo(entry: &OffsetLog) -> anyhow::Result<Self> {
+        let id = entry
+            .id
+            .ok_or_else(|| anyhow!("Offset history entry has no id"))?;
+        let offset_diff = entry.old_value - entry.new_value;
+        Ok(Self {
+            machine_id: entry.machine_id,
+            key: ChannelKey::new(entry.path, entry.tool_num),
+            offset_diff,
+            requested_change: offset_diff,
+            decision: OffsetDecision::Undo,
+            expected_current: Some(entry.new_value),
+            undo_of: Some(id),
+            rationale: None,
+        })
+    }
+}
+
 pub async fn write_offset_to_cnc(
-    handle_table: Arc<HashMap<u16, FocasClient>>,
+    handle_table: Arc<HashMap<u16, FocasWorker>>,
     logger: HistoryLogger,
-    tool_data: Arc<Mutex<HashMap<u16, (ToolData, ToolData)>>>,
-    machine_id: u16,
-    tool_num: i16,
-    offset_diff: i32,
+    tool_data: Arc<Mutex<HashMap<u16, Vec<ToolData>>>>,
+    request: OffsetRequest,
 ) -> anyhow::Result<()> {
-    if let Some(client) = handle_table.get(&machine_id) {
-        let current_offset = client.rdtofs(tool_num, 0)?;
-        let old_offset = current_offset.data as i32;
-        let new_offset = current_offset.data as i32 + offset_diff;
-        let result = client.wrtofs(tool_num, 0, new_offset);
+    let OffsetRequest {
+        machine_id,
+        key,
+        offset_diff,
+        requested_change,
+        decision,
+        expected_current,
+        undo_of,
+        rationale,
+    } = request;
+    let tool = find_tool(&tool_data, machine_id, key)
+        .ok_or_else(|| anyhow!("No tool {} found for machine {}", key, machine_id))?;
+    if let Some(worker) = handle_table.get(&machine_id) {
+        let job_tool = tool.clone();
+        let written = worker.written_offsets();
+        // 시간 초과로 기다리지 않게 된 쓰기는 기록하지 않고 옵셋 감시가 외부 변경으로 맞추게 한다
+        let handoff = Arc::new(Mutex::new(Handoff::Waiting));
+        let job_handoff = handoff.clone();
+        let job_written = written.clone();
+        let outcome = worker
+            .call(move |client| {
+                let outcome = write_verified(&job_tool, client, offset_diff, expected_current)?;
+                let mut written = job_written.lock().unwrap();
+                let mut handoff = job_handoff.lock().unwrap();
+                if let (Handoff::Waiting, Some(value)) = (&*handoff, outcome.logged_value()) {
+                    *handoff = Handoff::Recorded(written.insert(job_tool.key(), value));
+                }
+                Ok(outcome)
+            })
+            .await;
+        let WriteOutcome {
+            old_offset,
+            base,
+            new_offset,
+            result,
+            read_back,
+            races,
+            restored,
+        } = match outcome {
+            Ok(outcome) => outcome,
+            Err(e) => {
+                let mut written = written.lock().unwrap();
+                let mut handoff = handoff.lock().unwrap();
+                if let Handoff::Recorded(previous) = *handoff {
+                    match previous {
+                        Some(value) => written.insert(key, value),
+                        None => written.remove(&key),
+                    };
+                }
+                *handoff = Handoff::Abandoned;
+                return Err(e);
+            }
+        };
+        // 쓰는 도중 조작반 등에서 바꾼 값은 앱의 보정과 따로 외부 변경으로 남긴다
+        for (from, to) in races {
+            logger.log_offset(OffsetLog {
+                id: None,
+                timestamp: chrono::Utc::now(),
+                machine_id,
+                path: key.path,
+                tool_num: key.tool_num,
+                old_value: from,
+                change_amount: to - from,
+                new_value: to,
+                success: true,
+                verified: true,
+                decision: Some(OffsetDecision::External),
+                requested_change: None,
+                undo_of: None,
+                rationale: Some("changed at the control during offset write".to_string()),
+                recipe: None,
+            });
+        }
+        if let Some(restore) = restored {
+            // 되돌렸으므로 앱의 보정은 순변화 0으로 남기고 누적량/쿨다운/마모는 건드리지 않는다
+            eprintln!(
+                "Offset write for machine {}, tool {} rolled back to {}",
+                machine_id, key, old_offset
+            );
+            logger.log_offset(OffsetLog {
+                id: None,
+                timestamp: chrono::Utc::now(),
+                machine_id,
+                path: key.path,
+                tool_num: key.tool_num,
+                old_value: old_offset,
+                change_amount: 0,
+                new_value: old_offset,
+                success: false,
+                verified: false,
+                decision: Some(decision),
+                requested_change: Some(requested_change),
+                undo_of,
+                rationale: Some(
+                    rationale.map_or_else(String::new, |r| r + "; ")
+                        + "rolled back after read-back mismatch",
+                ),
+                recipe: None,
+            });
+            logger.log_offset(OffsetLog {
+                id: None,
+                timestamp: chrono::Utc::now(),
+                machine_id,
+                path: key.path,
+                tool_num: key.tool_num,
+                old_value: restore.from,
+                change_amount: old_offset - restore.from,
+                new_value: old_offset,
+                success: restore.result.is_ok(),
+                verified: restore.read_back == Some(old_offset),
+                decision: Some(OffsetDecision::Restored),
+                requested_change: None,
+                undo_of: None,
+                rationale: None,
+                recipe: None,
+            });
+            return Ok(());
+        }
+        let verified = result.is_ok() && read_back == Some(new_offset);
         if result.is_ok() {
+            let part_count = logger.last_part_count(machine_id);
             println!(
-                "Successfully updated offset for machine {}, tool {}: {} -> {}",
-                machine_id, tool_num, old_offset, new_offset
+                "Successfully updated offset for machine {}, tool {}: {} -> {} (verified: {})",
+                machine_id, key, base, new_offset, verified
             );
-            if let Some((upper, lower)) = tool_data.lock().unwrap().get_mut(&machine_id) {
-                if upper.tool_num == tool_num {
-                    upper.manual_offset = 0.0
-                } else if lower.tool_num == tool_num {
-                    lower.manual_offset = 0.0
-                }
+            if let Some(tool) = find_tool_mut(&mut tool_data.lock().unwrap(), machine_id, key) {
+                tool.on_offset_written(offset_diff, decision, part_count);
             }
         }
         logger.log_offset(OffsetLog {
+            id: None,
             timestamp: chrono::Utc::now(),
             machine_id,
-            tool_num,
-            old_value: old_offset,
+            path: key.path,
+            tool_num: key.tool_num,
+            old_value: base,
             change_amount: offset_diff,
             new_value: new_offset,
             success: result.is_ok(),
+            verified,
+            decision: Some(decision),
+            requested_change: Some(requested_change),
+            undo_of,
+            rationale,
+            recipe: None,
         });
         Ok(())
     } else {
-        Err(anyhow!("No CNC client found for machine {}", machine_id))
+        Err(anyhow!("No FOCAS worker found for machine {}", machine_id))
+    }
+}
+
+fn is_offset_jump(tool: &ToolData, change: i32) -> bool {
+    tool.tool_change_offset > 0.0 && (change.abs() as f64 / 1000.0) >= tool.tool_change_offset
+}
+
+/// 쓰기 작업이 앱이 쓴 값을 넘겼는지. 기다리던 쪽이 포기하면 넘긴 값을 되돌린다.
+enum Handoff {
+    Waiting,
+    Recorded(Option<i32>), // 덮어쓴 이전 값
+    Abandoned,
+}
+
+struct WriteOutcome {
+    old_offset: i32, // 첫 쓰기 전 값
+    base: i32,       // 마지막 쓰기의 기준 값 (재시도하면 다른 쪽에서 바꾼 값)
+    new_offset: i32,
+    result: anyhow::Result<()>,
+    read_back: Option<i32>,
+    races: Vec<(i32, i32)>, // 쓰는 도중 다른 쪽에서 바꾼 값 (이전 값, 바뀐 값)
+    restored: Option<RestoreOutcome>,
+}
+
+impl WriteOutcome {
+    /// 기록에 남는 CNC의 마지막 값. 옵셋 감시가 같은 변경을 다시 기록하지 않도록 넘긴다.
+    fn logged_value(&self) -> Option<i32> {
+        match &self.restored {
+            Some(restore) if restore.result.is_ok() => Some(self.old_offset),
+            Some(restore) => Some(restore.from),
+            None if self.result.is_ok() => Some(self.new_offset),
+            None => self.races.last().map(|&(_, to)| to),
+        }
+    }
+}
+
+struct RestoreOutcome {
+    from: i32, // 되돌리기 직전 CNC 값
+    result: anyhow::Result<()>,
+    read_back: Option<i32>,
+}
+
+/// 읽기 → 쓰기 → 다시 읽기를 한 워커 작업 안에서 실행한다.
+fn write_verified(
+    tool: &ToolData,
+    client: &dyn CncBackend,
+    offset_diff: i32,
+    expected_current: Option<i32>,
+) -> anyhow::Result<WriteOutcome> {
+    // 첫 시도 전 값. 되돌림에는 이 값을 쓴다.
+    let old_offset = tool.read_offset(client)?;
+    if let Some(expected) = expected_current {
+        if old_offset != expected {
+            return Err(anyhow!(
+                "CNC offset for machine {}, tool {} is {}, expected {}",
+                tool.machine_id,
+                tool.tool_num,
+                old_offset,
+                expected
+            ));
+        }
+    }
+    let mut base = old_offset;
+    let mut new_offset = old_offset + offset_diff;
+    let (mut result, mut read_back) = write_and_read_back(tool, client, new_offset);
+    let mut retries = 0;
+    let mut races = Vec::new();
+    let mut restored = None;
+    // 쓰기 직후 다른 쪽(조작반 등)에서 값을 바꿨다면 정책에 따라 재시도하거나 되돌림
+    while result.is_ok() && read_back != Some(new_offset) {
+        eprintln!(
+            "Offset read-back mismatch for machine {}, tool {}: expected {}, read {:?}",
+            tool.machine_id, tool.tool_num, new_offset, read_back
+        );
+        let Some(current) = read_back else {
+            break;
+        };
+        match tool.verify_policy {
+            VerifyPolicy::Retry { attempts } if retries < attempts => {
+                retries += 1;
+                // 앞선 쓰기는 덮어써졌으므로 기준 값에서 바뀐 값까지를 외부 변경으로 본다
+                races.push((base, current));
+                base = current;
+                new_offset = current + offset_diff;
+                (result, read_back) = write_and_read_back(tool, client, new_offset);
+            }
+            VerifyPolicy::Retry { .. } => break,
+            VerifyPolicy::Restore => {
+                races.push((old_offset, current));
+                let (result, read_back) = write_and_read_back(tool, client, old_offset);
+                restored = Some(RestoreOutcome {
+                    from: current,
+                    result,
+                    read_back,
+                });
+                break;
+            }
+        }
+    }
+    Ok(WriteOutcome {
+        old_offset,
+        base,
+        new_offset,
+        result,
+        read_back,
+        races,
+        restored,
+    })
+}
+
+fn write_and_read_back(
+    tool: &ToolData,
+    client: &dyn CncBackend,
+    value: i32,
+) -> (anyhow::Result<()>, Option<i32>) {
+    let result = tool.write_offset(client, value);
+    let read_back = if result.is_ok() {
+        tool.read_offset(client).ok()
+    } else {
+        None
+    };
+    (result, read_back)
+}
+
+pub fn find_tool(
+    tool_data: &Mutex<HashMap<u16, Vec<ToolData>>>,
+    machine_id: u16,
+    key: ChannelKey,
+) -> Option<ToolData> {
+    let tool_data_map = tool_data.lock().unwrap();
+    tool_data_map
+        .get(&machine_id)?
+        .iter()
+        .find(|tool| tool.key() == key)
+        .cloned()
+}
+
+pub fn find_tool_mut(
+    tool_data_map: &mut HashMap<u16, Vec<ToolData>>,
+    machine_id: u16,
+    key: ChannelKey,
+) -> Option<&mut ToolData> {
+    tool_data_map
+        .get_mut(&machine_id)?
+        .iter_mut()
+        .find(|tool| tool.key() == key)
+}
+
+/// 한 기계에서 이 측정 항목을 따르는 채널이 둘 이상인지
+pub fn shares_feature(tools: &[ToolData], feature: i32) -> bool {
+    tools.iter().filter(|tool| tool.feature == feature).count() > 1
+}
+
+/// 한 기계의 채널 목록에서 (계통, 공구 번호)가 겹치는 키를 찾는다.
+pub fn duplicate_key(tools: &[ToolData]) -> Option<ChannelKey> {
+    let mut seen = std::collections::HashSet::new();
+    tools
+        .iter()
+        .map(ToolData::key)
+        .find(|key| !seen.insert(*key))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::AppConfig;
+
+    fn tool() -> ToolData {
+        AppConfig::default().mapping.tool_data[&0][0].clone()
+    }
+
+    #[test]
+    fn test_exclude_from_shared() {
+        let mut tool = tool();
+        tool.skip_through = Some(2);
+        tool.warmup_remaining = 1;
+        tool.cooldown_parts_remaining = 1;
+        let batch: Vec<BatchEntry> = (1..=5).map(|id| (id, 100, None)).collect();
+        let (kept, excluded) = tool.exclude_from_shared(&batch);
+        // 1, 2: 교체 전 측정 / 3: 워밍업 / 4: 쿨다운
+        assert_eq!(kept, vec![(5, 100, None)]);
+        assert_eq!(
+            excluded.iter().map(|&(id, _)| id).collect::<Vec<_>>(),
+            vec![1, 2, 3, 4]
+        );
+        assert_eq!(tool.warmup_remaining, 0);
+        assert_eq!(tool.cooldown_parts_remaining, 0);
+
+        // 제외 조건이 없으면 그대로
+        let (kept, excluded) = tool.exclude_from_shared(&[(6, 100, None)]);
+        assert_eq!(kept.len(), 1);
+        assert!(excluded.is_empty());
+    }
+
+    fn parts(parts: std::ops::RangeInclusive<i64>) -> Vec<BatchEntry> {
+        parts.map(|part| (part as i32, 1000, Some(part))).collect()
+    }
+
+    #[test]
+    fn test_compensate_transit() {
+        let mut tool = tool();
+        // 부품 10까지 가공된 뒤 +0.005mm, 12까지 가공된 뒤 -0.003mm를 썼다
+        tool.on_offset_written(5, OffsetDecision::Applied, Some(10));
+        tool.on_offset_written(-3, OffsetDecision::Applied, Some(12));
+        let (values, adjusted) = tool.compensate_transit(&parts(9..=13));
+        assert_eq!(values, vec![1020, 1020, 970, 970, 1000]);
+        assert_eq!(adjusted, 4);
+        // 배치의 마지막 부품(13)은 두 보정 이후에 가공됐으므로 목록에서 빠진다
+        assert!(tool.in_transit.is_empty());
+
+        // 부품 번호가 없는 측정은 그대로
+        tool.on_offset_written(5, OffsetDecision::Applied, Some(20));
+        let batch = [(1, 1000, None), (2, 1000, None)];
+        assert_eq!(tool.compensate_transit(&batch), (vec![1000, 1000], 0));
+        assert_eq!(tool.in_transit.len(), 1);
+    }
+
+    #[test]
+    fn test_compensate_transit_beyond_history() {
+        let mut tool = tool();
+        // 이송 중 부품이 배치보다 많아 배치 전체가 보정 전에 가공된 경우
+        tool.on_offset_written(4, OffsetDecision::Applied, Some(20));
+        let (values, adjusted) = tool.compensate_transit(&parts(3..=7));
+        assert_eq!(values, vec![1040; 5]);
+        assert_eq!(adjusted, 5);
+        // 보정이 아직 측정에 나타나지 않았으므로 다음 배치를 위해 남겨 둔다
+        assert_eq!(tool.in_transit.len(), 1);
+        let (values, _) = tool.compensate_transit(&parts(18..=22));
+        assert_eq!(values, vec![1040, 1040, 1040, 1000, 1000]);
+        assert!(tool.in_transit.is_empty());
+    }
+
+    #[test]
+    fn test_limit_step() {
+        let tool = tool(); // 한계 -0.010 ~ +0.010mm
+        assert_eq!(tool.limit_step(10), None);
+        assert_eq!(tool.limit_step(-10), None);
+        assert_eq!(tool.limit_step(11), Some(10));
+        assert_eq!(tool.limit_step(-25), Some(-10));
+    }
+
+    #[test]
+    fn test_decide_step_policies() {
+        let mut tool = tool();
+        assert_eq!(tool.decide_step(5, 0.0), (5, OffsetDecision::Applied));
+        tool.step_policy = StepPolicy::Clamp;
+        assert_eq!(tool.decide_step(15, 0.0), (10, OffsetDecision::Clamped));
+        assert_eq!(tool.decide_step(-15, 0.0), (-10, OffsetDecision::Clamped));
+        tool.step_policy = StepPolicy::SkipAndAlarm;
+        assert_eq!(tool.decide_step(15, 0.0), (15, OffsetDecision::Skipped));
+        tool.step_policy = StepPolicy::RequireApproval;
+        assert_eq!(
+            tool.decide_step(15, 0.0),
+            (15, OffsetDecision::AwaitingApproval)
+        );
+        assert_eq!(tool.decide_step(5, 0.0), (5, OffsetDecision::Applied));
+    }
+
+    #[test]
+    fn test_decide_step_approval_threshold() {
+        let mut tool = tool();
+        tool.approval_threshold = 0.004;
+        assert_eq!(tool.decide_step(4, 0.0), (4, OffsetDecision::Applied));
+        assert_eq!(
+            tool.decide_step(-5, 0.0),
+            (-5, OffsetDecision::AwaitingApproval)
+        );
+        // 한계로 자른 값이 승인 기준을 넘어도 승인 대기
+        assert_eq!(
+            tool.decide_step(15, 0.0),
+            (10, OffsetDecision::AwaitingApproval)
+        );
+        // 건너뛴 보정은 승인 요청하지 않는다
+        tool.step_policy = StepPolicy::SkipAndAlarm;
+        assert_eq!(tool.decide_step(15, 0.0), (15, OffsetDecision::Skipped));
+    }
+
+    #[test]
+    fn test_decide_step_deadband() {
+        let mut tool = tool();
+        tool.approval_threshold = 0.001;
+        // 불감대 이내는 한계/승인보다 먼저 판단한다
+        assert_eq!(
+            tool.decide_step(1, 0.002),
+            (0, OffsetDecision::WithinDeadband)
+        );
+        assert_eq!(
+            tool.decide_step(-1, 0.002),
+            (0, OffsetDecision::WithinDeadband)
+        );
+        assert_eq!(
+            tool.decide_step(2, 0.002),
+            (2, OffsetDecision::AwaitingApproval)
+        );
+        tool.approval_threshold = 0.0;
+        assert_eq!(tool.decide_step(2, 0.002), (2, OffsetDecision::Applied));
+    }
+
+    #[test]
+    fn test_deadband_for() {
+        let mut tool = tool();
+        tool.deadband = 0.002;
+        let spread = [0, 100, 0, 100]; // 0 / 0.010mm
+        assert_eq!(tool.deadband_for(&spread), 0.002);
+        // σ = 0.005774mm, 표준오차 σ/√4의 2배 → 0.005774mm
+        tool.deadband_sigma = 2.0;
+        assert!((tool.deadband_for(&spread) - 0.005774).abs() < 1e-6);
+        // 산포가 작으면 고정 불감대
+        assert_eq!(tool.deadband_for(&[100; 4]), 0.002);
+        assert_eq!(tool.deadband_for(&[100]), 0.002);
+    }
+
+    // 첫 쓰기 직후 조작반에서 값을 바꾸는 CNC
+    struct RacingCnc {
+        value: std::cell::Cell<i32>,
+        race: std::cell::Cell<Option<i32>>,
+    }
+
+    impl RacingCnc {
+        fn new(value: i32, race: i32) -> Self {
+            Self {
+                value: std::cell::Cell::new(value),
+                race: std::cell::Cell::new(Some(race)),
+            }
+        }
+    }
+
+    impl CncBackend for RacingCnc {
+        fn set_path(&self, _path: i16) -> anyhow::Result<()> {
+            Ok(())
+        }
+        fn read_tool_offset(&self, _tool_num: i16) -> anyhow::Result<i32> {
+            Ok(self.value.get())
+        }
+        fn write_tool_offset(&self, _tool_num: i16, value: i32) -> anyhow::Result<()> {
+            self.value.set(self.race.take().unwrap_or(value));
+            Ok(())
+        }
+        fn read_macro(&self, _number: i16) -> anyhow::Result<(i32, i16)> {
+            unimplemented!()
+        }
+        fn write_macro(&self, _number: i16, _value: i32, _dec_val: i16) -> anyhow::Result<()> {
+            unimplemented!()
+        }
+        fn read_tool_life(&self, _tool_num: i16) -> anyhow::Result<i32> {
+            unimplemented!()
+        }
+        fn read_tool_count(&self, _tool_num: i16) -> anyhow::Result<i32> {
+            unimplemented!()
+        }
+        fn write_pmc(&self, _adr_type: i16, _address: u16, _data: &[u8]) -> anyhow::Result<()> {
+            unimplemented!()
+        }
+    }
+
+    #[test]
+    fn test_write_verified_retry_race() {
+        let tool = tool();
+        let cnc = RacingCnc::new(100, 150);
+        let outcome = write_verified(&tool, &cnc, 10, None).unwrap();
+        // 조작반 변경 100 -> 150은 따로, 앱의 보정은 150 -> 160으로 남긴다
+        assert_eq!(outcome.races, vec![(100, 150)]);
+        assert_eq!((outcome.base, outcome.new_offset), (150, 160));
+        assert_eq!(outcome.read_back, Some(160));
+        assert!(outcome.restored.is_none());
+        assert_eq!(outcome.logged_value(), Some(160));
+        assert_eq!(cnc.value.get(), 160);
+    }
+
+    #[test]
+    fn test_write_verified_restore_race() {
+        let mut tool = tool();
+        tool.verify_policy = VerifyPolicy::Restore;
+        let cnc = RacingCnc::new(100, 150);
+        let outcome = write_verified(&tool, &cnc, 10, None).unwrap();
+        assert_eq!(outcome.races, vec![(100, 150)]);
+        let restore = outcome.restored.as_ref().unwrap();
+        assert_eq!(restore.from, 150);
+        assert_eq!(restore.read_back, Some(100));
+        assert_eq!(outcome.logged_value(), Some(100));
+        assert_eq!(cnc.value.get(), 100);
     }
 }
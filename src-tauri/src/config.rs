@@ -3,7 +3,13 @@ use std::collections::HashMap;
 use std::fs;
 use std::path::Path;
 
-use crate::cnc::ToolData;
+use crate::cnc::{duplicate_key, OffsetTarget, StepPolicy, ToolData, VerifyPolicy};
+use crate::outlier::OutlierRule;
+use crate::recipe::Recipe;
+use crate::sim::SimulationConfig;
+use crate::spc::SpcConfig;
+use crate::strategy::{StrategyConfig, StrategyState};
+use crate::wear::WearModel;
 use crate::AppState;
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -14,6 +20,10 @@ pub struct AppConfig {
     pub admin: AdminConfig,
     pub log_path: String,
     pub ui: UiConfig,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub simulation: Option<SimulationConfig>, // 있으면 실제 CNC/게이지 대신 시뮬레이션
+    #[serde(default)]
+    pub recipes: Vec<Recipe>, // 부품별 설정
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -36,110 +46,125 @@ pub struct MachineConfig {
     pub name: String, // "1호기(OP-10)"
     pub ip: String,   // CNC IP
     pub port: i16,    // Focas 포트 (보통 8193)
+    #[serde(default)]
+    pub parts_in_transit: u32, // 가공 완료 후 게이지에 도달하기 전까지 대기 중인 부품 수 (이송 지연)
+    #[serde(default = "default_part_count_macro")]
+    pub part_count_macro: i16, // 가공 수량 매크로 변수 (#3901)
+    #[serde(default = "default_part_count_path")]
+    pub part_count_path: i16,
+    #[serde(default = "default_focas_timeout_ms")]
+    pub focas_timeout_ms: u64, // FOCAS 호출 1회당 응답 대기 시간
+}
+
+fn default_focas_timeout_ms() -> u64 {
+    3000
+}
+
+fn default_part_count_macro() -> i16 {
+    3901
+}
+
+fn default_part_count_path() -> i16 {
+    1
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct MappingConfig {
-    pub tool_data: HashMap<u16, (ToolData, ToolData)>, // machine_id -> (ToolDataUpper, ToolDataLower)
-    pub batch_size: HashMap<u16, usize>,               // machine_id -> batch_size
+    pub tool_data: HashMap<u16, Vec<ToolData>>, // machine_id -> 보정 채널 목록
+    pub batch_size: HashMap<u16, usize>,        // machine_id -> batch_size
+    #[serde(default)]
+    pub recipe: HashMap<u16, String>, // machine_id -> 활성 레시피 이름
+}
+
+impl MappingConfig {
+    /// 이전 설정은 (황삭, 정삭) 2개 배열이었으므로 이름과 게이지 값 번호가 없으면
+    /// 순서대로 채운다 (0번: 황삭/Value1, 1번: 정삭/Value2).
+    fn fill_channel_defaults(&mut self) {
+        for tools in self.tool_data.values_mut() {
+            for (index, tool) in tools.iter_mut().enumerate() {
+                if tool.feature == 0 {
+                    tool.feature = index as i32 + 1;
+                }
+                if tool.name.is_empty() {
+                    tool.name = match index {
+                        0 => "황삭".to_string(),
+                        1 => "정삭".to_string(),
+                        _ => format!("T{}", tool.tool_num),
+                    };
+                }
+            }
+        }
+    }
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct AdminConfig {
     pub password: String,
+    #[serde(default = "default_approval_ttl_secs")]
+    pub approval_ttl_secs: u64, // 승인 대기 보정의 유효 시간
+}
+
+fn default_approval_ttl_secs() -> u64 {
+    600
 }
 
 impl Default for AppConfig {
     fn default() -> Self {
-        let tool_data = HashMap::from([
-            (
-                0,
-                (
-                    ToolData {
-                        machine_id: 0,
-                        tool_num: 11,
-                        basic_size: 48.0,
-                        manual_offset: 0.0,
-                        offset_rate: 1.0,
-                        active: false,
-                        avg_gauge: None,
-                        final_offset: None,
-                        max_limit: 0.01,
-                        min_limit: -0.01,
-                    },
-                    ToolData {
-                        machine_id: 0,
-                        tool_num: 12,
-                        basic_size: 48.0,
-                        manual_offset: 0.0,
-                        offset_rate: 1.0,
-                        active: false,
-                        avg_gauge: None,
-                        final_offset: None,
-                        max_limit: 0.01,
-                        min_limit: -0.01,
-                    },
-                ),
-            ),
-            (
-                1,
-                (
-                    ToolData {
-                        machine_id: 1,
-                        tool_num: 11,
-                        basic_size: 48.0,
-                        manual_offset: 0.0,
-                        offset_rate: 1.0,
-                        active: false,
-                        avg_gauge: None,
-                        final_offset: None,
-                        max_limit: 0.01,
-                        min_limit: -0.01,
-                    },
-                    ToolData {
-                        machine_id: 1,
-                        tool_num: 12,
-                        basic_size: 48.0,
-                        manual_offset: 0.0,
-                        offset_rate: 1.0,
-                        active: false,
-                        avg_gauge: None,
-                        final_offset: None,
-                        max_limit: 0.01,
-                        min_limit: -0.01,
-                    },
-                ),
-            ),
-            (
-                2,
-                (
-                    ToolData {
-                        machine_id: 2,
-                        tool_num: 11,
-                        basic_size: 48.0,
-                        manual_offset: 0.0,
-                        offset_rate: 1.0,
-                        active: false,
-                        avg_gauge: None,
-                        final_offset: None,
-                        max_limit: 0.01,
-                        min_limit: -0.01,
-                    },
-                    ToolData {
-                        machine_id: 2,
-                        tool_num: 12,
-                        basic_size: 48.0,
-                        manual_offset: 0.0,
-                        offset_rate: 1.0,
-                        active: false,
-                        avg_gauge: None,
-                        final_offset: None,
-                        max_limit: 0.01,
-                        min_limit: -0.01,
-                    },
-                ),
-            ),
-        ]);
+        let tool = |machine_id: u16, tool_num: i16, name: &str, feature: i32| ToolData {
+            name: name.to_string(),
+            feature,
+            machine_id,
+            tool_num,
+            basic_size: 48.0,
+            manual_offset: 0.0,
+            offset_rate: 1.0,
+            active: false,
+            avg_gauge: None,
+            final_offset: None,
+            max_limit: 0.01,
+            min_limit: -0.01,
+            target: OffsetTarget::ToolOffset,
+            path: 1,
+            verify_policy: VerifyPolicy::default(),
+            life_warning: 0.9,
+            life_limit: 1.0,
+            life_stop_signal: None,
+            tool_change_offset: 0.1,
+            warmup_parts: 0,
+            warmup_remaining: 0,
+            cooldown_parts: 0,
+            cooldown_secs: 0,
+            cooldown_parts_remaining: 0,
+            cooldown_until: None,
+            max_drift: 0.1,
+            drift: 0.0,
+            drift_locked: false,
+            step_policy: StepPolicy::Clamp,
+            approval_threshold: 0.0,
+            shadow: false,
+            deadband: 0.0,
+            deadband_sigma: 0.0,
+            outlier_rule: OutlierRule::default(),
+            strategy: StrategyConfig::default(),
+            strategy_state: StrategyState::default(),
+            spc: SpcConfig::default(),
+            predictive: false,
+            wear_window: 10,
+            feedforward_step: 0.002,
+            wear: WearModel::default(),
+            tool_count: None,
+            in_transit: Vec::new(),
+            skip_through: None,
+            auto_tune: false,
+            rate_suggestion: None,
+        };
+        let channels = |machine_id: u16| {
+            vec![
+                tool(machine_id, 11, "황삭", 1),
+                tool(machine_id, 12, "정삭", 2),
+            ]
+        };
+        let tool_data = HashMap::from([(0, channels(0)), (1, channels(1)), (2, channels(2))]);
         let batch_size = HashMap::from([(0, 5), (1, 5), (2, 5)]);
         Self {
             gauge: GaugeConfig {
@@ -155,40 +180,81 @@ impl Default for AppConfig {
                     name: "Lathe #1 (OP-10)".to_string(),
                     ip: "192.168.0.145".to_string(),
                     port: 8193,
+                    parts_in_transit: 0,
+                    part_count_macro: 3901,
+                    part_count_path: 1,
+                    focas_timeout_ms: 3000,
                 },
                 MachineConfig {
                     id: 1,
                     name: "Lathe #1 (OP-10)".to_string(),
                     ip: "192.168.0.146".to_string(),
                     port: 8193,
+                    parts_in_transit: 0,
+                    part_count_macro: 3901,
+                    part_count_path: 1,
+                    focas_timeout_ms: 3000,
                 },
                 MachineConfig {
                     id: 2,
                     name: "Lathe #2 (OP-20)".to_string(),
                     ip: "192.168.0.147".to_string(),
                     port: 8193,
+                    parts_in_transit: 0,
+                    part_count_macro: 3901,
+                    part_count_path: 1,
+                    focas_timeout_ms: 3000,
                 },
             ],
             mapping: MappingConfig {
                 tool_data,
                 batch_size,
+                recipe: HashMap::new(),
             },
             admin: AdminConfig {
                 password: "admin123".to_string(),
+                approval_ttl_secs: 600,
             },
             log_path: "logs/log.db".to_string(),
             ui: UiConfig { font_size: 26 },
+            simulation: None,
+            recipes: Vec::new(),
         }
     }
 }
 
 impl AppConfig {
     pub fn load(path: &str) -> Self {
-        if let Ok(config_str) = fs::read_to_string(path) {
+        let mut config: Self = if let Ok(config_str) = fs::read_to_string(path) {
             serde_json::from_str(&config_str).unwrap_or_default()
         } else {
             Self::default()
+        };
+        config.mapping.fill_channel_defaults();
+        config
+    }
+
+    /// 한 기계(또는 레시피) 안에서 보정 채널 키 (계통, 공구 번호)가 겹치면 오류
+    pub fn validate(&self) -> anyhow::Result<()> {
+        for (machine_id, tools) in &self.mapping.tool_data {
+            if let Some(key) = duplicate_key(tools) {
+                anyhow::bail!(
+                    "Machine {} has more than one channel for {}",
+                    machine_id,
+                    key
+                );
+            }
+        }
+        for recipe in &self.recipes {
+            if let Some(key) = duplicate_key(&recipe.tools) {
+                anyhow::bail!(
+                    "Recipe '{}' has more than one channel for {}",
+                    recipe.name,
+                    key
+                );
+            }
         }
+        Ok(())
     }
 
     pub fn save(&self, path: &str) -> std::io::Result<()> {
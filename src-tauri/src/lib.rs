@@ -1,19 +1,45 @@
 use std::sync::{Arc, OnceLock};
+use std::time::Duration;
 use std::{collections::HashMap, sync::Mutex};
 
 use chrono::{DateTime, Utc};
 use focas_rs::FocasClient;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use tauri::{Manager, State};
 
-use crate::cnc::{update_offset_logs, write_offset_to_cnc, ToolData};
-use crate::logger::HistoryLogger;
+use crate::alarm::{AlarmLevel, AlarmLog, ToolLifeMonitor, APP_HANDLE};
+use crate::approval::{ApprovalQueue, PendingCorrection};
+use crate::backtest::{BacktestCandidate, BacktestReport};
+use crate::cnc::{
+    check_drift_limit, duplicate_key, find_tool, find_tool_mut, handle_tool_change,
+    log_unwritten_correction, reset_drift, spawn_part_counter, update_offset_logs,
+    write_offset_to_cnc, ChannelKey, OffsetRequest, ShadowLog, ToolChangeLog, ToolChangeReason,
+    ToolCountTracker, ToolData,
+};
+use crate::logger::{HistoryLogger, RawGaugeLog};
+use crate::recipe::Recipe;
+use crate::sim::{Simulation, SimulationReport};
+use crate::spc::{spawn_spc_monitor, SpcReport};
+use crate::tuning::{spawn_rate_tuner, RateSuggestion};
+use crate::worker::{CncBackend, FocasWorker};
 use crate::{cnc::spawn_cnc_loop, config::AppConfig, gauge::spawn_gauge_stream};
 
+pub mod alarm;
+pub mod approval;
+pub mod backtest;
 pub mod cnc;
 pub mod config;
 pub mod gauge;
 pub mod logger;
+pub mod outlier;
+pub mod recipe;
+pub mod sim;
+pub mod spc;
+pub mod stats;
+pub mod strategy;
+pub mod tuning;
+pub mod wear;
+pub mod worker;
 
 #[derive(Debug, Clone)]
 pub struct HexCommands {
@@ -25,24 +51,101 @@ pub struct HexCommands {
 static HEX_CMDS: OnceLock<HexCommands> = OnceLock::new();
 
 pub struct AppState {
-    pub handle_table: Arc<HashMap<u16, FocasClient>>,
-    pub tool_data: Arc<Mutex<HashMap<u16, (ToolData, ToolData)>>>,
+    pub handle_table: Arc<HashMap<u16, FocasWorker>>,
+    pub tool_data: Arc<Mutex<HashMap<u16, Vec<ToolData>>>>,
     pub batch_size: Arc<Mutex<HashMap<u16, usize>>>,
     pub ui_cache: Arc<Mutex<HashMap<u16, MachineUiState>>>,
     pub logger: HistoryLogger,
     pub password: String,
     pub font_size: u32,
+    pub approvals: ApprovalQueue,
+    pub simulation: Option<Simulation>,
 }
 
 #[derive(Debug, Serialize)]
 pub struct OffsetLog {
+    pub id: Option<i64>, // DB에서 읽은 기록만 Some
     pub timestamp: DateTime<Utc>,
     pub machine_id: u16,
+    pub path: i16,
     pub tool_num: i16,
     pub old_value: i32,
     pub change_amount: i32,
     pub new_value: i32,
-    pub success: bool,
+    pub success: bool,                    // CNC가 쓰기 명령을 받아들였는지
+    pub verified: bool,                   // 다시 읽은 값이 new_value와 일치하는지
+    pub decision: Option<OffsetDecision>, // 이전 버전 기록은 None
+    pub requested_change: Option<i32>,    // 한계 적용 전 계산된 보정량
+    pub undo_of: Option<i64>,             // 되돌리기 기록이면 원래 기록 id
+    pub rationale: Option<String>,        // 자동 보정의 계산 근거 (전략, 추정 치수)
+    pub recipe: Option<String>,           // 기록 당시 활성 레시피 (기록할 때 logger가 채움)
+}
+
+/// offset_history에 남기는 보정 결정
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OffsetDecision {
+    Applied,          // 자동 보정, 한계 이내
+    Clamped,          // 자동 보정, 한계로 잘라서 씀
+    Skipped,          // 한계 초과로 쓰지 않음
+    WithinDeadband,   // 보정량이 불감대 이내라 쓰지 않음
+    AwaitingApproval, // 작업자 승인 필요 (모의 운전 기록용)
+    Approved,         // 승인 후 씀
+    Rejected,         // 작업자가 거부
+    Expired,          // 승인 대기 시간 초과 또는 새 제안으로 대체
+    Undo,             // 이전 기록을 되돌림
+    Predictive,       // 마모율 예측 보정
+    Manual,           // 작업자가 직접 씀
+    External,         // CNC에서 직접 바뀐 값 감지
+    Restored,         // 검증 실패 후 되돌림
+}
+
+impl OffsetDecision {
+    /// 앱이 계산하거나 작업자가 앱에서 지시해 CNC에 쓴 보정
+    pub const APP_WRITES: [OffsetDecision; 5] = [
+        OffsetDecision::Applied,
+        OffsetDecision::Clamped,
+        OffsetDecision::Approved,
+        OffsetDecision::Predictive,
+        OffsetDecision::Manual,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OffsetDecision::Applied => "applied",
+            OffsetDecision::Clamped => "clamped",
+            OffsetDecision::Skipped => "skipped",
+            OffsetDecision::WithinDeadband => "within_deadband",
+            OffsetDecision::AwaitingApproval => "awaiting_approval",
+            OffsetDecision::Approved => "approved",
+            OffsetDecision::Rejected => "rejected",
+            OffsetDecision::Expired => "expired",
+            OffsetDecision::Undo => "undo",
+            OffsetDecision::Predictive => "predictive",
+            OffsetDecision::Manual => "manual",
+            OffsetDecision::External => "external",
+            OffsetDecision::Restored => "restored",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "applied" => Some(OffsetDecision::Applied),
+            "clamped" => Some(OffsetDecision::Clamped),
+            "skipped" => Some(OffsetDecision::Skipped),
+            "within_deadband" => Some(OffsetDecision::WithinDeadband),
+            "awaiting_approval" => Some(OffsetDecision::AwaitingApproval),
+            "approved" => Some(OffsetDecision::Approved),
+            "rejected" => Some(OffsetDecision::Rejected),
+            "expired" => Some(OffsetDecision::Expired),
+            "undo" => Some(OffsetDecision::Undo),
+            "predictive" => Some(OffsetDecision::Predictive),
+            "manual" => Some(OffsetDecision::Manual),
+            "external" => Some(OffsetDecision::External),
+            "restored" => Some(OffsetDecision::Restored),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug, serde::Serialize, Clone)]
@@ -53,14 +156,18 @@ pub struct ToolUiState {
     pub previous_offset: f64,
     pub life: i32,
     pub count: i32,
+    pub life_alarm: Option<AlarmLevel>,
+    pub in_cooldown: bool,
+    pub wear_rate: Option<f64>, // 예측 보정용 마모율 (mm/part)
+    pub rate_suggestion: Option<RateSuggestion>,
 }
 
 #[derive(Debug, Serialize, Clone)]
 pub struct MachineUiState {
     pub machine_id: u16,
-    pub upper_tool: ToolUiState, // 황삭 (Tuple의 0번)
-    pub lower_tool: ToolUiState, // 정삭 (Tuple의 1번)
+    pub tools: Vec<ToolUiState>, // 보정 채널 (설정 순서)
     pub batch_size: usize,
+    pub recipe: Option<String>, // 활성 레시피
 }
 
 #[tauri::command]
@@ -71,13 +178,14 @@ fn verify_password(input: String, state: State<'_, AppState>) -> bool {
 #[tauri::command]
 async fn get_offset_history(
     machine_id: u16,
+    path: i16,
     tool_num: i16,
     limit: u32,
     state: tauri::State<'_, AppState>,
 ) -> Result<Vec<OffsetLog>, String> {
     state
         .logger
-        .get_offset_history(machine_id, tool_num, limit)
+        .get_offset_history(machine_id, ChannelKey::new(path, tool_num), limit)
         .await
         .map_err(|e| e.to_string())
 }
@@ -85,15 +193,28 @@ async fn get_offset_history(
 #[tauri::command]
 async fn get_latest_offset_log(
     machine_id: u16,
+    path: i16,
     tool_num: i16,
     state: tauri::State<'_, AppState>,
 ) -> Result<OffsetLog, String> {
     state
         .logger
-        .get_latest_offset(machine_id, tool_num)
+        .get_latest_offset(machine_id, ChannelKey::new(path, tool_num))
         .ok_or("Failed to get latest offset log".to_string())
 }
 
+#[tauri::command]
+async fn get_alarm_history(
+    limit: u32,
+    state: tauri::State<'_, AppState>,
+) -> Result<Vec<AlarmLog>, String> {
+    state
+        .logger
+        .get_alarm_history(limit)
+        .await
+        .map_err(|e| e.to_string())
+}
+
 #[tauri::command]
 async fn get_all_machine_states(state: State<'_, AppState>) -> Result<Vec<MachineUiState>, String> {
     let cache = state.ui_cache.lock().unwrap();
@@ -102,13 +223,68 @@ async fn get_all_machine_states(state: State<'_, AppState>) -> Result<Vec<Machin
     Ok(results)
 }
 
+// UI 갱신용으로 공구마다 한 번에 읽는 값 (읽기 실패 시 -1)
+struct ToolReading {
+    offset: f64,
+    life: i32,
+    count: i32,
+}
+
+impl ToolReading {
+    const UNAVAILABLE: Self = Self {
+        offset: -1.0,
+        life: -1,
+        count: -1,
+    };
+
+    fn read(tool: &ToolData, client: &dyn CncBackend) -> Self {
+        let offset = tool
+            .read_offset(client)
+            .map(|o| o as f64 / 1000.0)
+            .unwrap_or_else(|e| {
+                eprintln!(
+                    "Failed to read offset for machine_id={}, tool_num={} - {}",
+                    tool.machine_id, tool.tool_num, e
+                );
+                -1.0
+            });
+        let life = match tool.read_life(client) {
+            Ok(life) => life,
+            Err(e) => {
+                eprintln!(
+                    "Failed to read life for machine_id={}, tool_num={} - {}",
+                    tool.machine_id, tool.tool_num, e
+                );
+                -1
+            }
+        };
+        let count = match tool.read_count(client) {
+            Ok(count) => count,
+            Err(e) => {
+                eprintln!(
+                    "Failed to read count for machine_id={}, tool_num={} - {}",
+                    tool.machine_id, tool.tool_num, e
+                );
+                -1
+            }
+        };
+        Self {
+            offset,
+            life,
+            count,
+        }
+    }
+}
+
 pub async fn update_ui_cache(
     ui_cache: Arc<Mutex<HashMap<u16, MachineUiState>>>,
-    handle_table: Arc<HashMap<u16, FocasClient>>,
-    tool_data: Arc<Mutex<HashMap<u16, (ToolData, ToolData)>>>,
+    handle_table: Arc<HashMap<u16, FocasWorker>>,
+    tool_data: Arc<Mutex<HashMap<u16, Vec<ToolData>>>>,
     batch_size: Arc<Mutex<HashMap<u16, usize>>>,
     logger: HistoryLogger,
 ) {
+    let mut life_monitor = ToolLifeMonitor::default();
+    let mut count_tracker = ToolCountTracker::default();
     loop {
         let (keys, tool_data_map, batch_size_map) = {
             let td_guard = tool_data.lock().unwrap();
@@ -122,99 +298,74 @@ pub async fn update_ui_cache(
         };
 
         for id in keys {
-            if let Some((upper, lower)) = tool_data_map.get(&id) {
+            if let Some(tools) = tool_data_map.get(&id) {
                 let batch_size = batch_size_map.get(&id).cloned().unwrap_or(5);
 
-                let upper_offset_prev = logger
-                    .get_latest_offset(id, upper.tool_num)
-                    .map_or(0.0, |log| log.new_value as f64 / 1000.0);
-                let lower_offset_prev = logger
-                    .get_latest_offset(id, lower.tool_num)
-                    .map_or(0.0, |log| log.new_value as f64 / 1000.0);
-
-                let client = handle_table
-                    .get(&id)
-                    .unwrap_or_else(|| panic!("No FocasClient found for machine ID {}", id));
-
-                let upper_offset = client
-                    .rdtofs(upper.tool_num, 0)
-                    .map(|o| o.data as f64 / 1000.0)
-                    .unwrap_or_else(|e| {
-                        eprintln!(
-                            "Failed to read offset for machine_id={}, tool_num={} - {}",
-                            id, upper.tool_num, e
-                        );
-                        -1.0
-                    });
-                let lower_offset = client
-                    .rdtofs(lower.tool_num, 0)
-                    .map(|o| o.data as f64 / 1000.0)
+                let Some(worker) = handle_table.get(&id) else {
+                    eprintln!("No FOCAS worker found for machine ID {}", id);
+                    continue;
+                };
+                let jobs = tools.clone();
+                let readings = worker
+                    .call(move |client| {
+                        Ok(jobs
+                            .iter()
+                            .map(|tool| ToolReading::read(tool, client))
+                            .collect::<Vec<_>>())
+                    })
+                    .await
                     .unwrap_or_else(|e| {
-                        eprintln!(
-                            "Failed to read offset for machine_id={}, tool_num={} - {}",
-                            id, upper.tool_num, e
-                        );
-                        -1.0
+                        eprintln!("Failed to read tool data for machine_id={} - {}", id, e);
+                        tools.iter().map(|_| ToolReading::UNAVAILABLE).collect()
                     });
-                let upper_life = match client.rdlife(upper.tool_num) {
-                    Ok(life) => life.data,
-                    Err(e) => {
-                        eprintln!(
-                            "Failed to read life for machine_id={}, tool_num={} - {}",
-                            id, upper.tool_num, e
-                        );
-                        -1
-                    }
-                };
-                let lower_life = match client.rdlife(lower.tool_num) {
-                    Ok(life) => life.data,
-                    Err(e) => {
-                        eprintln!(
-                            "Failed to read life for machine_id={}, tool_num={} - {}",
-                            id, lower.tool_num, e
-                        );
-                        -1
-                    }
-                };
-                let upper_count = match client.rdcount(upper.tool_num) {
-                    Ok(count) => count.data,
-                    Err(e) => {
-                        eprintln!(
-                            "Failed to read count for machine_id={}, tool_num={} - {}",
-                            id, upper.tool_num, e
-                        );
-                        -1
+
+                let mut tool_states = Vec::with_capacity(tools.len());
+                for (tool, reading) in tools.iter().zip(readings) {
+                    let ToolReading {
+                        offset,
+                        life,
+                        count,
+                    } = reading;
+                    life_monitor
+                        .check_and_raise(&logger, worker, tool, life, count)
+                        .await;
+                    if count_tracker.is_reset(tool, count) {
+                        if let Err(e) = handle_tool_change(
+                            &logger,
+                            &tool_data,
+                            id,
+                            tool.key(),
+                            ToolChangeReason::CountReset,
+                        ) {
+                            eprintln!("Tool change handling failed: {}", e);
+                        }
                     }
-                };
-                let lower_count = match client.rdcount(lower.tool_num) {
-                    Ok(count) => count.data,
-                    Err(e) => {
-                        eprintln!(
-                            "Failed to read count for machine_id={}, tool_num={} - {}",
-                            id, lower.tool_num, e
-                        );
-                        -1
+                    // 예측 보정은 공구 카운트로 마모율을 구하므로 보정 루프에 넘긴다
+                    if let Some(tool) =
+                        find_tool_mut(&mut tool_data.lock().unwrap(), id, tool.key())
+                    {
+                        tool.tool_count = (count >= 0).then_some(count as i64);
                     }
-                };
-                let upper_ui = ToolUiState {
-                    data: upper.clone(),
-                    current_offset: upper_offset,
-                    previous_offset: upper_offset_prev,
-                    life: upper_life,
-                    count: upper_count,
-                };
-                let lower_ui = ToolUiState {
-                    data: lower.clone(),
-                    current_offset: lower_offset,
-                    previous_offset: lower_offset_prev,
-                    life: lower_life,
-                    count: lower_count,
-                };
+                    let previous_offset = logger
+                        .get_latest_offset(id, tool.key())
+                        .map_or(0.0, |log| log.new_value as f64 / 1000.0);
+                    tool_states.push(ToolUiState {
+                        data: tool.clone(),
+                        current_offset: offset,
+                        previous_offset,
+                        life,
+                        count,
+                        life_alarm: ToolLifeMonitor::level(tool, life, count),
+                        in_cooldown: tool.in_cooldown(),
+                        wear_rate: tool.wear.rate,
+                        rate_suggestion: tool.rate_suggestion.clone(),
+                    });
+                }
                 let machine_state = MachineUiState {
                     machine_id: id,
-                    upper_tool: upper_ui,
-                    lower_tool: lower_ui,
+                    tools: tool_states,
                     batch_size,
+                    recipe: logger.active_recipe(id),
                 };
                 ui_cache.lock().unwrap().insert(id, machine_state);
             }
@@ -223,42 +374,54 @@ pub async fn update_ui_cache(
     }
 }
 
+/// update_tool_settings에서 바꿀 항목 (비운 항목은 그대로)
+#[derive(Debug, Deserialize)]
+pub struct ToolSettingsUpdate {
+    pub basic_size: Option<f64>,
+    pub manual_offset: Option<f64>,
+    pub offset_rate: Option<f64>,
+    pub active: Option<bool>,
+    pub tool_num: Option<i16>,
+}
+
 #[tauri::command]
 async fn update_tool_settings(
     machine_id: u16,
-    is_upper: bool, // true: 황삭, false: 정삭
-    basic_size: Option<f64>,
-    manual_offset: Option<f64>,
-    offset_rate: Option<f64>,
-    active: Option<bool>,
-    tool_num: Option<i16>,
+    path: i16,
+    tool_num: i16,
+    settings: ToolSettingsUpdate,
     state: State<'_, AppState>,
 ) -> Result<(), String> {
     {
         let mut tool_data_map = state.tool_data.lock().unwrap();
-
-        if let Some((upper, lower)) = tool_data_map.get_mut(&machine_id) {
-            if let Some(v) = basic_size {
-                upper.basic_size = v;
-                lower.basic_size = v;
-            }
-            if let Some(v) = manual_offset {
-                upper.manual_offset = v;
-                lower.manual_offset = v;
-            }
-
-            let target_tool = if is_upper { upper } else { lower };
-            if let Some(v) = offset_rate {
-                target_tool.offset_rate = v;
-            }
-            if let Some(v) = active {
-                target_tool.active = v;
-            }
-            if let Some(v) = tool_num {
-                target_tool.tool_num = v;
+        let tools = tool_data_map
+            .get_mut(&machine_id)
+            .ok_or_else(|| "Machine ID not found".to_string())?;
+        let key = ChannelKey::new(path, tool_num);
+        if let Some(new_tool_num) = settings.tool_num.filter(|&t| t != tool_num) {
+            let new_key = ChannelKey::new(path, new_tool_num);
+            if tools.iter().any(|tool| tool.key() == new_key) {
+                return Err(format!("Channel {} already exists", new_key));
             }
-        } else {
-            return Err("Machine ID not found".to_string());
+        }
+        let target_tool = tools
+            .iter_mut()
+            .find(|tool| tool.key() == key)
+            .ok_or_else(|| "Channel not found".to_string())?;
+        if let Some(v) = settings.basic_size {
+            target_tool.basic_size = v;
+        }
+        if let Some(v) = settings.manual_offset {
+            target_tool.manual_offset = v;
+        }
+        if let Some(v) = settings.offset_rate {
+            target_tool.offset_rate = v;
+        }
+        if let Some(v) = settings.active {
+            target_tool.active = v;
+        }
+        if let Some(v) = settings.tool_num {
+            target_tool.tool_num = v;
         }
     };
     let mut config = AppConfig::load("config.json");
@@ -293,46 +456,506 @@ async fn update_batch_size(
 #[tauri::command]
 async fn force_write_offset(
     machine_id: u16,
+    path: i16,
     tool_num: i16,
     offset_diff: i32,
     state: State<'_, AppState>,
 ) -> Result<(), String> {
-    {
-        let tool_data_map = state.tool_data.lock().unwrap();
-        if let Some((upper, lower)) = tool_data_map.get(&machine_id) {
-            let target_tool = if upper.tool_num == tool_num {
-                upper
-            } else if lower.tool_num == tool_num {
-                lower
-            } else {
-                return Err("Tool number not found for the specified machine".to_string());
-            };
-
-            let diff_mm = offset_diff as f64 / 1000.0;
-            if diff_mm > target_tool.max_limit || diff_mm < target_tool.min_limit {
-                return Err(format!(
-                    "Offset difference out of allowed range ({:.3}mm to {:.3}mm)",
-                    target_tool.min_limit, target_tool.max_limit
-                ));
-            }
-        }
+    let key = ChannelKey::new(path, tool_num);
+    let target_tool = find_tool(&state.tool_data, machine_id, key)
+        .ok_or_else(|| "Tool number not found for the specified machine".to_string())?;
+    let diff_mm = offset_diff as f64 / 1000.0;
+    if diff_mm > target_tool.max_limit || diff_mm < target_tool.min_limit {
+        return Err(format!(
+            "Offset difference out of allowed range ({:.3}mm to {:.3}mm)",
+            target_tool.min_limit, target_tool.max_limit
+        ));
     }
     write_offset_to_cnc(
         state.handle_table.clone(),
         state.logger.clone(),
         state.tool_data.clone(),
-        machine_id,
-        tool_num,
-        offset_diff,
+        OffsetRequest::new(machine_id, key, offset_diff, OffsetDecision::Manual),
     )
     .await
     .map_err(|e| e.to_string())?;
     println!(
-        "Force write offset: machine_id={}, tool_num={}, offset_diff={}",
-        machine_id, tool_num, offset_diff
+        "Force write offset: machine_id={}, tool={}, offset_diff={}",
+        machine_id, key, offset_diff
+    );
+    Ok(())
+}
+#[tauri::command]
+async fn undo_last_offset(
+    machine_id: u16,
+    path: i16,
+    tool_num: i16,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    let key = ChannelKey::new(path, tool_num);
+    let entry = state
+        .logger
+        .get_last_successful_offset(machine_id, key)
+        .await
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| "No offset change to undo".to_string())?;
+    let request = OffsetRequest::undo(&entry).map_err(|e| e.to_string())?;
+    println!(
+        "Undo offset change #{:?}: machine_id={}, tool={}, {} -> {}",
+        entry.id, machine_id, key, entry.new_value, entry.old_value
+    );
+    write_offset_to_cnc(
+        state.handle_table.clone(),
+        state.logger.clone(),
+        state.tool_data.clone(),
+        request,
+    )
+    .await
+    .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn mark_tool_change(
+    machine_id: u16,
+    path: i16,
+    tool_num: i16,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    let key = ChannelKey::new(path, tool_num);
+    handle_tool_change(
+        &state.logger,
+        &state.tool_data,
+        machine_id,
+        key,
+        ToolChangeReason::Operator,
+    )
+    .map_err(|e| e.to_string())
+}
+
+/// 누적 보정량 잠금을 풀고 자동 보정을 다시 켠다 (관리자, 공구 점검 후)
+#[tauri::command]
+async fn reset_tool_drift(
+    machine_id: u16,
+    path: i16,
+    tool_num: i16,
+    password: String,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    if password != state.password {
+        return Err("Invalid password".to_string());
+    }
+    let key = ChannelKey::new(path, tool_num);
+    reset_drift(&state.tool_data, machine_id, key).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn get_pending_approvals(state: State<'_, AppState>) -> Vec<PendingCorrection> {
+    state.approvals.list()
+}
+
+#[tauri::command]
+async fn approve_correction(
+    machine_id: u16,
+    path: i16,
+    tool_num: i16,
+    id: u64,
+    password: String,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    if password != state.password {
+        return Err("Invalid password".to_string());
+    }
+    let pending = state
+        .approvals
+        .take(machine_id, ChannelKey::new(path, tool_num), id)
+        .ok_or_else(|| format!("Approval #{} not found or expired", id))?;
+    println!(
+        "Approval #{} approved: machine_id={}, tool={}, offset_diff={}",
+        id,
+        pending.machine_id,
+        pending.key(),
+        pending.offset_diff
+    );
+    // 제안 이후 다른 보정으로 누적 한계에 걸렸을 수 있으므로 쓰기 직전에 다시 확인
+    if !check_drift_limit(
+        &state.logger,
+        &state.tool_data,
+        pending.machine_id,
+        pending.key(),
+        pending.offset_diff,
+    ) {
+        // 작업자가 거부한 것이 아니므로 건너뜀으로 남긴다
+        let mut request = pending.into_request(OffsetDecision::Skipped);
+        request.rationale = Some(
+            request.rationale.map_or_else(String::new, |r| r + "; ") + "approved but drift locked",
+        );
+        log_unwritten_correction(
+            &state.handle_table,
+            &state.logger,
+            &state.tool_data,
+            request,
+        )
+        .await
+        .map_err(|e| e.to_string())?;
+        return Err(
+            "Automatic compensation is locked by the drift limit. Inspect the tool and reset drift"
+                .to_string(),
+        );
+    }
+    write_offset_to_cnc(
+        state.handle_table.clone(),
+        state.logger.clone(),
+        state.tool_data.clone(),
+        pending.into_request(OffsetDecision::Approved),
+    )
+    .await
+    .map_err(|e| e.to_string())
+}
+
+/// 자동 조정이 제안한 보정률을 관리자 승인으로 적용한다.
+#[tauri::command]
+async fn apply_rate_suggestion(
+    machine_id: u16,
+    path: i16,
+    tool_num: i16,
+    password: String,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    if password != state.password {
+        return Err("Invalid password".to_string());
+    }
+    {
+        let mut tool_data_map = state.tool_data.lock().unwrap();
+        let key = ChannelKey::new(path, tool_num);
+        let tool = find_tool_mut(&mut tool_data_map, machine_id, key)
+            .ok_or_else(|| "Tool number not found for the specified machine".to_string())?;
+        let suggestion = tool
+            .rate_suggestion
+            .take()
+            .ok_or_else(|| "No offset rate suggestion for this tool".to_string())?;
+        // 화면 표시 단위(%)로 반올림
+        let rate = (suggestion.suggested_rate * 100.0).round() / 100.0;
+        println!(
+            "Offset rate for machine {}, tool {} changed {:.2} -> {:.2} (gain {:.3}, {} corrections)",
+            machine_id, key, tool.offset_rate, rate, suggestion.gain, suggestion.samples
+        );
+        tool.offset_rate = rate;
+    }
+    let mut config = AppConfig::load("config.json");
+    config.update_from_state(&state);
+    if let Err(e) = config.save("config.json") {
+        eprintln!("Config save failed: {}", e);
+    }
+    Ok(())
+}
+
+#[tauri::command]
+async fn reject_correction(
+    machine_id: u16,
+    path: i16,
+    tool_num: i16,
+    id: u64,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    let pending = state
+        .approvals
+        .take(machine_id, ChannelKey::new(path, tool_num), id)
+        .ok_or_else(|| format!("Approval #{} not found or expired", id))?;
+    println!(
+        "Approval #{} rejected: machine_id={}, tool={}",
+        id,
+        pending.machine_id,
+        pending.key()
     );
+    log_unwritten_correction(
+        &state.handle_table,
+        &state.logger,
+        &state.tool_data,
+        pending.into_request(OffsetDecision::Rejected),
+    )
+    .await
+    .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn get_shadow_history(
+    machine_id: u16,
+    path: i16,
+    tool_num: i16,
+    limit: u32,
+    state: State<'_, AppState>,
+) -> Result<Vec<ShadowLog>, String> {
+    state
+        .logger
+        .get_shadow_history(machine_id, ChannelKey::new(path, tool_num), limit)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn get_tool_change_history(
+    machine_id: u16,
+    limit: u32,
+    state: State<'_, AppState>,
+) -> Result<Vec<ToolChangeLog>, String> {
+    state
+        .logger
+        .get_tool_change_history(machine_id, limit)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn get_raw_gauge_logs(
+    machine_id: u16,
+    limit: u32,
+    state: State<'_, AppState>,
+) -> Result<Vec<RawGaugeLog>, String> {
+    state
+        .logger
+        .get_raw_gauge_logs(machine_id, limit)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+fn parse_time(t: Option<String>) -> Result<Option<DateTime<Utc>>, String> {
+    t.map(|t| {
+        DateTime::parse_from_rfc3339(&t)
+            .map(|t| t.with_timezone(&Utc))
+            .map_err(|e| format!("Invalid time {}: {}", t, e))
+    })
+    .transpose()
+}
+
+/// 기간(RFC3339, 생략 시 전체)의 측정값으로 SPC 보고서를 계산한다.
+#[tauri::command]
+async fn get_spc_report(
+    machine_id: u16,
+    path: i16,
+    tool_num: i16,
+    from: Option<String>,
+    to: Option<String>,
+    state: State<'_, AppState>,
+) -> Result<SpcReport, String> {
+    let tool = find_tool(
+        &state.tool_data,
+        machine_id,
+        ChannelKey::new(path, tool_num),
+    )
+    .ok_or_else(|| "Tool number not found for the specified machine".to_string())?;
+    let points = state
+        .logger
+        .get_gauge_points(
+            machine_id,
+            tool.feature,
+            parse_time(from)?,
+            parse_time(to)?,
+            None,
+        )
+        .await
+        .map_err(|e| e.to_string())?;
+    let values: Vec<f64> = points.iter().map(|p| p.value).collect();
+    Ok(SpcReport::compute(&values, &tool.spc))
+}
+
+/// 기간(RFC3339, 생략 시 전체)의 측정값을 후보 설정으로 다시 돌려 본다.
+#[tauri::command]
+async fn run_backtest(
+    machine_id: u16,
+    path: i16,
+    tool_num: i16,
+    from: Option<String>,
+    to: Option<String>,
+    candidate: BacktestCandidate,
+    state: State<'_, AppState>,
+) -> Result<BacktestReport, String> {
+    let key = ChannelKey::new(path, tool_num);
+    let tool = find_tool(&state.tool_data, machine_id, key)
+        .ok_or_else(|| "Tool number not found for the specified machine".to_string())?;
+    let (from, to) = (parse_time(from)?, parse_time(to)?);
+    let points = state
+        .logger
+        .get_gauge_points(machine_id, tool.feature, from, to, None)
+        .await
+        .map_err(|e| e.to_string())?;
+    let offsets = state
+        .logger
+        .get_offset_changes(machine_id, key, from, to)
+        .await
+        .map_err(|e| e.to_string())?;
+    let batch_size = *state
+        .batch_size
+        .lock()
+        .unwrap()
+        .get(&machine_id)
+        .unwrap_or(&5);
+    let transit = AppConfig::load("config.json")
+        .machines
+        .iter()
+        .find(|m| m.id as u16 == machine_id)
+        .map_or(0, |m| m.parts_in_transit as usize);
+    Ok(backtest::run(
+        &tool, batch_size, transit, &candidate, &points, &offsets,
+    ))
+}
+
+/// 시뮬레이션 모드의 부품 편차 통계 (시뮬레이션이 아니면 None)
+#[tauri::command]
+fn get_simulation_report(state: State<'_, AppState>) -> Option<SimulationReport> {
+    state.simulation.as_ref().map(|sim| sim.report())
+}
+
+#[tauri::command]
+fn list_recipes() -> Vec<Recipe> {
+    AppConfig::load("config.json").recipes
+}
+
+/// 기계의 현재 설정(보정 채널, 배치 크기)을 새 레시피로 저장한다 (관리자).
+#[tauri::command]
+async fn create_recipe(
+    machine_id: u16,
+    name: String,
+    part_number: String,
+    description: Option<String>,
+    password: String,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    if password != state.password {
+        return Err("Invalid password".to_string());
+    }
+    let mut config = AppConfig::load("config.json");
+    recipe::check_new_name(&config.recipes, &name).map_err(|e| e.to_string())?;
+    let new_recipe = {
+        let tool_data_map = state.tool_data.lock().unwrap();
+        let tools = tool_data_map
+            .get(&machine_id)
+            .ok_or_else(|| "Machine ID not found".to_string())?;
+        let batch_size = *state
+            .batch_size
+            .lock()
+            .unwrap()
+            .get(&machine_id)
+            .unwrap_or(&5);
+        Recipe::capture(
+            &name,
+            &part_number,
+            description.as_deref().unwrap_or_default(),
+            tools,
+            batch_size,
+        )
+    };
+    config.recipes.push(new_recipe);
+    config
+        .save("config.json")
+        .map_err(|e| format!("Config save failed: {}", e))?;
+    println!("Recipe '{}' created from machine {}", name, machine_id);
+    Ok(())
+}
+
+/// 저장된 레시피를 새 이름(과 품번)으로 복제한다 (관리자).
+#[tauri::command]
+async fn clone_recipe(
+    source: String,
+    name: String,
+    part_number: Option<String>,
+    password: String,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    if password != state.password {
+        return Err("Invalid password".to_string());
+    }
+    let mut config = AppConfig::load("config.json");
+    recipe::check_new_name(&config.recipes, &name).map_err(|e| e.to_string())?;
+    let mut new_recipe = recipe::find(&config.recipes, &source)
+        .map_err(|e| e.to_string())?
+        .clone();
+    new_recipe.name = name.clone();
+    if let Some(part_number) = part_number {
+        new_recipe.part_number = part_number;
+    }
+    config.recipes.push(new_recipe);
+    config
+        .save("config.json")
+        .map_err(|e| format!("Config save failed: {}", e))?;
+    println!("Recipe '{}' cloned from '{}'", name, source);
+    Ok(())
+}
+
+/// 기계에 레시피를 적용한다 (관리자). 이전 부품 기준으로 계산된 승인 대기 보정과
+/// 대기 중인 측정값은 버리고, 모든 공구를 교체 직후처럼 다시 시작한다.
+#[tauri::command]
+async fn activate_recipe(
+    machine_id: u16,
+    name: String,
+    password: String,
+    state: State<'_, AppState>,
+) -> Result<(), String> {
+    if password != state.password {
+        return Err("Invalid password".to_string());
+    }
+    let mut config = AppConfig::load("config.json");
+    let new_recipe = recipe::find(&config.recipes, &name)
+        .map_err(|e| e.to_string())?
+        .clone();
+    if !state.tool_data.lock().unwrap().contains_key(&machine_id) {
+        return Err("Machine ID not found".to_string());
+    }
+    if let Some(key) = duplicate_key(&new_recipe.tools) {
+        return Err(format!("Recipe has more than one channel for {}", key));
+    }
+
+    for pending in state.approvals.take_machine(machine_id) {
+        let id = pending.id;
+        if let Err(e) = log_unwritten_correction(
+            &state.handle_table,
+            &state.logger,
+            &state.tool_data,
+            pending.into_request(OffsetDecision::Expired),
+        )
+        .await
+        {
+            eprintln!("Failed to record expired approval #{}: {}", id, e);
+        }
+    }
+
+    let mut features: Vec<i32> = {
+        let mut tool_data_map = state.tool_data.lock().unwrap();
+        let previous = tool_data_map
+            .insert(machine_id, new_recipe.tools_for(machine_id))
+            .unwrap_or_default();
+        previous
+            .iter()
+            .chain(&new_recipe.tools)
+            .map(|tool| tool.feature)
+            .collect()
+    };
+    state
+        .batch_size
+        .lock()
+        .unwrap()
+        .insert(machine_id, new_recipe.batch_size);
+    features.sort_unstable();
+    features.dedup();
+    let discarded: usize = features
+        .into_iter()
+        .map(|feature| state.logger.discard_pending(machine_id, feature))
+        .sum();
+    state
+        .logger
+        .set_active_recipe(machine_id, Some(name.clone()));
+    println!(
+        "Recipe '{}' (part {}) activated on machine {}: discarded {} pending measurements",
+        name, new_recipe.part_number, machine_id, discarded
+    );
+
+    config.update_from_state(&state);
+    config.mapping.recipe.insert(machine_id, name);
+    if let Err(e) = config.save("config.json") {
+        eprintln!("Config save failed: {}", e);
+    }
     Ok(())
 }
+
 #[tauri::command]
 fn get_font_size(state: State<'_, AppState>) -> u32 {
     state.font_size
@@ -340,11 +963,30 @@ fn get_font_size(state: State<'_, AppState>) -> u32 {
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
+    let _ = env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn"))
+        .try_init();
     tauri::Builder::default()
         .setup(|app| {
             let config = AppConfig::load("config.json");
+            config.validate().expect("Invalid config.json");
+            APP_HANDLE.set(app.handle().clone()).unwrap_or_else(|_| {
+                panic!("Failed to set APP_HANDLE. This should never happen since it's only set once.")
+            });
+            let simulation = config
+                .simulation
+                .clone()
+                .map(|sim| Simulation::new(sim, &config.machines));
             let mut handle_table = HashMap::new();
             for machine in &config.machines {
+                let timeout = Duration::from_millis(machine.focas_timeout_ms);
+                if let Some(cnc) = simulation.as_ref().and_then(|sim| sim.cnc(machine.id as u16)) {
+                    println!("Using simulated CNC for {}", machine.name);
+                    handle_table.insert(
+                        machine.id as u16,
+                        FocasWorker::spawn(machine.id as u16, cnc, timeout),
+                    );
+                    continue;
+                }
                 let client_res = FocasClient::new(&machine.ip, machine.port as u16);
                 match client_res {
                     Ok(client) => {
@@ -352,7 +994,10 @@ pub fn run() {
                             "Connected to CNC {} at {}:{}",
                             machine.name, machine.ip, machine.port
                         );
-                        handle_table.insert(machine.id as u16, client);
+                        handle_table.insert(
+                            machine.id as u16,
+                            FocasWorker::spawn(machine.id as u16, client, timeout),
+                        );
                     }
                     Err(e) => {
                         println!(
@@ -375,6 +1020,9 @@ pub fn run() {
             });
             let handle_table = Arc::new(handle_table);
             let history_logger = HistoryLogger::new(&config.log_path);
+            for (&machine_id, name) in &config.mapping.recipe {
+                history_logger.set_active_recipe(machine_id, Some(name.clone()));
+            }
             let ui_cache = Arc::new(Mutex::new(HashMap::new()));
             let app_state = AppState {
                 handle_table: handle_table.clone(),
@@ -384,6 +1032,10 @@ pub fn run() {
                 password: config.admin.password.clone(),
                 font_size: config.ui.font_size,
                 ui_cache: ui_cache.clone(),
+                approvals: ApprovalQueue::new(Duration::from_secs(
+                    config.admin.approval_ttl_secs,
+                )),
+                simulation: simulation.clone(),
             };
             let ui_cache_clone = Arc::clone(&ui_cache);
             let handle_table_clone = Arc::clone(&app_state.handle_table);
@@ -403,12 +1055,14 @@ pub fn run() {
             let tool_data_clone = Arc::clone(&app_state.tool_data);
             let batch_size_clone = Arc::clone(&app_state.batch_size);
             let history_logger_clone = history_logger.clone();
+            let approvals_clone = app_state.approvals.clone();
             tauri::async_runtime::spawn(async move {
                 match spawn_cnc_loop(
                     handle_table_clone,
                     tool_data_clone,
                     batch_size_clone,
                     history_logger_clone,
+                    approvals_clone,
                 ) {
                     Ok(_) => println!("CNC loop exited gracefully"),
                     Err(e) => eprintln!("CNC loop encountered an error: {}", e),
@@ -422,7 +1076,48 @@ pub fn run() {
                 update_offset_logs(history_logger_clone, handle_table_clone, tool_data_clone).await;
             });
 
+            // 시뮬레이션은 가공하는 순간에 사이클을 기록한다 (배속 시 수량 확인 주기보다 빠름)
+            if simulation.is_none() {
+                let handle_table_clone = Arc::clone(&app_state.handle_table);
+                let history_logger_clone = history_logger.clone();
+                let machines = config.machines.clone();
+                tauri::async_runtime::spawn(async move {
+                    spawn_part_counter(history_logger_clone, handle_table_clone, machines).await;
+                });
+            }
+
+            let tool_data_clone = Arc::clone(&app_state.tool_data);
+            let history_logger_clone = history_logger.clone();
+            tauri::async_runtime::spawn(async move {
+                spawn_spc_monitor(history_logger_clone, tool_data_clone).await;
+            });
+
+            let tool_data_clone = Arc::clone(&app_state.tool_data);
+            let batch_size_clone = Arc::clone(&app_state.batch_size);
+            let history_logger_clone = history_logger.clone();
+            let machines = config.machines.clone();
+            tauri::async_runtime::spawn(async move {
+                spawn_rate_tuner(
+                    history_logger_clone,
+                    tool_data_clone,
+                    batch_size_clone,
+                    machines,
+                )
+                .await;
+            });
+
             let history_logger_clone = history_logger.clone();
+            if let Some(simulation) = simulation {
+                let tool_data_clone = Arc::clone(&app_state.tool_data);
+                let machines = config.machines.clone();
+                tauri::async_runtime::spawn(async move {
+                    simulation
+                        .run(history_logger_clone, tool_data_clone, machines)
+                        .await;
+                });
+                app.manage(app_state);
+                return Ok(());
+            }
             tauri::async_runtime::spawn(async move {
                 match spawn_gauge_stream(
                     &config.gauge.ip,
@@ -447,9 +1142,8 @@ pub fn run() {
                         .lock()
                         .unwrap()
                         .iter_mut()
-                        .for_each(|(_, (upper, lower))| {
-                            upper.active = false;
-                            lower.active = false;
+                        .for_each(|(_, tools)| {
+                            tools.iter_mut().for_each(|tool| tool.active = false);
                         });
                     config.update_from_state(&state);
                     if let Err(e) = config.save("config.json") {
@@ -462,10 +1156,28 @@ pub fn run() {
             verify_password,
             get_offset_history,
             get_latest_offset_log,
+            get_alarm_history,
             get_all_machine_states,
             update_tool_settings,
             update_batch_size,
             force_write_offset,
+            undo_last_offset,
+            mark_tool_change,
+            reset_tool_drift,
+            get_tool_change_history,
+            get_raw_gauge_logs,
+            get_spc_report,
+            run_backtest,
+            get_simulation_report,
+            get_shadow_history,
+            get_pending_approvals,
+            approve_correction,
+            apply_rate_suggestion,
+            reject_correction,
+            list_recipes,
+            create_recipe,
+            clone_recipe,
+            activate_recipe,
             get_font_size,
         ])
         .run(tauri::generate_context!())
@@ -1,7 +1,13 @@
+use std::collections::HashMap;
 use std::path::Path;
+use std::sync::{Arc, Mutex};
 
-use crate::{gauge::GaugeResponse, OffsetLog};
-use rusqlite::{params, Connection};
+use chrono::{DateTime, Utc};
+
+use crate::alarm::{AlarmKind, AlarmLevel, AlarmLog};
+use crate::cnc::{BatchEntry, ChannelKey, ShadowLog, ToolChangeLog};
+use crate::{gauge::GaugeResponse, OffsetDecision, OffsetLog};
+use rusqlite::{params, Connection, OptionalExtension};
 use serde::Serialize;
 
 #[derive(Debug, Serialize)]
@@ -11,12 +17,33 @@ pub struct RawGaugeLog {
     pub active_line: i32,
     pub tool_type: i32, // 1: 황삭, 2: 정삭
     pub measured_value: f64,
-    pub is_used: i32,
+    pub is_used: i32, // 0: 대기중, 1: 사용중, 2: 사용됨, 3: 제외됨, 4: 이상치
+    pub part_number: Option<i64>, // 사이클 종료 시점 가공 수량으로 추정한 부품 번호
+    pub reject_reason: Option<String>, // 이상치로 제외된 이유
+    pub recipe: Option<String>, // 기록 당시 활성 레시피
+}
+
+/// SPC 계산용 측정값 (mm)
+#[derive(Debug)]
+pub struct GaugePoint {
+    pub id: i64,
+    pub timestamp: String,
+    pub value: f64,
+}
+
+impl GaugePoint {
+    // gauge_raw_logs.timestamp는 SQLite CURRENT_TIMESTAMP (UTC) 형식
+    pub fn time(&self) -> Option<DateTime<Utc>> {
+        chrono::NaiveDateTime::parse_from_str(&self.timestamp, "%Y-%m-%d %H:%M:%S")
+            .ok()
+            .map(|t| t.and_utc())
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct HistoryLogger {
     db_path: String,
+    active_recipes: Arc<Mutex<HashMap<u16, String>>>, // machine_id -> 활성 레시피 (모든 기록에 남김)
 }
 
 impl HistoryLogger {
@@ -46,11 +73,27 @@ impl HistoryLogger {
                 old_value INTEGER NOT NULL,
                 change_amount INTEGER NOT NULL,
                 new_value INTEGER NOT NULL,
-                success BOOLEAN NOT NULL
+                success BOOLEAN NOT NULL,
+                verified BOOLEAN NOT NULL DEFAULT 0
             )",
             [],
         )
         .expect("Failed to create offset_history table");
+        add_column_if_missing(
+            &conn,
+            "offset_history",
+            "verified",
+            "BOOLEAN NOT NULL DEFAULT 0",
+        )
+        .expect("Failed to migrate offset_history table");
+        add_column_if_missing(&conn, "offset_history", "decision", "TEXT")
+            .expect("Failed to migrate offset_history table");
+        add_column_if_missing(&conn, "offset_history", "requested_change", "INTEGER")
+            .expect("Failed to migrate offset_history table");
+        add_column_if_missing(&conn, "offset_history", "undo_of", "INTEGER")
+            .expect("Failed to migrate offset_history table");
+        add_column_if_missing(&conn, "offset_history", "rationale", "TEXT")
+            .expect("Failed to migrate offset_history table");
 
         conn.execute(
             "CREATE TABLE IF NOT EXISTS gauge_raw_logs (
@@ -60,67 +103,248 @@ impl HistoryLogger {
                 machine_id INTEGER NOT NULL,   -- 0, 1... (내부 로직용)
                 tool_type INTEGER NOT NULL,    -- 1: 황삭(Value1), 2: 정삭(Value2)
                 measured_value REAL NOT NULL,
-                is_used INTEGER DEFAULT 0      -- 0: 미사용, 1: 사용됨
+                is_used INTEGER DEFAULT 0      -- 0: 미사용, 1: 사용됨, 2: 사용완료, 3: 제외, 4: 이상치
             )",
             [],
         )
         .expect("Failed to create gauge_raw_logs table");
-        Self { db_path: path }
+        add_column_if_missing(&conn, "gauge_raw_logs", "part_number", "INTEGER")
+            .expect("Failed to migrate gauge_raw_logs table");
+        add_column_if_missing(&conn, "gauge_raw_logs", "reject_reason", "TEXT")
+            .expect("Failed to migrate gauge_raw_logs table");
+
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS part_cycles (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                timestamp DATETIME DEFAULT CURRENT_TIMESTAMP,
+                machine_id INTEGER NOT NULL,
+                part_count INTEGER NOT NULL,  -- CNC 가공 수량
+                gauge_part INTEGER NOT NULL   -- 지금 게이지에 도착할 부품 번호 (part_count - 이송중 수량)
+            )",
+            [],
+        )
+        .expect("Failed to create part_cycles table");
+
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS alarm_history (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                timestamp TEXT NOT NULL,
+                machine_id INTEGER NOT NULL,
+                tool_num INTEGER NOT NULL,
+                kind TEXT NOT NULL,
+                level TEXT NOT NULL,
+                message TEXT NOT NULL
+            )",
+            [],
+        )
+        .expect("Failed to create alarm_history table");
+
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS tool_change_history (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                timestamp TEXT NOT NULL,
+                machine_id INTEGER NOT NULL,
+                tool_num INTEGER NOT NULL,
+                reason TEXT NOT NULL,
+                discarded INTEGER NOT NULL
+            )",
+            [],
+        )
+        .expect("Failed to create tool_change_history table");
+
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS shadow_history (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                timestamp TEXT NOT NULL,
+                machine_id INTEGER NOT NULL,
+                tool_num INTEGER NOT NULL,
+                avg_gauge REAL,
+                current_value INTEGER NOT NULL,
+                change_amount INTEGER NOT NULL,
+                requested_change INTEGER NOT NULL,
+                decision TEXT NOT NULL
+            )",
+            [],
+        )
+        .expect("Failed to create shadow_history table");
+        add_column_if_missing(&conn, "shadow_history", "rationale", "TEXT")
+            .expect("Failed to migrate shadow_history table");
+        for table in [
+            "offset_history",
+            "gauge_raw_logs",
+            "part_cycles",
+            "alarm_history",
+            "tool_change_history",
+            "shadow_history",
+        ] {
+            add_column_if_missing(&conn, table, "recipe", "TEXT")
+                .expect("Failed to add recipe column");
+        }
+        // 계통 (이전 기록은 1계통)
+        for table in [
+            "offset_history",
+            "alarm_history",
+            "tool_change_history",
+            "shadow_history",
+        ] {
+            add_column_if_missing(&conn, table, "path", "INTEGER NOT NULL DEFAULT 1")
+                .expect("Failed to add path column");
+        }
+        Self {
+            db_path: path,
+            active_recipes: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub fn set_active_recipe(&self, machine_id: u16, recipe: Option<String>) {
+        let mut active = self.active_recipes.lock().unwrap();
+        match recipe {
+            Some(name) => active.insert(machine_id, name),
+            None => active.remove(&machine_id),
+        };
+    }
+
+    pub fn active_recipe(&self, machine_id: u16) -> Option<String> {
+        self.active_recipes
+            .lock()
+            .unwrap()
+            .get(&machine_id)
+            .cloned()
     }
 
     pub fn log_offset(&self, log: OffsetLog) {
         let path = self.db_path.clone();
+        let recipe = self.active_recipe(log.machine_id);
+
+        tokio::task::spawn_blocking(move || {
+            if let Ok(conn) = Connection::open(path) {
+                let _ = insert_offset(&conn, &log, recipe);
+            }
+        });
+    }
+
+    pub fn log_alarm(&self, alarm: AlarmLog) {
+        let path = self.db_path.clone();
+        let recipe = self.active_recipe(alarm.machine_id);
 
         tokio::task::spawn_blocking(move || {
             if let Ok(conn) = Connection::open(path) {
                 let _ = conn.execute(
-                   "INSERT INTO offset_history (timestamp, machine_id, tool_num, old_value, change_amount, new_value, success) 
-                    VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                    "INSERT INTO alarm_history (timestamp, machine_id, tool_num, kind, level, message, recipe, path)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                    params![
+                        alarm.timestamp.to_rfc3339(),
+                        alarm.machine_id,
+                        alarm.tool_num,
+                        alarm.kind.as_str(),
+                        alarm.level.as_str(),
+                        alarm.message,
+                        recipe,
+                        alarm.path
+                    ],
+                );
+            }
+        });
+    }
+
+    pub async fn get_alarm_history(&self, limit: u32) -> anyhow::Result<Vec<AlarmLog>> {
+        let conn = Connection::open(&self.db_path)?;
+        tokio::task::spawn_blocking(move || {
+            let mut stmt = conn.prepare(
+                "SELECT timestamp, machine_id, tool_num, kind, level, message, path
+                 FROM alarm_history
+                 ORDER BY timestamp DESC
+                 LIMIT ?1",
+            )?;
+
+            let rows = stmt.query_map(params![limit], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, u16>(1)?,
+                    row.get::<_, i16>(2)?,
+                    row.get::<_, String>(3)?,
+                    row.get::<_, String>(4)?,
+                    row.get::<_, String>(5)?,
+                    row.get::<_, i16>(6)?,
+                ))
+            })?;
+
+            let mut history = Vec::new();
+            for row in rows.flatten() {
+                let (timestamp, machine_id, tool_num, kind, level, message, path) = row;
+                let (Some(kind), Some(level)) =
+                    (AlarmKind::parse(&kind), AlarmLevel::parse(&level))
+                else {
+                    continue;
+                };
+                history.push(AlarmLog {
+                    timestamp: chrono::DateTime::parse_from_rfc3339(&timestamp)?
+                        .with_timezone(&chrono::Utc),
+                    machine_id,
+                    path,
+                    tool_num,
+                    kind,
+                    level,
+                    message,
+                });
+            }
+            Ok(history)
+        })
+        .await?
+    }
+
+    pub fn log_tool_change(&self, log: ToolChangeLog) {
+        let path = self.db_path.clone();
+        let recipe = self.active_recipe(log.machine_id);
+
+        tokio::task::spawn_blocking(move || {
+            if let Ok(conn) = Connection::open(path) {
+                let _ = conn.execute(
+                    "INSERT INTO tool_change_history (timestamp, machine_id, tool_num, reason, discarded, recipe, path)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
                     params![
                         log.timestamp.to_rfc3339(),
                         log.machine_id,
                         log.tool_num,
-                        log.old_value,
-                        log.change_amount,
-                        log.new_value,
-                        log.success
+                        log.reason,
+                        log.discarded as i64,
+                        recipe,
+                        log.path
                     ],
                 );
             }
         });
     }
 
-    pub async fn get_offset_history(
+    pub async fn get_tool_change_history(
         &self,
         machine_id: u16,
-        tool_num: i16,
         limit: u32,
-    ) -> anyhow::Result<Vec<OffsetLog>> {
+    ) -> anyhow::Result<Vec<ToolChangeLog>> {
         let conn = Connection::open(&self.db_path)?;
         tokio::task::spawn_blocking(move || {
             let mut stmt = conn.prepare(
-                "SELECT timestamp, machine_id, tool_num, old_value, change_amount, new_value, success 
-                 FROM offset_history 
-                 WHERE machine_id = ?1 AND tool_num = ?2 
-                 ORDER BY timestamp DESC 
-                 LIMIT ?3"
+                "SELECT timestamp, machine_id, tool_num, reason, discarded, path
+                 FROM tool_change_history
+                 WHERE machine_id = ?1
+                 ORDER BY timestamp DESC
+                 LIMIT ?2",
             )?;
 
-            let rows = stmt.query_map(
-                params![machine_id, tool_num, limit],
-                |row| {
-                    Ok(OffsetLog {
-                        timestamp: chrono::DateTime::parse_from_rfc3339(
-                            row.get::<_, String>(0)?.as_str()
-                        ).unwrap().with_timezone(&chrono::Utc),
-                        machine_id: row.get(1)?,
-                        tool_num: row.get(2)?,
-                        old_value: row.get(3)?,
-                        change_amount: row.get(4)?,
-                        new_value: row.get(5)?,
-                        success: row.get(6)?,
-                    })
-                })?;
+            let rows = stmt.query_map(params![machine_id, limit], |row| {
+                Ok(ToolChangeLog {
+                    timestamp: chrono::DateTime::parse_from_rfc3339(
+                        row.get::<_, String>(0)?.as_str(),
+                    )
+                    .unwrap()
+                    .with_timezone(&chrono::Utc),
+                    machine_id: row.get(1)?,
+                    path: row.get(5)?,
+                    tool_num: row.get(2)?,
+                    reason: row.get(3)?,
+                    discarded: row.get::<_, i64>(4)? as usize,
+                })
+            })?;
 
             let mut history = Vec::new();
             for log in rows {
@@ -131,34 +355,195 @@ impl HistoryLogger {
         .await?
     }
 
-    pub fn get_latest_offset(&self, machine_id: u16, tool_num: i16) -> Option<OffsetLog> {
-        let conn = Connection::open(&self.db_path).ok()?;
-        let mut stmt = conn
-            .prepare(
-                "SELECT timestamp, machine_id, tool_num, old_value, change_amount, new_value, success 
-                 FROM offset_history 
-                 WHERE machine_id = ?1 AND tool_num = ?2 
-                 ORDER BY timestamp DESC 
-                 LIMIT 1",
-            )
-            .ok()?;
+    pub fn log_shadow(&self, log: ShadowLog) {
+        let path = self.db_path.clone();
+        let recipe = self.active_recipe(log.machine_id);
 
-        let log = stmt
-            .query_row(params![machine_id, tool_num], |row| {
-                Ok(OffsetLog {
+        tokio::task::spawn_blocking(move || {
+            if let Ok(conn) = Connection::open(path) {
+                let _ = conn.execute(
+                    "INSERT INTO shadow_history (timestamp, machine_id, tool_num, avg_gauge, current_value, change_amount, requested_change, decision, rationale, recipe, path)
+                     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+                    params![
+                        log.timestamp.to_rfc3339(),
+                        log.machine_id,
+                        log.tool_num,
+                        log.avg_gauge,
+                        log.current_value,
+                        log.change_amount,
+                        log.requested_change,
+                        log.decision.as_str(),
+                        log.rationale,
+                        recipe,
+                        log.path
+                    ],
+                );
+            }
+        });
+    }
+
+    pub async fn get_shadow_history(
+        &self,
+        machine_id: u16,
+        key: ChannelKey,
+        limit: u32,
+    ) -> anyhow::Result<Vec<ShadowLog>> {
+        let conn = Connection::open(&self.db_path)?;
+        tokio::task::spawn_blocking(move || {
+            let mut stmt = conn.prepare(
+                "SELECT timestamp, machine_id, tool_num, avg_gauge, current_value, change_amount, requested_change, decision, rationale, path
+                 FROM shadow_history
+                 WHERE machine_id = ?1 AND path = ?2 AND tool_num = ?3
+                 ORDER BY timestamp DESC
+                 LIMIT ?4",
+            )?;
+
+            let rows = stmt.query_map(params![machine_id, key.path, key.tool_num, limit], |row| {
+                let decision: String = row.get(7)?;
+                Ok(ShadowLog {
                     timestamp: chrono::DateTime::parse_from_rfc3339(
                         row.get::<_, String>(0)?.as_str(),
                     )
                     .unwrap()
                     .with_timezone(&chrono::Utc),
                     machine_id: row.get(1)?,
+                    path: row.get(9)?,
                     tool_num: row.get(2)?,
-                    old_value: row.get(3)?,
-                    change_amount: row.get(4)?,
-                    new_value: row.get(5)?,
-                    success: row.get(6)?,
+                    avg_gauge: row.get(3)?,
+                    current_value: row.get(4)?,
+                    change_amount: row.get(5)?,
+                    requested_change: row.get(6)?,
+                    decision: OffsetDecision::parse(&decision).unwrap_or(OffsetDecision::Applied),
+                    rationale: row.get(8)?,
                 })
-            })
+            })?;
+
+            let mut history = Vec::new();
+            for log in rows {
+                history.push(log?);
+            }
+            Ok(history)
+        })
+        .await?
+    }
+
+    pub async fn get_offset_history(
+        &self,
+        machine_id: u16,
+        key: ChannelKey,
+        limit: u32,
+    ) -> anyhow::Result<Vec<OffsetLog>> {
+        let conn = Connection::open(&self.db_path)?;
+        tokio::task::spawn_blocking(move || {
+            let mut stmt = conn.prepare(&format!(
+                "SELECT {} FROM offset_history
+                     WHERE machine_id = ?1 AND path = ?2 AND tool_num = ?3
+                     ORDER BY timestamp DESC
+                     LIMIT ?4",
+                OFFSET_COLUMNS
+            ))?;
+
+            let rows = stmt.query_map(
+                params![machine_id, key.path, key.tool_num, limit],
+                offset_log_from_row,
+            )?;
+
+            let mut history = Vec::new();
+            for log in rows {
+                history.push(log?);
+            }
+            Ok(history)
+        })
+        .await?
+    }
+
+    /// 기간 안에 CNC 값이 실제로 바뀐 옵셋 기록 (오래된 순).
+    /// 외부 변경, 복구, 되돌리기도 포함하므로 합이 CNC의 순변화와 같다.
+    pub async fn get_offset_changes(
+        &self,
+        machine_id: u16,
+        key: ChannelKey,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+    ) -> anyhow::Result<Vec<OffsetLog>> {
+        let db_path = self.db_path.clone();
+        let (from, to) = (from.map(|t| t.to_rfc3339()), to.map(|t| t.to_rfc3339()));
+        tokio::task::spawn_blocking(move || {
+            let conn = Connection::open(db_path)?;
+            let mut stmt = conn.prepare(&format!(
+                "SELECT {} FROM offset_history
+                     WHERE machine_id = ?1 AND path = ?2 AND tool_num = ?3
+                       AND success = 1 AND change_amount != 0
+                       AND (?4 IS NULL OR timestamp >= ?4)
+                       AND (?5 IS NULL OR timestamp <= ?5)
+                     ORDER BY timestamp ASC",
+                OFFSET_COLUMNS
+            ))?;
+
+            let rows = stmt.query_map(
+                params![machine_id, key.path, key.tool_num, from, to],
+                offset_log_from_row,
+            )?;
+
+            let mut history = Vec::new();
+            for log in rows {
+                history.push(log?);
+            }
+            Ok(history)
+        })
+        .await?
+    }
+
+    /// 되돌리기 대상: 앱이 써서 실제로 값이 바뀐 가장 최근의 성공 기록.
+    /// 외부 변경/복원/되돌리기 기록과 이미 되돌린 기록은 제외한다.
+    pub async fn get_last_successful_offset(
+        &self,
+        machine_id: u16,
+        key: ChannelKey,
+    ) -> anyhow::Result<Option<OffsetLog>> {
+        let conn = Connection::open(&self.db_path)?;
+        tokio::task::spawn_blocking(move || {
+            let mut stmt = conn.prepare(&format!(
+                "SELECT {} FROM offset_history
+                 WHERE machine_id = ?1 AND path = ?2 AND tool_num = ?3
+                   AND success = 1 AND new_value != old_value
+                   AND {}
+                   AND id NOT IN (
+                       SELECT undo_of FROM offset_history
+                       WHERE undo_of IS NOT NULL AND success = 1
+                   )
+                 ORDER BY timestamp DESC
+                 LIMIT 1",
+                OFFSET_COLUMNS,
+                app_writes_filter()
+            ))?;
+            Ok(stmt
+                .query_row(
+                    params![machine_id, key.path, key.tool_num],
+                    offset_log_from_row,
+                )
+                .optional()?)
+        })
+        .await?
+    }
+
+    pub fn get_latest_offset(&self, machine_id: u16, key: ChannelKey) -> Option<OffsetLog> {
+        let conn = Connection::open(&self.db_path).ok()?;
+        let mut stmt = conn
+            .prepare(&format!(
+                "SELECT {} FROM offset_history
+                 WHERE machine_id = ?1 AND path = ?2 AND tool_num = ?3
+                 ORDER BY timestamp DESC
+                 LIMIT 1",
+                OFFSET_COLUMNS
+            ))
+            .ok()?;
+
+        let log = stmt
+            .query_row(
+                params![machine_id, key.path, key.tool_num],
+                offset_log_from_row,
+            )
             .ok()?;
 
         Some(log)
@@ -166,30 +551,40 @@ impl HistoryLogger {
 
     pub fn insert_gauge_response(&self, res: GaugeResponse) {
         let path = self.db_path.clone();
+        // active_line은 1부터 시작하므로, machine_id는 -1 해줌
+        let machine_id = if res.active_line > 0 {
+            res.active_line - 1
+        } else {
+            0
+        };
+        let recipe = self.active_recipe(machine_id);
 
         tokio::task::spawn_blocking(move || {
             if let Ok(mut conn) = Connection::open(path) {
                 let tx = conn.transaction();
                 if let Ok(tx) = tx {
-                    // active_line은 1부터 시작하므로, machine_id는 -1 해줌
-                    let machine_id = if res.active_line > 0 {
-                        res.active_line - 1
-                    } else {
-                        0
-                    };
+                    // 마지막 사이클 종료 기록에서 게이지에 도착한 부품 번호 추정 (이송중 수량 반영)
+                    let part_number: Option<i64> = tx
+                        .query_row(
+                            "SELECT gauge_part FROM part_cycles
+                             WHERE machine_id = ?1 ORDER BY id DESC LIMIT 1",
+                            params![machine_id],
+                            |row| row.get(0),
+                        )
+                        .ok();
 
                     // 1. 황삭 데이터 (Value 1) -> tool_type: 1
                     let _ = tx.execute(
-                        "INSERT INTO gauge_raw_logs (active_line, machine_id, tool_type, measured_value, is_used) 
-                         VALUES (?1, ?2, 1, ?3, 0)",
-                        params![res.active_line, machine_id, res.value1],
+                        "INSERT INTO gauge_raw_logs (active_line, machine_id, tool_type, measured_value, is_used, part_number, recipe) 
+                         VALUES (?1, ?2, 1, ?3, 0, ?4, ?5)",
+                        params![res.active_line, machine_id, res.value1, part_number, recipe],
                     );
 
                     // 2. 정삭 데이터 (Value 2) -> tool_type: 2
                     let _ = tx.execute(
-                        "INSERT INTO gauge_raw_logs (active_line, machine_id, tool_type, measured_value, is_used) 
-                         VALUES (?1, ?2, 2, ?3, 0)",
-                        params![res.active_line, machine_id, res.value2],
+                        "INSERT INTO gauge_raw_logs (active_line, machine_id, tool_type, measured_value, is_used, part_number, recipe) 
+                         VALUES (?1, ?2, 2, ?3, 0, ?4, ?5)",
+                        params![res.active_line, machine_id, res.value2, part_number, recipe],
                     );
 
                     let _ = tx.commit(); // 둘 다 성공해야 저장
@@ -198,7 +593,39 @@ impl HistoryLogger {
         });
     }
 
-    pub fn fetch_and_process_batch(&self, machine_id: u16, batch_size: usize) -> Option<Vec<i32>> {
+    /// 마지막으로 기록된 가공 수량
+    pub fn last_part_count(&self, machine_id: u16) -> Option<i64> {
+        let conn = Connection::open(&self.db_path).ok()?;
+        conn.query_row(
+            "SELECT part_count FROM part_cycles WHERE machine_id = ?1 ORDER BY id DESC LIMIT 1",
+            params![machine_id],
+            |row| row.get(0),
+        )
+        .ok()
+    }
+
+    pub fn log_part_cycle(&self, machine_id: u16, part_count: i64, gauge_part: i64) {
+        let path = self.db_path.clone();
+        let recipe = self.active_recipe(machine_id);
+
+        tokio::task::spawn_blocking(move || {
+            if let Ok(conn) = Connection::open(path) {
+                let _ = conn.execute(
+                    "INSERT INTO part_cycles (machine_id, part_count, gauge_part, recipe) VALUES (?1, ?2, ?3, ?4)",
+                    params![machine_id, part_count, gauge_part, recipe],
+                );
+            }
+        });
+    }
+
+    /// 한 측정 항목(tool_type)의 미사용 측정값이 batch_size만큼 모였으면 꺼내서 사용 처리.
+    /// (id, 측정값, 부품 번호) 목록을 반환한다.
+    pub fn fetch_and_process_batch(
+        &self,
+        machine_id: u16,
+        tool_type: i32,
+        batch_size: usize,
+    ) -> Option<Vec<BatchEntry>> {
         let mut conn = Connection::open(&self.db_path).ok()?;
         let tx = conn.transaction().ok()?;
 
@@ -206,15 +633,19 @@ impl HistoryLogger {
             // 미사용 데이터 조회 (오래된 순)
             let mut stmt = tx
                 .prepare(
-                    "SELECT id, measured_value FROM gauge_raw_logs 
-                 WHERE machine_id = ?1 AND is_used = 0 
+                    "SELECT id, measured_value, part_number FROM gauge_raw_logs 
+                 WHERE machine_id = ?1 AND tool_type = ?2 AND is_used = 0 
                  ORDER BY timestamp ASC",
                 )
                 .ok()?;
 
             let rows = stmt
-                .query_map(rusqlite::params![machine_id], |row| {
-                    Ok((row.get::<_, i32>(0)?, row.get::<_, i32>(1)?))
+                .query_map(rusqlite::params![machine_id, tool_type], |row| {
+                    Ok((
+                        row.get::<_, i32>(0)?,
+                        row.get::<_, i32>(1)?,
+                        row.get::<_, Option<i64>>(2)?,
+                    ))
                 })
                 .ok()?;
 
@@ -223,7 +654,7 @@ impl HistoryLogger {
 
             for row in rows.flatten() {
                 ids.push(row.0);
-                values.push(row.1);
+                values.push((row.0, row.1, row.2));
             }
 
             drop(stmt);
@@ -235,8 +666,9 @@ impl HistoryLogger {
                 let target_values = values[0..batch_size].to_vec();
 
                 let _ = tx.execute(
-                    "UPDATE gauge_raw_logs SET is_used = 2 WHERE machine_id = ?1 AND is_used = 1",
-                    params![machine_id],
+                    "UPDATE gauge_raw_logs SET is_used = 2
+                     WHERE machine_id = ?1 AND tool_type = ?2 AND is_used = 1",
+                    params![machine_id, tool_type],
                 );
                 // 사용 처리 (Update)
                 // rusqlite는 배열 바인딩이 복잡하므로 단순 루프로 처리 (배치 사이즈가 작으므로 성능 영향 미미)
@@ -256,15 +688,126 @@ impl HistoryLogger {
         None
     }
 
+    /// 배치에서 이상치로 판정된 측정값을 사유와 함께 표시한다.
+    pub fn mark_rejected(&self, rejected: Vec<(i32, String)>) {
+        if rejected.is_empty() {
+            return;
+        }
+        let Ok(mut conn) = Connection::open(&self.db_path) else {
+            return;
+        };
+        let Ok(tx) = conn.transaction() else {
+            return;
+        };
+        for (id, reason) in &rejected {
+            let _ = tx.execute(
+                "UPDATE gauge_raw_logs SET is_used = 4, reject_reason = ?2 WHERE id = ?1",
+                params![id, reason],
+            );
+        }
+        let _ = tx.commit();
+    }
+
+    /// 해당 공구의 대기중 측정값을 모두 제외 처리하고 개수를 반환
+    pub fn discard_pending(&self, machine_id: u16, tool_type: i32) -> usize {
+        let Ok(conn) = Connection::open(&self.db_path) else {
+            return 0;
+        };
+        conn.execute(
+            "UPDATE gauge_raw_logs SET is_used = 3
+             WHERE machine_id = ?1 AND tool_type = ?2 AND is_used = 0",
+            params![machine_id, tool_type],
+        )
+        .unwrap_or(0)
+    }
+
+    /// 대기중 측정값의 마지막 id와 개수. 대기중인 측정값이 없으면 None
+    pub fn last_pending(&self, machine_id: u16, tool_type: i32) -> Option<(i32, usize)> {
+        let conn = Connection::open(&self.db_path).ok()?;
+        conn.query_row(
+            "SELECT MAX(id), COUNT(*) FROM gauge_raw_logs
+             WHERE machine_id = ?1 AND tool_type = ?2 AND is_used = 0",
+            params![machine_id, tool_type],
+            |row| {
+                Ok(row
+                    .get::<_, Option<i32>>(0)?
+                    .map(|last| (last, row.get::<_, i64>(1).unwrap_or(0) as usize)))
+            },
+        )
+        .ok()
+        .flatten()
+    }
+
+    /// 대기중 측정값 중 오래된 것부터 최대 limit개를 제외 처리하고 개수를 반환
+    pub fn skip_pending(&self, machine_id: u16, tool_type: i32, limit: usize) -> usize {
+        let Ok(conn) = Connection::open(&self.db_path) else {
+            return 0;
+        };
+        conn.execute(
+            "UPDATE gauge_raw_logs SET is_used = 3
+             WHERE id IN (
+                 SELECT id FROM gauge_raw_logs
+                 WHERE machine_id = ?1 AND tool_type = ?2 AND is_used = 0
+                 ORDER BY timestamp ASC
+                 LIMIT ?3
+             )",
+            params![machine_id, tool_type, limit as i64],
+        )
+        .unwrap_or(0)
+    }
+
+    /// 한 측정 항목의 측정값을 시간순으로 읽는다. from/to로 기간을 정하고,
+    /// limit이 있으면 그 중 최근 limit개만 읽는다.
+    pub async fn get_gauge_points(
+        &self,
+        machine_id: u16,
+        tool_type: i32,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+        limit: Option<u32>,
+    ) -> anyhow::Result<Vec<GaugePoint>> {
+        let db_path = self.db_path.clone();
+        // gauge_raw_logs.timestamp는 SQLite CURRENT_TIMESTAMP (UTC) 형식
+        let format = |t: DateTime<Utc>| t.format("%Y-%m-%d %H:%M:%S").to_string();
+        let (from, to) = (from.map(format), to.map(format));
+        tokio::task::spawn_blocking(move || {
+            let conn = Connection::open(db_path)?;
+            let mut stmt = conn.prepare(
+                "SELECT id, timestamp, measured_value FROM gauge_raw_logs
+                 WHERE machine_id = ?1 AND tool_type = ?2
+                   AND (?3 IS NULL OR timestamp >= ?3)
+                   AND (?4 IS NULL OR timestamp <= ?4)
+                 ORDER BY id DESC LIMIT ?5",
+            )?;
+            let limit = limit.map_or(-1, |l| l as i64);
+            let rows = stmt.query_map(params![machine_id, tool_type, from, to, limit], |row| {
+                Ok(GaugePoint {
+                    id: row.get(0)?,
+                    timestamp: row.get(1)?,
+                    value: row.get::<_, f64>(2)? / 10000.0,
+                })
+            })?;
+
+            let mut points = Vec::new();
+            for point in rows {
+                points.push(point?);
+            }
+            points.reverse();
+            Ok(points)
+        })
+        .await?
+    }
+
     pub async fn get_raw_gauge_logs(
-        db_path: String,
+        &self,
         machine_id: u16,
         limit: u32,
     ) -> anyhow::Result<Vec<RawGaugeLog>> {
+        let db_path = self.db_path.clone();
         tokio::task::spawn_blocking(move || {
             let conn = Connection::open(db_path)?;
             let mut stmt = conn.prepare(
-                "SELECT id, timestamp, active_line, tool_type, measured_value, is_used 
+                "SELECT id, timestamp, active_line, tool_type, measured_value, is_used, part_number, reject_reason, recipe 
                  FROM gauge_raw_logs 
                  WHERE machine_id = ?1 
                  ORDER BY timestamp DESC LIMIT ?2",
@@ -278,6 +821,9 @@ impl HistoryLogger {
                     tool_type: row.get(3)?,
                     measured_value: row.get(4)?,
                     is_used: row.get::<_, i32>(5)?,
+                    part_number: row.get(6)?,
+                    reject_reason: row.get(7)?,
+                    recipe: row.get(8)?,
                 })
             })?;
 
@@ -290,3 +836,231 @@ impl HistoryLogger {
         .await?
     }
 }
+
+// 기존 DB 파일에 새 컬럼을 추가 (CREATE TABLE IF NOT EXISTS는 컬럼을 늘리지 않음)
+fn add_column_if_missing(
+    conn: &Connection,
+    table: &str,
+    column: &str,
+    definition: &str,
+) -> rusqlite::Result<()> {
+    let mut stmt = conn.prepare(&format!("PRAGMA table_info({})", table))?;
+    let exists = stmt
+        .query_map([], |row| row.get::<_, String>(1))?
+        .flatten()
+        .any(|name| name == column);
+    if !exists {
+        conn.execute(
+            &format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition),
+            [],
+        )?;
+    }
+    Ok(())
+}
+
+const OFFSET_COLUMNS: &str =
+    "id, timestamp, machine_id, tool_num, old_value, change_amount, new_value, \
+     success, verified, decision, requested_change, undo_of, rationale, recipe, path";
+
+fn insert_offset(
+    conn: &Connection,
+    log: &OffsetLog,
+    recipe: Option<String>,
+) -> rusqlite::Result<usize> {
+    conn.execute(
+        "INSERT INTO offset_history (timestamp, machine_id, tool_num, old_value, change_amount, new_value, success, verified, decision, requested_change, undo_of, rationale, recipe, path) 
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)",
+        params![
+            log.timestamp.to_rfc3339(),
+            log.machine_id,
+            log.tool_num,
+            log.old_value,
+            log.change_amount,
+            log.new_value,
+            log.success,
+            log.verified,
+            log.decision.map(|d| d.as_str()),
+            log.requested_change,
+            log.undo_of,
+            log.rationale,
+            recipe,
+            log.path
+        ],
+    )
+}
+
+/// 앱이 결정해서 쓴 보정만 고르는 조건 (외부 변경, 복원, 되돌리기 제외)
+fn app_writes_filter() -> String {
+    let decisions: Vec<String> = OffsetDecision::APP_WRITES
+        .iter()
+        .map(|d| format!("'{}'", d.as_str()))
+        .collect();
+    format!("decision IN ({})", decisions.join(", "))
+}
+
+fn offset_log_from_row(row: &rusqlite::Row) -> rusqlite::Result<OffsetLog> {
+    Ok(OffsetLog {
+        id: row.get(0)?,
+        timestamp: chrono::DateTime::parse_from_rfc3339(row.get::<_, String>(1)?.as_str())
+            .unwrap()
+            .with_timezone(&chrono::Utc),
+        machine_id: row.get(2)?,
+        path: row.get(14)?,
+        tool_num: row.get(3)?,
+        old_value: row.get(4)?,
+        change_amount: row.get(5)?,
+        new_value: row.get(6)?,
+        success: row.get(7)?,
+        verified: row.get(8)?,
+        decision: row
+            .get::<_, Option<String>>(9)?
+            .and_then(|d| OffsetDecision::parse(&d)),
+        requested_change: row.get(10)?,
+        undo_of: row.get(11)?,
+        rationale: row.get(12)?,
+        recipe: row.get(13)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_logger(name: &str) -> HistoryLogger {
+        let path = std::env::temp_dir().join(format!("{}_{}.db", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        HistoryLogger::new(path.to_str().unwrap())
+    }
+
+    fn offset(
+        seq: i64,
+        decision: OffsetDecision,
+        old: i32,
+        new: i32,
+        undo_of: Option<i64>,
+    ) -> OffsetLog {
+        OffsetLog {
+            id: None,
+            timestamp: Utc::now() + chrono::Duration::seconds(seq),
+            machine_id: 0,
+            path: 1,
+            tool_num: 11,
+            old_value: old,
+            change_amount: new - old,
+            new_value: new,
+            success: true,
+            verified: true,
+            decision: Some(decision),
+            requested_change: None,
+            undo_of,
+            rationale: None,
+            recipe: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_repeated_undo() {
+        let logger = test_logger("test_repeated_undo");
+        let conn = Connection::open(&logger.db_path).unwrap();
+        let key = ChannelKey::new(1, 11);
+        let last = || async {
+            logger
+                .get_last_successful_offset(0, key)
+                .await
+                .unwrap()
+                .and_then(|l| l.id)
+        };
+
+        insert_offset(
+            &conn,
+            &offset(1, OffsetDecision::Applied, 0, 10, None),
+            None,
+        )
+        .unwrap();
+        insert_offset(
+            &conn,
+            &offset(2, OffsetDecision::Manual, 10, 15, None),
+            None,
+        )
+        .unwrap();
+        // 앱이 쓴 값을 옵셋 감시가 다시 기록한 외부 변경은 되돌리기 대상이 아니다
+        insert_offset(
+            &conn,
+            &offset(3, OffsetDecision::External, 10, 15, None),
+            None,
+        )
+        .unwrap();
+        assert_eq!(last().await, Some(2));
+
+        insert_offset(
+            &conn,
+            &offset(4, OffsetDecision::Undo, 15, 10, Some(2)),
+            None,
+        )
+        .unwrap();
+        assert_eq!(last().await, Some(1));
+
+        insert_offset(
+            &conn,
+            &offset(5, OffsetDecision::Undo, 10, 0, Some(1)),
+            None,
+        )
+        .unwrap();
+        assert_eq!(last().await, None);
+    }
+
+    #[tokio::test]
+    async fn test_offset_changes_all_sources() {
+        let logger = test_logger("test_offset_changes_all_sources");
+        let conn = Connection::open(&logger.db_path).unwrap();
+        let key = ChannelKey::new(1, 11);
+
+        insert_offset(
+            &conn,
+            &offset(1, OffsetDecision::Applied, 0, 10, None),
+            None,
+        )
+        .unwrap();
+        // 조작반에서 작업자가 바꾼 값
+        insert_offset(
+            &conn,
+            &offset(2, OffsetDecision::External, 10, 15, None),
+            None,
+        )
+        .unwrap();
+        insert_offset(
+            &conn,
+            &offset(3, OffsetDecision::Clamped, 15, 25, None),
+            None,
+        )
+        .unwrap();
+        insert_offset(
+            &conn,
+            &offset(4, OffsetDecision::Undo, 25, 15, Some(3)),
+            None,
+        )
+        .unwrap();
+        // 값이 바뀌지 않은 기록은 제외
+        insert_offset(
+            &conn,
+            &offset(5, OffsetDecision::Skipped, 15, 15, None),
+            None,
+        )
+        .unwrap();
+
+        let changes = logger.get_offset_changes(0, key, None, None).await.unwrap();
+        let decisions: Vec<_> = changes.iter().map(|l| l.decision).collect();
+        assert_eq!(
+            decisions,
+            vec![
+                Some(OffsetDecision::Applied),
+                Some(OffsetDecision::External),
+                Some(OffsetDecision::Clamped),
+                Some(OffsetDecision::Undo)
+            ]
+        );
+        // 되돌린 보정은 되돌리기 기록과 상쇄되어 CNC의 순변화와 같다
+        let net: i32 = changes.iter().map(|l| l.change_amount).sum();
+        assert_eq!(net, 15);
+    }
+}
@@ -0,0 +1,197 @@
+use serde::{Deserialize, Serialize};
+
+use crate::stats;
+
+/// 양끝 제외 후 남아야 하는 최소 측정 수. 이보다 적게 남으면 제외하지 않는다.
+const MIN_REMAINING: usize = 3;
+
+/// 배치에서 이상치를 제외하는 규칙 (config.json의 "outlier_rule")
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "rule", rename_all = "snake_case")]
+pub enum OutlierRule {
+    /// 양쪽 끝에서 count개씩 제외 (남는 값이 MIN_REMAINING개보다 적으면 제외하지 않음)
+    Trim { count: usize },
+    /// 양쪽 끝에서 percent%씩 제외
+    TrimPercent { percent: f64 },
+    /// 사분위 범위 밖 [Q1 - k * IQR, Q3 + k * IQR] 제외
+    Iqr { k: f64 },
+    /// 중앙값에서 k * MAD(정규 환산) 이상 떨어진 값 제외 (Hampel)
+    Hampel { k: f64 },
+    /// Grubbs 검정을 반복해 유의수준 alpha로 하나씩 제외
+    Grubbs { alpha: f64 },
+    /// 제외하지 않음 (기본값. 양끝 제외는 배치 크기에 맞춰 설정에서 고른다)
+    #[default]
+    None,
+}
+
+impl OutlierRule {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OutlierRule::Trim { .. } => "trim",
+            OutlierRule::TrimPercent { .. } => "trim_percent",
+            OutlierRule::Iqr { .. } => "iqr",
+            OutlierRule::Hampel { .. } => "hampel",
+            OutlierRule::Grubbs { .. } => "grubbs",
+            OutlierRule::None => "none",
+        }
+    }
+
+    /// 값마다 제외 사유를 돌려준다 (None이면 사용). 결과 길이는 values와 같다.
+    pub fn reject(&self, values: &[i32]) -> Vec<Option<String>> {
+        let mut reasons = vec![None; values.len()];
+        let x: Vec<f64> = values.iter().map(|&v| v as f64).collect();
+        match *self {
+            OutlierRule::Trim { count } => trim(&x, count, &mut reasons),
+            OutlierRule::TrimPercent { percent } => {
+                let count = (x.len() as f64 * percent.clamp(0.0, 50.0) / 100.0).floor() as usize;
+                trim(&x, count, &mut reasons)
+            }
+            OutlierRule::Iqr { k } => {
+                let mut sorted = x.clone();
+                sorted.sort_by(|a, b| a.total_cmp(b));
+                let (Some(q1), Some(q3)) = (
+                    stats::quantile_sorted(&sorted, 0.25),
+                    stats::quantile_sorted(&sorted, 0.75),
+                ) else {
+                    return reasons;
+                };
+                let (lo, hi) = (q1 - k * (q3 - q1), q3 + k * (q3 - q1));
+                for (i, &v) in x.iter().enumerate() {
+                    if v < lo || v > hi {
+                        reasons[i] = Some(format!("iqr: outside [{}, {}]", lo, hi));
+                    }
+                }
+            }
+            OutlierRule::Hampel { k } => {
+                let Some(med) = stats::median(&x) else {
+                    return reasons;
+                };
+                let deviations: Vec<f64> = x.iter().map(|v| (v - med).abs()).collect();
+                // 정규분포 기준 표준편차로 환산
+                let mad = stats::median(&deviations).unwrap_or(0.0) * 1.4826;
+                if mad > 0.0 {
+                    for (i, d) in deviations.iter().enumerate() {
+                        if *d > k * mad {
+                            reasons[i] = Some(format!(
+                                "hampel: |x - {}| = {} > {} x MAD {:.1}",
+                                med, d, k, mad
+                            ));
+                        }
+                    }
+                }
+            }
+            OutlierRule::Grubbs { alpha } => {
+                let mut remaining: Vec<usize> = (0..x.len()).collect();
+                while remaining.len() >= 3 {
+                    let sample: Vec<f64> = remaining.iter().map(|&i| x[i]).collect();
+                    let (Some(m), Some(s)) = (stats::mean(&sample), stats::std_dev(&sample)) else {
+                        break;
+                    };
+                    if s == 0.0 {
+                        break;
+                    }
+                    let (pos, g) = sample.iter().map(|v| (v - m).abs() / s).enumerate().fold(
+                        (0, 0.0),
+                        |best, (j, g)| if g > best.1 { (j, g) } else { best },
+                    );
+                    let critical = grubbs_critical(sample.len(), alpha);
+                    if g <= critical {
+                        break;
+                    }
+                    let index = remaining.remove(pos);
+                    reasons[index] = Some(format!("grubbs: G = {:.2} > {:.2}", g, critical));
+                }
+            }
+            OutlierRule::None => {}
+        }
+        reasons
+    }
+}
+
+fn trim(x: &[f64], count: usize, reasons: &mut [Option<String>]) {
+    if count == 0 || x.len() < count * 2 + MIN_REMAINING {
+        return;
+    }
+    let mut order: Vec<usize> = (0..x.len()).collect();
+    order.sort_by(|&a, &b| x[a].total_cmp(&x[b]));
+    for &i in order[..count].iter().chain(&order[x.len() - count..]) {
+        reasons[i] = Some(format!("trim: {} lowest/highest of {}", count, x.len()));
+    }
+}
+
+/// 양측 Grubbs 검정 임계값
+fn grubbs_critical(n: usize, alpha: f64) -> f64 {
+    let n = n as f64;
+    let t = stats::t_quantile(1.0 - alpha / (2.0 * n), n - 2.0);
+    (n - 1.0) / n.sqrt() * (t * t / (n - 2.0 + t * t)).sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rejected(rule: &OutlierRule, values: &[i32]) -> Vec<usize> {
+        rule.reject(values)
+            .iter()
+            .enumerate()
+            .filter_map(|(i, r)| r.as_ref().map(|_| i))
+            .collect()
+    }
+
+    #[test]
+    fn test_default_keeps_all() {
+        assert_eq!(OutlierRule::default(), OutlierRule::None);
+        assert!(rejected(&OutlierRule::default(), &[100, 101, 99, 100, 150]).is_empty());
+    }
+
+    #[test]
+    fn test_trim() {
+        let batch = [103, 100, 99, 150, 101];
+        assert_eq!(
+            rejected(&OutlierRule::Trim { count: 1 }, &batch),
+            vec![2, 3]
+        );
+        // 5개에서 양끝 2개씩 빼면 1개만 남으므로 제외하지 않는다
+        assert!(rejected(&OutlierRule::Trim { count: 2 }, &batch).is_empty());
+        assert_eq!(
+            rejected(&OutlierRule::Trim { count: 2 }, &[5, 1, 4, 2, 3, 7, 6]),
+            vec![1, 3, 5, 6]
+        );
+        assert!(rejected(&OutlierRule::Trim { count: 0 }, &batch).is_empty());
+        assert!(rejected(&OutlierRule::Trim { count: 1 }, &[]).is_empty());
+        // 같은 값이 여러 개여도 한쪽에서 count개씩만 제외
+        assert_eq!(
+            rejected(&OutlierRule::Trim { count: 1 }, &[100; 5]).len(),
+            2
+        );
+        assert_eq!(
+            rejected(&OutlierRule::TrimPercent { percent: 20.0 }, &batch),
+            vec![2, 3]
+        );
+    }
+
+    #[test]
+    fn test_grubbs() {
+        let rule = OutlierRule::Grubbs { alpha: 0.05 };
+        assert_eq!(rejected(&rule, &[100, 101, 99, 100, 150]), vec![4]);
+        assert!(rejected(&rule, &[100, 101, 99, 100, 102]).is_empty());
+        // 모두 같은 값이면 표준편차가 0이라 검정하지 않는다
+        assert!(rejected(&rule, &[100; 5]).is_empty());
+    }
+
+    #[test]
+    fn test_small_batches() {
+        let rules = [
+            OutlierRule::Trim { count: 1 },
+            OutlierRule::TrimPercent { percent: 50.0 },
+            OutlierRule::Iqr { k: 1.5 },
+            OutlierRule::Hampel { k: 3.0 },
+            OutlierRule::Grubbs { alpha: 0.05 },
+        ];
+        for rule in &rules {
+            assert!(rule.reject(&[]).is_empty(), "{}", rule.as_str());
+            assert!(rejected(rule, &[100]).is_empty(), "{}", rule.as_str());
+            assert!(rejected(rule, &[100, 200]).is_empty(), "{}", rule.as_str());
+        }
+    }
+}
@@ -0,0 +1,99 @@
+use anyhow::{anyhow, bail};
+use serde::{Deserialize, Serialize};
+
+use crate::cnc::ToolData;
+
+/// 부품(품번)별 설정 묶음. 기계에서 활성화하면 그 기계의 보정 채널과 배치 크기를
+/// 이 값으로 바꾼다.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Recipe {
+    pub name: String,
+    pub part_number: String, // 부품 품번
+    #[serde(default)]
+    pub description: String,
+    pub batch_size: usize,
+    pub tools: Vec<ToolData>, // 보정 채널 (기준 치수, 한계, 공차, 전략, 공구 번호)
+}
+
+impl Recipe {
+    /// 기계의 현재 설정으로 레시피를 만든다. 런타임 상태와 자동 보정 켜짐은 남기지 않는다.
+    pub fn capture(
+        name: &str,
+        part_number: &str,
+        description: &str,
+        tools: &[ToolData],
+        batch_size: usize,
+    ) -> Self {
+        let tools = tools
+            .iter()
+            .map(|tool| {
+                let mut tool = tool.clone();
+                tool.reset_state();
+                tool.rate_suggestion = None;
+                tool.active = false;
+                tool
+            })
+            .collect();
+        Self {
+            name: name.to_string(),
+            part_number: part_number.to_string(),
+            description: description.to_string(),
+            batch_size,
+            tools,
+        }
+    }
+
+    /// 기계에 적용할 보정 채널. 부품이 바뀌었으므로 공구 교체 직후처럼 시작한다.
+    pub fn tools_for(&self, machine_id: u16) -> Vec<ToolData> {
+        self.tools
+            .iter()
+            .map(|tool| {
+                let mut tool = tool.clone();
+                tool.machine_id = machine_id;
+                tool.reset_state();
+                tool.rate_suggestion = None;
+                // 새 부품의 첫 측정을 확인할 때까지 자동 보정은 꺼 둔다
+                tool.active = false;
+                tool
+            })
+            .collect()
+    }
+}
+
+pub fn find<'a>(recipes: &'a [Recipe], name: &str) -> anyhow::Result<&'a Recipe> {
+    recipes
+        .iter()
+        .find(|r| r.name == name)
+        .ok_or_else(|| anyhow!("Recipe '{}' not found", name))
+}
+
+/// 새 레시피 이름 확인: 비어 있거나 이미 있으면 오류
+pub fn check_new_name(recipes: &[Recipe], name: &str) -> anyhow::Result<()> {
+    if name.trim().is_empty() {
+        bail!("Recipe name is empty");
+    }
+    if recipes.iter().any(|r| r.name == name) {
+        bail!("Recipe '{}' already exists", name);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::AppConfig;
+
+    #[test]
+    fn test_recipe_tools_inactive() {
+        let mut tool = AppConfig::default().mapping.tool_data[&0][0].clone();
+        tool.active = true;
+        let mut recipe = Recipe::capture("A", "P-1", "", &[tool.clone()], 5);
+        assert!(!recipe.tools[0].active);
+
+        // config.json에 켜진 채로 저장된 레시피도 켜진 채로 적용하지 않는다
+        recipe.tools[0].active = true;
+        let tools = recipe.tools_for(1);
+        assert_eq!(tools[0].machine_id, 1);
+        assert!(!tools[0].active);
+    }
+}
@@ -0,0 +1,378 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::cnc::{macro_to_micron, OffsetTarget, ToolData};
+use crate::config::MachineConfig;
+use crate::gauge::GaugeResponse;
+use crate::logger::HistoryLogger;
+use crate::stats;
+use crate::worker::CncBackend;
+
+/// config.json의 "simulation". 있으면 실제 CNC와 게이지 대신 시뮬레이션으로 돈다.
+/// 보정 루프(GaugeBatches, write_offset_to_cnc)는 그대로 시뮬레이션 CNC에 연결된다.
+/// 기록은 log_path에 남으므로 실제 기록과 다른 파일을 쓰는 것이 좋다.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SimulationConfig {
+    #[serde(default = "default_cycle_secs")]
+    pub cycle_secs: f64, // 부품 1개 가공 시간 (시뮬레이션 시간)
+    #[serde(default = "default_speedup")]
+    pub speedup: f64, // 시간 배속
+    #[serde(default)]
+    pub initial_error: f64, // 시작 시 치수 오차 (mm)
+    #[serde(default = "default_wear_rate")]
+    pub wear_rate: f64, // 공구 마모로 인한 치수 증가 (mm/part)
+    #[serde(default)]
+    pub thermal_drift: f64, // 열변위 포화값 (mm)
+    #[serde(default = "default_thermal_tau_parts")]
+    pub thermal_tau_parts: f64, // 열변위 시상수 (부품 수)
+    #[serde(default = "default_noise")]
+    pub noise: f64, // 측정 잡음 표준편차 (mm)
+    #[serde(default)]
+    pub tool_life_parts: u32, // 이 수량마다 공구 교체 (0: 교체 안 함)
+    #[serde(default)]
+    pub parts: u64, // 이 수량을 가공하면 멈춤 (0: 계속)
+    #[serde(default)]
+    pub seed: u64,
+}
+
+fn default_cycle_secs() -> f64 {
+    30.0
+}
+
+fn default_speedup() -> f64 {
+    30.0
+}
+
+fn default_wear_rate() -> f64 {
+    0.0002
+}
+
+fn default_thermal_tau_parts() -> f64 {
+    50.0
+}
+
+fn default_noise() -> f64 {
+    0.001
+}
+
+// 가공 한 사이클의 최소 실제 시간. 게이지 기록이 사이클 기록보다 늦게 저장되도록 둘로 나눠 쓴다.
+const MIN_CYCLE: Duration = Duration::from_millis(200);
+
+#[derive(Default)]
+struct CncState {
+    path: i16,
+    offsets: HashMap<(i16, i16), i32>, // (계통, 공구) -> 옵셋 (µm)
+    macros: HashMap<(i16, i16), (i32, i16)>, // (계통, 번호) -> (값, 소수 자리수)
+    counts: HashMap<(i16, i16), i32>,  // (계통, 공구) -> 공구 카운트
+    tool_life: i32,
+    part_count: i64,
+    offset_writes: u64,
+}
+
+impl CncState {
+    /// 이 채널 보정 대상의 현재 값 (µm). 워커 작업의 계통 선택과 섞이지 않도록 계통을 직접 지정
+    fn offset_of(&self, tool: &ToolData) -> i32 {
+        match tool.target {
+            OffsetTarget::ToolOffset => {
+                *self.offsets.get(&(tool.path, tool.tool_num)).unwrap_or(&0)
+            }
+            OffsetTarget::MacroVariable { number } => self
+                .macros
+                .get(&(tool.path, number))
+                .map_or(0, |&(value, dec_val)| macro_to_micron(value, dec_val)),
+        }
+    }
+}
+
+/// 옵셋 테이블, 공구 카운트, 가공 수량 매크로만 가진 CNC
+#[derive(Clone, Default)]
+pub struct SimulatedCnc {
+    state: Arc<Mutex<CncState>>,
+}
+
+impl CncBackend for SimulatedCnc {
+    fn set_path(&self, path: i16) -> anyhow::Result<()> {
+        self.state.lock().unwrap().path = path;
+        Ok(())
+    }
+
+    fn read_tool_offset(&self, tool_num: i16) -> anyhow::Result<i32> {
+        let state = self.state.lock().unwrap();
+        Ok(*state.offsets.get(&(state.path, tool_num)).unwrap_or(&0))
+    }
+
+    fn write_tool_offset(&self, tool_num: i16, value: i32) -> anyhow::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let path = state.path;
+        state.offsets.insert((path, tool_num), value);
+        state.offset_writes += 1;
+        Ok(())
+    }
+
+    fn read_macro(&self, number: i16) -> anyhow::Result<(i32, i16)> {
+        let state = self.state.lock().unwrap();
+        Ok(*state.macros.get(&(state.path, number)).unwrap_or(&(0, 0)))
+    }
+
+    fn write_macro(&self, number: i16, value: i32, dec_val: i16) -> anyhow::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let path = state.path;
+        state.macros.insert((path, number), (value, dec_val));
+        state.offset_writes += 1;
+        Ok(())
+    }
+
+    fn read_tool_life(&self, _tool_num: i16) -> anyhow::Result<i32> {
+        Ok(self.state.lock().unwrap().tool_life)
+    }
+
+    fn read_tool_count(&self, tool_num: i16) -> anyhow::Result<i32> {
+        let state = self.state.lock().unwrap();
+        Ok(*state.counts.get(&(state.path, tool_num)).unwrap_or(&0))
+    }
+
+    fn write_pmc(&self, adr_type: i16, address: u16, data: &[u8]) -> anyhow::Result<()> {
+        println!(
+            "Simulated PMC write: type {}, address {}, data {:?}",
+            adr_type, address, data
+        );
+        Ok(())
+    }
+}
+
+/// 시뮬레이션 중 측정 항목 하나의 편차 통계 (mm, 기준 치수 대비 실제 치수)
+#[derive(Debug, Serialize)]
+pub struct FeatureDeviation {
+    pub machine_id: u16,
+    pub feature: i32,
+    pub parts: usize,
+    pub mean: Option<f64>,
+    pub std_dev: Option<f64>,
+    pub max_abs: Option<f64>,
+    pub last: Option<f64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SimulationReport {
+    pub parts: u64,
+    pub offset_writes: u64,
+    pub features: Vec<FeatureDeviation>,
+}
+
+// (기계, 측정 항목) -> 부품별 편차 (mm)
+type Deviations = HashMap<(u16, i32), Vec<f64>>;
+
+/// 기계별 시뮬레이션 CNC와, 가공한 부품을 이송 지연 뒤에 재는 시뮬레이션 게이지
+#[derive(Clone)]
+pub struct Simulation {
+    config: SimulationConfig,
+    machines: Arc<HashMap<u16, SimulatedCnc>>,
+    deviations: Arc<Mutex<Deviations>>,
+}
+
+impl Simulation {
+    pub fn new(config: SimulationConfig, machines: &[MachineConfig]) -> Self {
+        let machines = machines
+            .iter()
+            .map(|machine| {
+                let cnc = SimulatedCnc::default();
+                cnc.state.lock().unwrap().tool_life = config.tool_life_parts as i32;
+                (machine.id as u16, cnc)
+            })
+            .collect();
+        Self {
+            config,
+            machines: Arc::new(machines),
+            deviations: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub fn cnc(&self, machine_id: u16) -> Option<SimulatedCnc> {
+        self.machines.get(&machine_id).cloned()
+    }
+
+    pub fn report(&self) -> SimulationReport {
+        let deviations = self.deviations.lock().unwrap();
+        let mut features: Vec<FeatureDeviation> = deviations
+            .iter()
+            .map(|(&(machine_id, feature), values)| FeatureDeviation {
+                machine_id,
+                feature,
+                parts: values.len(),
+                mean: stats::mean(values),
+                std_dev: stats::std_dev(values),
+                max_abs: values.iter().map(|v| v.abs()).reduce(f64::max),
+                last: values.last().copied(),
+            })
+            .collect();
+        features.sort_by_key(|f| (f.machine_id, f.feature));
+        let (mut parts, mut offset_writes) = (0, 0);
+        for cnc in self.machines.values() {
+            let state = cnc.state.lock().unwrap();
+            parts += state.part_count as u64;
+            offset_writes += state.offset_writes;
+        }
+        SimulationReport {
+            parts,
+            offset_writes,
+            features,
+        }
+    }
+
+    /// 모든 기계에서 사이클마다 부품 1개를 가공하고, parts_in_transit개 앞서 가공한
+    /// 부품을 게이지 측정값으로 기록한다. 실제 치수는
+    /// 기준 치수 + 시작 오차 + 마모율 x 공구 카운트 + 열변위 + 옵셋이고, 측정값은 여기에 잡음을 더한 값이다.
+    pub async fn run(
+        self,
+        logger: HistoryLogger,
+        tool_data: Arc<Mutex<HashMap<u16, Vec<ToolData>>>>,
+        machines: Vec<MachineConfig>,
+    ) {
+        let config = &self.config;
+        let cycle =
+            Duration::from_secs_f64(config.cycle_secs / config.speedup.max(1.0)).max(MIN_CYCLE);
+        println!(
+            "Simulation started: {} machines, {:?} per part",
+            machines.len(),
+            cycle
+        );
+        let mut rng = Rng::new(config.seed);
+        let mut in_transit: HashMap<u16, VecDeque<(i32, i32)>> = HashMap::new();
+        let mut parts = 0;
+        while config.parts == 0 || parts < config.parts {
+            parts += 1;
+            let snapshot = tool_data.lock().unwrap().clone();
+            let mut measured = Vec::new();
+            for machine in &machines {
+                let machine_id = machine.id as u16;
+                let (Some(cnc), Some(tools)) =
+                    (self.machines.get(&machine_id), snapshot.get(&machine_id))
+                else {
+                    continue;
+                };
+                let sizes = self.machine_part(cnc, machine, tools, parts);
+                // 1/10000mm 단위 게이지 값 (Value1: 1번 항목, Value2: 2번 항목)
+                let mut gauge = |feature: i32| {
+                    sizes.get(&feature).map_or(0, |size| {
+                        ((size + config.noise * rng.normal()) * 10000.0).round() as i32
+                    })
+                };
+                let values = (gauge(1), gauge(2));
+                let count = cnc.state.lock().unwrap().part_count;
+                logger.log_part_cycle(machine_id, count, count - machine.parts_in_transit as i64);
+                let queue = in_transit.entry(machine_id).or_default();
+                queue.push_back(values);
+                if queue.len() > machine.parts_in_transit as usize {
+                    measured.extend(queue.pop_front().map(|values| (machine_id, values)));
+                }
+            }
+            tokio::time::sleep(cycle / 2).await;
+            for (machine_id, (value1, value2)) in measured {
+                logger.insert_gauge_response(GaugeResponse {
+                    active_line: machine_id + 1,
+                    raw_data: "simulated".to_string(),
+                    plc_data_on: true,
+                    value1,
+                    value2,
+                });
+            }
+            tokio::time::sleep(cycle / 2).await;
+            if parts % 100 == 0 {
+                self.print_report();
+            }
+        }
+        println!("Simulation finished after {} parts", parts);
+        self.print_report();
+    }
+
+    /// 부품 1개 가공: 가공 수량과 공구 카운트를 올리고 측정 항목별 실제 치수(mm)를 반환
+    fn machine_part(
+        &self,
+        cnc: &SimulatedCnc,
+        machine: &MachineConfig,
+        tools: &[ToolData],
+        parts: u64,
+    ) -> HashMap<i32, f64> {
+        let config = &self.config;
+        let mut state = cnc.state.lock().unwrap();
+        state.part_count += 1;
+        let count = state.part_count as i32;
+        state.macros.insert(
+            (machine.part_count_path, machine.part_count_macro),
+            (count, 0),
+        );
+        let thermal = config.thermal_drift
+            * (1.0 - (-(parts as f64) / config.thermal_tau_parts.max(1.0)).exp());
+        let mut sizes: HashMap<i32, f64> = HashMap::new();
+        for tool in tools {
+            let key = (tool.path, tool.tool_num);
+            let tool_count = state.counts.entry(key).or_insert(0);
+            if config.tool_life_parts > 0 && *tool_count >= config.tool_life_parts as i32 {
+                *tool_count = 0;
+            }
+            *tool_count += 1;
+            let wear = config.wear_rate * *tool_count as f64;
+            let offset = state.offset_of(tool) as f64 / 1000.0;
+            // 같은 항목을 여러 채널이 따르면 옵셋과 마모가 모두 치수에 더해진다
+            *sizes
+                .entry(tool.feature)
+                .or_insert(tool.basic_size + config.initial_error + thermal) += wear + offset;
+        }
+        let mut deviations = self.deviations.lock().unwrap();
+        for (&feature, &size) in &sizes {
+            let basic_size = tools
+                .iter()
+                .find(|tool| tool.feature == feature)
+                .map_or(0.0, |tool| tool.basic_size);
+            deviations
+                .entry((machine.id as u16, feature))
+                .or_default()
+                .push(size - basic_size);
+        }
+        sizes
+    }
+
+    fn print_report(&self) {
+        let report = self.report();
+        println!(
+            "Simulation: {} parts, {} offset writes",
+            report.parts, report.offset_writes
+        );
+        for f in report.features {
+            println!(
+                "  machine {} feature {}: mean {:+.4}, std {:.4}, max |dev| {:.4}",
+                f.machine_id,
+                f.feature,
+                f.mean.unwrap_or(0.0),
+                f.std_dev.unwrap_or(0.0),
+                f.max_abs.unwrap_or(0.0)
+            );
+        }
+    }
+}
+
+// 측정 잡음용 xorshift64* (재현 가능하도록 seed 고정)
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Self(seed ^ 0x9E37_79B9_7F4A_7C15)
+    }
+
+    fn next_f64(&mut self) -> f64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        (self.0.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// 표준정규분포 (Box-Muller)
+    fn normal(&mut self) -> f64 {
+        let u1 = self.next_f64().max(f64::MIN_POSITIVE);
+        let u2 = self.next_f64();
+        (-2.0 * u1.ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos()
+    }
+}
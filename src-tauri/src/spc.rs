@@ -0,0 +1,559 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+
+use crate::alarm::{raise_alarm, AlarmKind, AlarmLevel, AlarmLog};
+use crate::cnc::{ChannelKey, ToolData};
+use crate::logger::HistoryLogger;
+use crate::stats;
+
+/// 공구별 SPC 설정 (config.json의 "spc")
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SpcConfig {
+    #[serde(default)]
+    pub tolerance: Option<Tolerance>, // 없으면 공정능력을 계산하지 않음
+    #[serde(default = "default_subgroup_size")]
+    pub subgroup_size: usize, // X̄/R 관리도 부분군 크기 (2~10)
+    #[serde(default)]
+    pub rules: RuleSet,
+    #[serde(default = "default_alarm_window")]
+    pub alarm_window: u32, // 알람 판정에 쓸 최근 측정 수 (0: 알람 사용 안 함)
+}
+
+fn default_subgroup_size() -> usize {
+    5
+}
+
+fn default_alarm_window() -> u32 {
+    100
+}
+
+impl Default for SpcConfig {
+    fn default() -> Self {
+        Self {
+            tolerance: None,
+            subgroup_size: default_subgroup_size(),
+            rules: RuleSet::default(),
+            alarm_window: default_alarm_window(),
+        }
+    }
+}
+
+/// 규격 하한/상한 (mm)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Tolerance {
+    pub lower: f64,
+    pub upper: f64,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RuleSet {
+    #[default]
+    WesternElectric,
+    Nelson,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct ControlLimits {
+    pub center: f64,
+    pub ucl: f64,
+    pub lcl: f64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct XbarRChart {
+    pub subgroup_size: usize,
+    pub means: Vec<f64>,
+    pub ranges: Vec<f64>,
+    pub xbar: ControlLimits,
+    pub range: ControlLimits,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ImrChart {
+    pub values: Vec<f64>,
+    pub moving_ranges: Vec<f64>,
+    pub individuals: ControlLimits,
+    pub moving_range: ControlLimits,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Capability {
+    pub sigma_within: f64,  // R̄/d2 (없으면 MR̄/d2)
+    pub sigma_overall: f64, // 표본 표준편차
+    pub cp: Option<f64>,
+    pub cpk: Option<f64>,
+    pub pp: Option<f64>,
+    pub ppk: Option<f64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Chart {
+    Individuals,
+    Xbar,
+}
+
+/// 런 규칙 위반. index는 관리도의 점 번호 (규칙 창의 마지막 점)
+#[derive(Debug, Clone, Serialize)]
+pub struct RuleViolation {
+    pub chart: Chart,
+    pub rule: u8,
+    pub index: usize,
+    pub description: &'static str,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SpcReport {
+    pub count: usize,
+    pub mean: Option<f64>,
+    pub xbar_r: Option<XbarRChart>,
+    pub imr: Option<ImrChart>,
+    pub capability: Option<Capability>,
+    pub violations: Vec<RuleViolation>,
+}
+
+// 부분군 크기별 관리도 계수 (n = 2..=10): A2, D3, D4, d2
+const XBAR_R_CONSTANTS: [(f64, f64, f64, f64); 9] = [
+    (1.880, 0.0, 3.267, 1.128),
+    (1.023, 0.0, 2.574, 1.693),
+    (0.729, 0.0, 2.282, 2.059),
+    (0.577, 0.0, 2.114, 2.326),
+    (0.483, 0.0, 2.004, 2.534),
+    (0.419, 0.076, 1.924, 2.704),
+    (0.373, 0.136, 1.864, 2.847),
+    (0.337, 0.184, 1.816, 2.970),
+    (0.308, 0.223, 1.777, 3.078),
+];
+
+// 이동범위(n = 2) 계수
+const MR_D2: f64 = 1.128;
+const MR_D4: f64 = 3.267;
+
+impl SpcReport {
+    /// 시간순 측정값(mm)으로 관리도, 공정능력, 런 규칙 위반을 계산한다.
+    pub fn compute(values: &[f64], config: &SpcConfig) -> Self {
+        let xbar_r = xbar_r_chart(values, config.subgroup_size);
+        let imr = imr_chart(values);
+
+        let mut violations = Vec::new();
+        if let Some(chart) = &imr {
+            violations.extend(run_rules(
+                Chart::Individuals,
+                &chart.values,
+                chart.individuals,
+                config.rules,
+            ));
+        }
+        if let Some(chart) = &xbar_r {
+            violations.extend(run_rules(
+                Chart::Xbar,
+                &chart.means,
+                chart.xbar,
+                config.rules,
+            ));
+        }
+
+        let sigma_within = match (&xbar_r, &imr) {
+            (Some(chart), _) => Some(chart.range.center / d2(chart.subgroup_size)),
+            (None, Some(chart)) => Some(chart.moving_range.center / MR_D2),
+            _ => None,
+        };
+        let capability = match (sigma_within, stats::std_dev(values), stats::mean(values)) {
+            (Some(sigma_within), Some(sigma_overall), Some(mean)) => Some(Capability {
+                sigma_within,
+                sigma_overall,
+                cp: config
+                    .tolerance
+                    .as_ref()
+                    .and_then(|t| potential(t, sigma_within)),
+                cpk: config
+                    .tolerance
+                    .as_ref()
+                    .and_then(|t| actual(t, mean, sigma_within)),
+                pp: config
+                    .tolerance
+                    .as_ref()
+                    .and_then(|t| potential(t, sigma_overall)),
+                ppk: config
+                    .tolerance
+                    .as_ref()
+                    .and_then(|t| actual(t, mean, sigma_overall)),
+            }),
+            _ => None,
+        };
+
+        SpcReport {
+            count: values.len(),
+            mean: stats::mean(values),
+            xbar_r,
+            imr,
+            capability,
+            violations,
+        }
+    }
+}
+
+fn constants(subgroup_size: usize) -> (f64, f64, f64, f64) {
+    XBAR_R_CONSTANTS[subgroup_size.clamp(2, 10) - 2]
+}
+
+fn d2(subgroup_size: usize) -> f64 {
+    constants(subgroup_size).3
+}
+
+fn potential(tolerance: &Tolerance, sigma: f64) -> Option<f64> {
+    (sigma > 0.0).then(|| (tolerance.upper - tolerance.lower) / (6.0 * sigma))
+}
+
+fn actual(tolerance: &Tolerance, mean: f64, sigma: f64) -> Option<f64> {
+    (sigma > 0.0).then(|| (tolerance.upper - mean).min(mean - tolerance.lower) / (3.0 * sigma))
+}
+
+/// 부분군은 최신 측정이 마지막 부분군을 채우도록 뒤에서부터 나눈다 (앞쪽 나머지는 버림).
+fn xbar_r_chart(values: &[f64], subgroup_size: usize) -> Option<XbarRChart> {
+    let n = subgroup_size.clamp(2, 10);
+    let skip = values.len() % n;
+    let (means, ranges): (Vec<f64>, Vec<f64>) = values[skip..]
+        .chunks(n)
+        .map(|group| {
+            let max = group.iter().cloned().fold(f64::MIN, f64::max);
+            let min = group.iter().cloned().fold(f64::MAX, f64::min);
+            (stats::mean(group).unwrap_or(0.0), max - min)
+        })
+        .unzip();
+    if means.len() < 2 {
+        return None;
+    }
+    let (a2, d3, d4, _) = constants(n);
+    let grand_mean = stats::mean(&means)?;
+    let mean_range = stats::mean(&ranges)?;
+    Some(XbarRChart {
+        subgroup_size: n,
+        means,
+        ranges,
+        xbar: ControlLimits {
+            center: grand_mean,
+            ucl: grand_mean + a2 * mean_range,
+            lcl: grand_mean - a2 * mean_range,
+        },
+        range: ControlLimits {
+            center: mean_range,
+            ucl: d4 * mean_range,
+            lcl: d3 * mean_range,
+        },
+    })
+}
+
+fn imr_chart(values: &[f64]) -> Option<ImrChart> {
+    if values.len() < 2 {
+        return None;
+    }
+    let moving_ranges: Vec<f64> = values.windows(2).map(|w| (w[1] - w[0]).abs()).collect();
+    let mean = stats::mean(values)?;
+    let mean_mr = stats::mean(&moving_ranges)?;
+    let sigma = mean_mr / MR_D2;
+    Some(ImrChart {
+        values: values.to_vec(),
+        moving_ranges,
+        individuals: ControlLimits {
+            center: mean,
+            ucl: mean + 3.0 * sigma,
+            lcl: mean - 3.0 * sigma,
+        },
+        moving_range: ControlLimits {
+            center: mean_mr,
+            ucl: MR_D4 * mean_mr,
+            lcl: 0.0,
+        },
+    })
+}
+
+/// Western Electric / Nelson 런 규칙. 관리한계로부터 σ = (UCL - CL) / 3
+pub fn run_rules(
+    chart: Chart,
+    points: &[f64],
+    limits: ControlLimits,
+    rules: RuleSet,
+) -> Vec<RuleViolation> {
+    let sigma = (limits.ucl - limits.center) / 3.0;
+    if sigma <= 0.0 {
+        return Vec::new();
+    }
+    let z: Vec<f64> = points.iter().map(|p| (p - limits.center) / sigma).collect();
+
+    // (규칙 번호, 창 크기, 판정, 설명)
+    type Check = fn(&[f64]) -> bool;
+    let checks: &[(u8, usize, Check, &'static str)] = match rules {
+        RuleSet::WesternElectric => &[
+            (1, 1, |w| beyond(w, 3.0, 1), "1 point beyond 3σ"),
+            (
+                2,
+                3,
+                |w| beyond(w, 2.0, 2),
+                "2 of 3 points beyond 2σ on one side",
+            ),
+            (
+                3,
+                5,
+                |w| beyond(w, 1.0, 4),
+                "4 of 5 points beyond 1σ on one side",
+            ),
+            (4, 8, same_side, "8 points in a row on one side of center"),
+        ],
+        RuleSet::Nelson => &[
+            (1, 1, |w| beyond(w, 3.0, 1), "1 point beyond 3σ"),
+            (2, 9, same_side, "9 points in a row on one side of center"),
+            (3, 6, trending, "6 points in a row increasing or decreasing"),
+            (
+                4,
+                14,
+                alternating,
+                "14 points in a row alternating up and down",
+            ),
+            (
+                5,
+                3,
+                |w| beyond(w, 2.0, 2),
+                "2 of 3 points beyond 2σ on one side",
+            ),
+            (
+                6,
+                5,
+                |w| beyond(w, 1.0, 4),
+                "4 of 5 points beyond 1σ on one side",
+            ),
+            (
+                7,
+                15,
+                |w| w.iter().all(|z| z.abs() < 1.0),
+                "15 points in a row within 1σ",
+            ),
+            (
+                8,
+                8,
+                |w| w.iter().all(|z| z.abs() > 1.0),
+                "8 points in a row beyond 1σ on either side",
+            ),
+        ],
+    };
+
+    let mut violations = Vec::new();
+    for &(rule, window, check, description) in checks {
+        if z.len() < window {
+            continue;
+        }
+        for end in window - 1..z.len() {
+            if check(&z[end + 1 - window..=end]) {
+                violations.push(RuleViolation {
+                    chart,
+                    rule,
+                    index: end,
+                    description,
+                });
+            }
+        }
+    }
+    violations.sort_by_key(|v| (v.index, v.rule));
+    violations
+}
+
+// 같은 쪽에서 k σ를 넘는 점이 count개 이상
+fn beyond(window: &[f64], k: f64, count: usize) -> bool {
+    window.iter().filter(|&&z| z > k).count() >= count
+        || window.iter().filter(|&&z| z < -k).count() >= count
+}
+
+fn same_side(window: &[f64]) -> bool {
+    window.iter().all(|&z| z > 0.0) || window.iter().all(|&z| z < 0.0)
+}
+
+fn trending(window: &[f64]) -> bool {
+    window.windows(2).all(|w| w[1] > w[0]) || window.windows(2).all(|w| w[1] < w[0])
+}
+
+fn alternating(window: &[f64]) -> bool {
+    let diffs: Vec<f64> = window.windows(2).map(|w| w[1] - w[0]).collect();
+    diffs.iter().all(|&d| d != 0.0) && diffs.windows(2).all(|d| d[0].signum() != d[1].signum())
+}
+
+/// 공구마다 최근 alarm_window개 측정으로 런 규칙을 확인하고, 새로 들어온 측정에서
+/// 위반이 생기면 알람을 낸다. 시작 시점 이전의 위반은 알리지 않는다.
+pub async fn spawn_spc_monitor(
+    logger: HistoryLogger,
+    tool_data: Arc<Mutex<HashMap<u16, Vec<ToolData>>>>,
+) {
+    let mut last_checked: HashMap<(u16, ChannelKey), i64> = HashMap::new();
+    loop {
+        let tools: Vec<ToolData> = tool_data
+            .lock()
+            .unwrap()
+            .values()
+            .flatten()
+            .filter(|tool| tool.spc.alarm_window > 0)
+            .cloned()
+            .collect();
+        for tool in tools {
+            let points = match logger
+                .get_gauge_points(
+                    tool.machine_id,
+                    tool.feature,
+                    None,
+                    None,
+                    Some(tool.spc.alarm_window),
+                )
+                .await
+            {
+                Ok(points) => points,
+                Err(e) => {
+                    eprintln!(
+                        "Failed to read gauge values for SPC (machine {}, tool {}): {}",
+                        tool.machine_id,
+                        tool.key(),
+                        e
+                    );
+                    continue;
+                }
+            };
+            let Some(newest) = points.last().map(|p| p.id) else {
+                continue;
+            };
+            let key = (tool.machine_id, tool.key());
+            let Some(previous) = last_checked.insert(key, newest) else {
+                continue;
+            };
+            if newest == previous {
+                continue;
+            }
+
+            let values: Vec<f64> = points.iter().map(|p| p.value).collect();
+            let report = SpcReport::compute(&values, &tool.spc);
+            let subgroup = report.xbar_r.as_ref().map_or(1, |c| c.subgroup_size);
+            let skip = values.len() % subgroup;
+            // 위반 점에 해당하는 마지막 측정 id
+            let point_id = |v: &RuleViolation| match v.chart {
+                Chart::Individuals => points[v.index].id,
+                Chart::Xbar => points[skip + (v.index + 1) * subgroup - 1].id,
+            };
+            let new: Vec<String> = report
+                .violations
+                .iter()
+                .filter(|v| point_id(v) > previous)
+                .map(|v| {
+                    let chart = match v.chart {
+                        Chart::Individuals => "I",
+                        Chart::Xbar => "X̄",
+                    };
+                    format!("{} rule {}: {}", chart, v.rule, v.description)
+                })
+                .collect();
+            if new.is_empty() {
+                continue;
+            }
+            let mut messages = new;
+            messages.sort();
+            messages.dedup();
+            raise_alarm(
+                &logger,
+                AlarmLog {
+                    timestamp: Utc::now(),
+                    machine_id: tool.machine_id,
+                    path: tool.path,
+                    tool_num: tool.tool_num,
+                    kind: AlarmKind::SpcViolation,
+                    level: AlarmLevel::Warning,
+                    message: format!("SPC run rule violation: {}", messages.join(", ")),
+                },
+            );
+        }
+        tokio::time::sleep(tokio::time::Duration::from_secs(10)).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limits() -> ControlLimits {
+        ControlLimits {
+            center: 0.0,
+            ucl: 3.0,
+            lcl: -3.0,
+        }
+    }
+
+    fn rules_hit(points: &[f64], rules: RuleSet) -> Vec<u8> {
+        let mut hit: Vec<u8> = run_rules(Chart::Individuals, points, limits(), rules)
+            .iter()
+            .map(|v| v.rule)
+            .collect();
+        hit.sort_unstable();
+        hit.dedup();
+        hit
+    }
+
+    #[test]
+    fn test_imr_limits() {
+        let values = [10.0, 12.0, 11.0, 13.0, 12.0];
+        let chart = imr_chart(&values).unwrap();
+        // MR = 2, 1, 2, 1 -> MR̄ = 1.5
+        assert!((chart.moving_range.center - 1.5).abs() < 1e-9);
+        assert!((chart.individuals.center - 11.6).abs() < 1e-9);
+        assert!((chart.individuals.ucl - (11.6 + 3.0 * 1.5 / MR_D2)).abs() < 1e-9);
+        assert!((chart.moving_range.ucl - MR_D4 * 1.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_xbar_r_drops_oldest_partial_subgroup() {
+        let values = [100.0, 1.0, 2.0, 3.0, 4.0, 5.0, 6.0];
+        let chart = xbar_r_chart(&values, 3).unwrap();
+        assert_eq!(chart.means, vec![2.0, 5.0]);
+        assert_eq!(chart.ranges, vec![2.0, 2.0]);
+        assert!((chart.xbar.ucl - (3.5 + 1.023 * 2.0)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_capability() {
+        let config = SpcConfig {
+            tolerance: Some(Tolerance {
+                lower: 9.0,
+                upper: 15.0,
+            }),
+            ..SpcConfig::default()
+        };
+        let values = [11.0, 13.0, 11.0, 13.0, 11.0, 13.0];
+        let report = SpcReport::compute(&values, &config);
+        let capability = report.capability.unwrap();
+        let cp = capability.cp.unwrap();
+        let cpk = capability.cpk.unwrap();
+        // 평균이 규격 중앙이면 Cp = Cpk
+        assert!((cp - cpk).abs() < 1e-9);
+        assert!((cp - 6.0 / (6.0 * capability.sigma_within)).abs() < 1e-9);
+        assert!(capability.pp.unwrap() > 0.0);
+    }
+
+    #[test]
+    fn test_run_rules() {
+        assert_eq!(
+            rules_hit(&[0.5, -0.5, 3.5], RuleSet::WesternElectric),
+            vec![1]
+        );
+        assert_eq!(
+            rules_hit(&[2.5, 0.1, 2.2], RuleSet::WesternElectric),
+            vec![2]
+        );
+        assert_eq!(rules_hit(&[0.5; 8], RuleSet::WesternElectric), vec![4]);
+        assert!(rules_hit(&[0.5; 7], RuleSet::WesternElectric).is_empty());
+
+        let trend = [-1.5, -1.2, -0.5, 0.2, 0.5, 0.9];
+        assert_eq!(rules_hit(&trend, RuleSet::Nelson), vec![3]);
+        let zigzag: Vec<f64> = (0..14)
+            .map(|i| if i % 2 == 0 { 0.5 } else { -0.5 })
+            .collect();
+        assert_eq!(rules_hit(&zigzag, RuleSet::Nelson), vec![4]);
+        assert_eq!(rules_hit(&[0.1; 15], RuleSet::Nelson), vec![2, 7]);
+    }
+}
@@ -0,0 +1,351 @@
+use std::collections::VecDeque;
+
+use serde::{Deserialize, Serialize};
+
+use crate::cnc::ToolData;
+
+// 게이지 원시값 단위 (1/10000mm)
+const GAUGE_SCALE: f64 = 10000.0;
+
+/// 배치 하나로 계산한 보정
+pub struct Correction {
+    pub avg_gauge: f64, // 전략이 추정한 현재 치수 (mm)
+    pub offset: f64,    // 보정량 (mm)
+    pub rationale: String,
+}
+
+/// 전략이 배치 사이에 유지하는 값. 공구 교체 시 초기화된다.
+#[derive(Debug, Clone, Default)]
+pub struct StrategyState {
+    pub ewma: Option<f64>,     // mm
+    pub window: VecDeque<i32>, // 최근 측정 원시값
+    pub integral: f64,         // PI 적분항 (mm)
+}
+
+pub trait CompensationStrategy {
+    /// batch는 이상치를 제외한 게이지 원시값. 비어 있으면 None
+    fn correct(
+        &self,
+        tool: &ToolData,
+        batch: &[i32],
+        state: &mut StrategyState,
+    ) -> Option<Correction>;
+}
+
+/// 공구별 보정 전략 설정 (config.json의 "strategy")
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum StrategyConfig {
+    // 이상치 제외(outlier_rule)가 분리되기 전 이름
+    #[default]
+    #[serde(alias = "trimmed_mean")]
+    Mean,
+    Median,
+    Ewma(Ewma),
+    MovingWindow(MovingWindow),
+    Pi(PiController),
+}
+
+impl StrategyConfig {
+    pub fn strategy(&self) -> &dyn CompensationStrategy {
+        match self {
+            StrategyConfig::Mean => &Mean,
+            StrategyConfig::Median => &Median,
+            StrategyConfig::Ewma(s) => s,
+            StrategyConfig::MovingWindow(s) => s,
+            StrategyConfig::Pi(s) => s,
+        }
+    }
+}
+
+/// 기준 치수와의 차이 (수동 보정 포함, mm)
+fn error(tool: &ToolData, avg_gauge: f64) -> f64 {
+    tool.basic_size - avg_gauge + tool.manual_offset
+}
+
+fn mean(values: &[i32]) -> Option<f64> {
+    if values.is_empty() {
+        return None;
+    }
+    Some(values.iter().map(|&v| v as f64).sum::<f64>() / values.len() as f64)
+}
+
+/// 추정 치수로 기존 비례 보정 (basic_size - avg + manual_offset) * offset_rate
+fn proportional(tool: &ToolData, avg_gauge: f64, estimate: String) -> Correction {
+    let e = error(tool, avg_gauge);
+    Correction {
+        avg_gauge,
+        offset: e * tool.offset_rate,
+        rationale: format!(
+            "{}; ({:.3} - {:.4} + {:.3}) x {:.2}",
+            estimate, tool.basic_size, avg_gauge, tool.manual_offset, tool.offset_rate
+        ),
+    }
+}
+
+/// 이상치를 제외한 배치의 평균
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mean;
+
+impl CompensationStrategy for Mean {
+    fn correct(&self, tool: &ToolData, batch: &[i32], _: &mut StrategyState) -> Option<Correction> {
+        let avg_gauge = mean(batch)?.round() / GAUGE_SCALE;
+        Some(proportional(
+            tool,
+            avg_gauge,
+            format!("mean of {} values = {:.4}", batch.len(), avg_gauge),
+        ))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Median;
+
+impl CompensationStrategy for Median {
+    fn correct(&self, tool: &ToolData, batch: &[i32], _: &mut StrategyState) -> Option<Correction> {
+        if batch.is_empty() {
+            return None;
+        }
+        let mut sorted = batch.to_vec();
+        sorted.sort_unstable();
+        // 홀수면 두 인덱스가 같다
+        let n = sorted.len();
+        let median = (sorted[(n - 1) / 2] as f64 + sorted[n / 2] as f64) / 2.0;
+        let avg_gauge = median.round() / GAUGE_SCALE;
+        Some(proportional(
+            tool,
+            avg_gauge,
+            format!("median of {} values = {:.4}", batch.len(), avg_gauge),
+        ))
+    }
+}
+
+/// 배치 평균의 지수 가중 이동 평균. alpha가 클수록 새 배치 비중이 큼
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Ewma {
+    pub alpha: f64,
+}
+
+impl CompensationStrategy for Ewma {
+    fn correct(
+        &self,
+        tool: &ToolData,
+        batch: &[i32],
+        state: &mut StrategyState,
+    ) -> Option<Correction> {
+        let batch_mean = mean(batch)? / GAUGE_SCALE;
+        let alpha = self.alpha.clamp(0.0, 1.0);
+        let ewma = match state.ewma {
+            Some(prev) => alpha * batch_mean + (1.0 - alpha) * prev,
+            None => batch_mean,
+        };
+        state.ewma = Some(ewma);
+        Some(proportional(
+            tool,
+            ewma,
+            format!(
+                "EWMA(alpha={:.2}) of batch mean {:.4} = {:.4}",
+                alpha, batch_mean, ewma
+            ),
+        ))
+    }
+}
+
+/// 배치와 상관없이 최근 window개 측정값의 평균
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MovingWindow {
+    pub window: usize,
+}
+
+impl CompensationStrategy for MovingWindow {
+    fn correct(
+        &self,
+        tool: &ToolData,
+        batch: &[i32],
+        state: &mut StrategyState,
+    ) -> Option<Correction> {
+        if batch.is_empty() {
+            return None;
+        }
+        state.window.extend(batch.iter().copied());
+        while state.window.len() > self.window.max(1) {
+            state.window.pop_front();
+        }
+        let values: Vec<i32> = state.window.iter().copied().collect();
+        let avg_gauge = mean(&values)?.round() / GAUGE_SCALE;
+        Some(proportional(
+            tool,
+            avg_gauge,
+            format!(
+                "moving window mean of last {} values = {:.4}",
+                values.len(),
+                avg_gauge
+            ),
+        ))
+    }
+}
+
+/// 배치 평균 오차에 대한 PI 제어. 적분항은 ±integral_limit로 제한하고,
+/// 출력이 1회 한계를 넘는 동안에는 같은 방향으로 적분하지 않는다 (anti-windup).
+/// offset_rate는 출력 배율로 계속 적용된다.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PiController {
+    pub kp: f64,
+    pub ki: f64,
+    pub integral_limit: f64, // mm
+}
+
+impl CompensationStrategy for PiController {
+    fn correct(
+        &self,
+        tool: &ToolData,
+        batch: &[i32],
+        state: &mut StrategyState,
+    ) -> Option<Correction> {
+        let avg_gauge = mean(batch)?.round() / GAUGE_SCALE;
+        let e = error(tool, avg_gauge);
+        let limit = self.integral_limit.abs();
+        let integral = (state.integral + e).clamp(-limit, limit);
+        let output = (self.kp * e + self.ki * integral) * tool.offset_rate;
+        let saturated = output > tool.max_limit || output < tool.min_limit;
+        let winding_up = saturated && e.signum() == output.signum();
+        if !winding_up {
+            state.integral = integral;
+        }
+        let offset = (self.kp * e + self.ki * state.integral) * tool.offset_rate;
+        Some(Correction {
+            avg_gauge,
+            offset,
+            rationale: format!(
+                "PI: mean {:.4}, error {:.4}, integral {:.4}{}; ({:.2} x e + {:.2} x i) x {:.2}",
+                avg_gauge,
+                e,
+                state.integral,
+                if winding_up { " (held)" } else { "" },
+                self.kp,
+                self.ki,
+                tool.offset_rate
+            ),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::AppConfig;
+
+    fn tool() -> ToolData {
+        let mut tool = AppConfig::default().mapping.tool_data[&0][0].clone();
+        tool.basic_size = 50.0;
+        tool.manual_offset = 0.0;
+        tool.offset_rate = 1.0;
+        tool
+    }
+
+    fn run(strategy: &dyn CompensationStrategy, batch: &[i32], state: &mut StrategyState) -> f64 {
+        strategy.correct(&tool(), batch, state).unwrap().offset
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-9,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn test_mean() {
+        let mut state = StrategyState::default();
+        let correction = Mean
+            .correct(&tool(), &[499990, 500010, 500000, 499980], &mut state)
+            .unwrap();
+        assert_close(correction.avg_gauge, 49.9995);
+        assert_close(correction.offset, 0.0005);
+        assert!(Mean.correct(&tool(), &[], &mut state).is_none());
+    }
+
+    #[test]
+    fn test_median() {
+        let mut state = StrategyState::default();
+        // 홀수: 가운데 값, 짝수: 가운데 두 값의 평균
+        assert_close(run(&Median, &[500020, 499900, 500000], &mut state), 0.0);
+        assert_close(
+            run(&Median, &[499980, 499990, 500000, 500900], &mut state),
+            0.0005,
+        );
+        assert!(Median.correct(&tool(), &[], &mut state).is_none());
+    }
+
+    #[test]
+    fn test_ewma() {
+        let ewma = Ewma { alpha: 0.5 };
+        let mut state = StrategyState::default();
+        // 첫 배치는 그대로, 다음부터 이전 값과 섞는다
+        assert_close(run(&ewma, &[499980], &mut state), 0.002);
+        assert_close(run(&ewma, &[500020], &mut state), 0.0);
+        assert_close(state.ewma.unwrap(), 50.0);
+        // 공구 교체로 상태를 비우면 다시 첫 배치부터
+        let mut state = StrategyState::default();
+        assert_close(run(&ewma, &[500020], &mut state), -0.002);
+    }
+
+    #[test]
+    fn test_moving_window() {
+        let window = MovingWindow { window: 3 };
+        let mut state = StrategyState::default();
+        assert_close(run(&window, &[499990, 499990], &mut state), 0.001);
+        // 최근 3개 (499990, 500020, 500020)의 평균
+        assert_close(run(&window, &[500020, 500020], &mut state), -0.001);
+        assert_eq!(state.window.len(), 3);
+        assert!(window.correct(&tool(), &[], &mut state).is_none());
+    }
+
+    #[test]
+    fn test_pi() {
+        let pi = PiController {
+            kp: 0.5,
+            ki: 0.5,
+            integral_limit: 0.05,
+        };
+        let mut state = StrategyState::default();
+        // 오차 0.004: 0.5 x 0.004 + 0.5 x 0.004
+        assert_close(run(&pi, &[499960], &mut state), 0.004);
+        assert_close(run(&pi, &[499960], &mut state), 0.006);
+        assert_close(state.integral, 0.008);
+        // 공구 교체로 상태를 비우면 적분항도 0부터
+        let mut state = StrategyState::default();
+        assert_close(run(&pi, &[499960], &mut state), 0.004);
+    }
+
+    #[test]
+    fn test_pi_anti_windup() {
+        let pi = PiController {
+            kp: 0.5,
+            ki: 0.5,
+            integral_limit: 0.05,
+        };
+        let mut state = StrategyState {
+            integral: 0.008,
+            ..Default::default()
+        };
+        // 오차 0.03으로 출력이 1회 한계(0.010)를 넘으므로 적분항을 늘리지 않는다
+        assert_close(run(&pi, &[499700], &mut state), 0.019);
+        assert_close(state.integral, 0.008);
+        // 반대 방향 오차는 포화 중이 아니면 적분한다
+        assert_close(run(&pi, &[500040], &mut state), 0.0);
+        assert_close(state.integral, 0.004);
+
+        // 적분항은 ±integral_limit로 제한
+        let limited = PiController {
+            integral_limit: 0.005,
+            ..pi
+        };
+        let mut state = StrategyState::default();
+        run(&limited, &[499960], &mut state);
+        run(&limited, &[499960], &mut state);
+        assert_close(state.integral, 0.005);
+    }
+}
@@ -0,0 +1,399 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::alarm::{raise_alarm, AlarmKind, AlarmLevel, AlarmLog};
+use crate::cnc::{find_tool_mut, ChannelKey, ToolData};
+use crate::config::MachineConfig;
+use crate::logger::{GaugePoint, HistoryLogger};
+use crate::stats;
+use crate::{OffsetDecision, OffsetLog};
+
+// 신뢰구간 수준 (양측)
+const CONFIDENCE: f64 = 0.95;
+// 이득을 추정할 최소 보정 횟수
+const MIN_SAMPLES: usize = 3;
+// 자동 조정에 쓰는 최근 측정 수
+const HISTORY: u32 = 1000;
+
+/// 옵셋 변화 대비 측정 변화로 추정한 이득과 그에 맞춘 보정률 제안
+#[derive(Debug, Clone, Serialize)]
+pub struct RateSuggestion {
+    pub samples: usize,
+    pub gain: f64, // 측정 변화 / 옵셋 변화
+    pub gain_lower: f64,
+    pub gain_upper: f64,
+    pub confidence: f64,
+    pub current_rate: f64,
+    pub suggested_rate: f64, // 1 / gain
+    pub rate_lower: f64,
+    pub rate_upper: Option<f64>, // 이득 하한이 0 이하면 상한 없음
+    pub significant: bool,       // 현재 보정률이 신뢰구간 밖
+}
+
+/// 앱이 쓴 보정마다 (옵셋 변화, 측정 평균 변화)를 모은다 (mm).
+/// 보정 전 측정은 직전 변경 이후 마지막 window개, 보정 후 측정은 이송 중 skip개를 건너뛴
+/// window개이며 다음 변경 전까지만 쓴다. 양쪽에 2개 이상 있어야 쓴다.
+/// 외부 변경, 되돌리기도 측정을 바꾸므로 창은 모든 변경에서 끊고, 표본으로는 쓰지 않는다.
+fn responses(
+    points: &[GaugePoint],
+    offsets: &[OffsetLog],
+    window: usize,
+    skip: usize,
+) -> Vec<(f64, f64)> {
+    let times: Vec<Option<DateTime<Utc>>> = points.iter().map(GaugePoint::time).collect();
+    // 보정 시각까지 측정된 수
+    let measured: Vec<usize> = offsets
+        .iter()
+        .map(|log| {
+            times
+                .iter()
+                .take_while(|t| t.is_some_and(|t| t <= log.timestamp))
+                .count()
+        })
+        .collect();
+    let mean_of = |range: std::ops::Range<usize>| -> Option<f64> {
+        let values: Vec<f64> = points[range].iter().map(|p| p.value).collect();
+        (values.len() >= 2).then(|| stats::mean(&values)).flatten()
+    };
+    let mut samples = Vec::new();
+    for (k, log) in offsets.iter().enumerate() {
+        if !log
+            .decision
+            .is_some_and(|d| OffsetDecision::APP_WRITES.contains(&d))
+        {
+            continue;
+        }
+        let at = measured[k];
+        let previous = if k > 0 { measured[k - 1] + skip } else { 0 };
+        let next = measured.get(k + 1).copied().unwrap_or(points.len());
+        let before = at.saturating_sub(window).max(previous).min(at);
+        let after_start = (at + skip).min(next);
+        let after_end = (after_start + window).min(next);
+        if let (Some(before), Some(after)) = (mean_of(before..at), mean_of(after_start..after_end))
+        {
+            samples.push((log.change_amount as f64 / 1000.0, after - before));
+        }
+    }
+    samples
+}
+
+/// 원점을 지나는 회귀 y = g * x 의 기울기와 신뢰구간 반폭
+fn fit_gain(samples: &[(f64, f64)]) -> Option<(f64, f64)> {
+    if samples.len() < MIN_SAMPLES {
+        return None;
+    }
+    let sxx: f64 = samples.iter().map(|(x, _)| x * x).sum();
+    if sxx <= 0.0 {
+        return None;
+    }
+    let gain = samples.iter().map(|(x, y)| x * y).sum::<f64>() / sxx;
+    let df = (samples.len() - 1) as f64;
+    let residual: f64 = samples.iter().map(|(x, y)| (y - gain * x).powi(2)).sum();
+    let se = (residual / df / sxx).sqrt();
+    let t = stats::t_quantile(1.0 - (1.0 - CONFIDENCE) / 2.0, df);
+    Some((gain, t * se))
+}
+
+/// 측정 이력과 옵셋 변경 이력으로 보정률을 제안한다. 이득이 0 이하면 None
+/// (보정 사이의 마모도 측정 변화에 섞이므로 보정이 많을수록 정확하다.)
+pub fn suggest_rate(
+    tool: &ToolData,
+    points: &[GaugePoint],
+    offsets: &[OffsetLog],
+    window: usize,
+    skip: usize,
+) -> Option<RateSuggestion> {
+    let samples = responses(points, offsets, window, skip);
+    let (gain, half_width) = fit_gain(&samples)?;
+    if gain <= 0.0 {
+        return None;
+    }
+    let (gain_lower, gain_upper) = (gain - half_width, gain + half_width);
+    let rate_lower = 1.0 / gain_upper;
+    let rate_upper = (gain_lower > 0.0).then(|| 1.0 / gain_lower);
+    Some(RateSuggestion {
+        samples: samples.len(),
+        gain,
+        gain_lower,
+        gain_upper,
+        confidence: CONFIDENCE,
+        current_rate: tool.offset_rate,
+        suggested_rate: 1.0 / gain,
+        rate_lower,
+        rate_upper,
+        significant: tool.offset_rate < rate_lower
+            || rate_upper.is_some_and(|upper| tool.offset_rate > upper),
+    })
+}
+
+/// auto_tune이 켜진 공구의 보정률 제안을 주기적으로 갱신한다.
+/// 현재 보정률이 신뢰구간을 벗어나면 알람을 올리고, 적용은 관리자 승인으로만 한다.
+pub async fn spawn_rate_tuner(
+    logger: HistoryLogger,
+    tool_data: Arc<Mutex<HashMap<u16, Vec<ToolData>>>>,
+    batch_size: Arc<Mutex<HashMap<u16, usize>>>,
+    machines: Vec<MachineConfig>,
+) {
+    let transit: HashMap<u16, usize> = machines
+        .iter()
+        .map(|m| (m.id as u16, m.parts_in_transit as usize))
+        .collect();
+    let mut alarmed: HashMap<(u16, ChannelKey), f64> = HashMap::new();
+    loop {
+        let tools: Vec<ToolData> = tool_data
+            .lock()
+            .unwrap()
+            .values()
+            .flatten()
+            .filter(|tool| tool.auto_tune)
+            .cloned()
+            .collect();
+        for tool in tools {
+            let points = match logger
+                .get_gauge_points(tool.machine_id, tool.feature, None, None, Some(HISTORY))
+                .await
+            {
+                Ok(points) => points,
+                Err(e) => {
+                    eprintln!(
+                        "Failed to read gauge values for rate tuning (machine {}, tool {}): {}",
+                        tool.machine_id,
+                        tool.key(),
+                        e
+                    );
+                    continue;
+                }
+            };
+            let from = points.first().and_then(GaugePoint::time);
+            let offsets =
+                match logger
+                    .get_offset_changes(tool.machine_id, tool.key(), from, None)
+                    .await
+                {
+                    Ok(offsets) => offsets,
+                    Err(e) => {
+                        eprintln!(
+                        "Failed to read offset history for rate tuning (machine {}, tool {}): {}",
+                        tool.machine_id, tool.key(), e
+                    );
+                        continue;
+                    }
+                };
+            let window = *batch_size
+                .lock()
+                .unwrap()
+                .get(&tool.machine_id)
+                .unwrap_or(&5);
+            let skip = *transit.get(&tool.machine_id).unwrap_or(&0);
+            let suggestion = suggest_rate(&tool, &points, &offsets, window, skip);
+
+            // 현재 보정률이 신뢰구간 밖일 때만, 같은 제안으로 반복하지 않도록 알람
+            let key = (tool.machine_id, tool.key());
+            let alarm = suggestion.as_ref().filter(|s| {
+                s.significant
+                    && alarmed
+                        .get(&key)
+                        .is_none_or(|&rate| (rate - s.suggested_rate).abs() > 0.05)
+            });
+            if let Some(s) = alarm {
+                alarmed.insert(key, s.suggested_rate);
+                raise_alarm(
+                    &logger,
+                    AlarmLog {
+                        timestamp: Utc::now(),
+                        machine_id: tool.machine_id,
+                        path: tool.path,
+                        tool_num: tool.tool_num,
+                        kind: AlarmKind::RateSuggestion,
+                        level: AlarmLevel::Warning,
+                        message: format!(
+                            "Measured gain {:.2} ({:.2} to {:.2}, {} corrections): offset rate {:.2} suggested instead of {:.2}",
+                            s.gain,
+                            s.gain_lower,
+                            s.gain_upper,
+                            s.samples,
+                            s.suggested_rate,
+                            s.current_rate
+                        ),
+                    },
+                );
+            }
+            if let Some(tool) =
+                find_tool_mut(&mut tool_data.lock().unwrap(), tool.machine_id, tool.key())
+            {
+                tool.rate_suggestion = suggestion;
+            }
+        }
+        tokio::time::sleep(tokio::time::Duration::from_secs(60)).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fit_gain() {
+        // 옵셋 변화의 80%만 측정에 나타나는 경우
+        let samples = [
+            (0.010, 0.0081),
+            (-0.005, -0.0039),
+            (0.008, 0.0064),
+            (0.004, 0.0033),
+        ];
+        let (gain, half_width) = fit_gain(&samples).unwrap();
+        assert!((gain - 0.8).abs() < 0.02);
+        assert!(half_width > 0.0 && half_width < 0.1);
+        assert!(fit_gain(&samples[..2]).is_none());
+    }
+
+    fn base() -> DateTime<Utc> {
+        use chrono::TimeZone;
+        Utc.with_ymd_and_hms(2026, 1, 1, 0, 0, 0).unwrap()
+    }
+
+    fn point(i: usize, value: f64) -> GaugePoint {
+        GaugePoint {
+            id: i as i64,
+            timestamp: (base() + chrono::Duration::seconds(i as i64))
+                .format("%Y-%m-%d %H:%M:%S")
+                .to_string(),
+            value,
+        }
+    }
+
+    // i번째 측정 직후에 쓴 옵셋 변경 (µm)
+    fn change(i: usize, amount: i32, decision: OffsetDecision) -> OffsetLog {
+        OffsetLog {
+            id: None,
+            timestamp: base() + chrono::Duration::seconds(i as i64),
+            machine_id: 0,
+            path: 1,
+            tool_num: 11,
+            old_value: 0,
+            change_amount: amount,
+            new_value: amount,
+            success: true,
+            verified: true,
+            decision: Some(decision),
+            requested_change: None,
+            undo_of: None,
+            rationale: None,
+            recipe: None,
+        }
+    }
+
+    // 보정마다 측정 10개, 보정 뒤 측정이 response만큼 바로 바뀌는 이력
+    fn series(steps: &[(i32, f64)]) -> (Vec<GaugePoint>, Vec<OffsetLog>) {
+        let (mut points, mut offsets, mut level) = (Vec::new(), Vec::new(), 0.0);
+        for &(amount, response) in steps {
+            for _ in 0..10 {
+                points.push(point(points.len(), level));
+            }
+            offsets.push(change(points.len() - 1, amount, OffsetDecision::Applied));
+            level += response;
+        }
+        for _ in 0..10 {
+            points.push(point(points.len(), level));
+        }
+        (points, offsets)
+    }
+
+    fn tool(offset_rate: f64) -> ToolData {
+        let mut tool = crate::config::AppConfig::default().mapping.tool_data[&0][0].clone();
+        tool.offset_rate = offset_rate;
+        tool
+    }
+
+    #[test]
+    fn test_responses_windows() {
+        // 0..10: 0, 10..15: 0.008, 15..20: 0.004
+        let points: Vec<GaugePoint> = (0..20)
+            .map(|i| {
+                point(
+                    i,
+                    [0.0, 0.008, 0.004][(i >= 10) as usize + (i >= 15) as usize],
+                )
+            })
+            .collect();
+        let offsets = [
+            change(7, 10, OffsetDecision::Applied),
+            change(14, -5, OffsetDecision::Clamped),
+        ];
+        let samples = responses(&points, &offsets, 5, 2);
+        assert_eq!(samples.len(), 2);
+        // 보정 후 창은 이송 중 2개를 건너뛴 10..15
+        assert!((samples[0].0 - 0.010).abs() < 1e-9);
+        assert!((samples[0].1 - 0.008).abs() < 1e-9);
+        // 보정 전 창은 직전 보정의 이송 구간 이후 10..15, 보정 후 창은 남은 17..20
+        assert!((samples[1].0 + 0.005).abs() < 1e-9);
+        assert!((samples[1].1 + 0.004).abs() < 1e-9);
+
+        // 보정 후 창에 측정이 2개 미만이면 쓰지 않는다
+        assert_eq!(responses(&points[..11], &offsets[..1], 5, 2).len(), 0);
+    }
+
+    #[test]
+    fn test_responses_skip_external() {
+        // 0..10: 0, 10..23: 0.008, 23..30: 0.013
+        let points: Vec<GaugePoint> = (0..30)
+            .map(|i| {
+                point(
+                    i,
+                    [0.0, 0.008, 0.013][(i >= 10) as usize + (i >= 23) as usize],
+                )
+            })
+            .collect();
+        // 조작반에서 바꾼 값은 앞 보정의 창을 끊지만 표본은 아니다
+        let offsets = [
+            change(7, 10, OffsetDecision::Applied),
+            change(20, 5, OffsetDecision::External),
+        ];
+        let samples = responses(&points, &offsets, 5, 2);
+        assert_eq!(samples.len(), 1);
+        assert!((samples[0].0 - 0.010).abs() < 1e-9);
+        assert!((samples[0].1 - 0.008).abs() < 1e-9);
+
+        // 창이 열리기 전에 되돌린 보정은 응답을 잴 수 없다
+        let offsets = [
+            change(7, 10, OffsetDecision::Applied),
+            change(8, -10, OffsetDecision::Undo),
+        ];
+        assert!(responses(&points, &offsets, 5, 2).is_empty());
+    }
+
+    #[test]
+    fn test_suggest_rate_min_samples() {
+        let (points, offsets) = series(&[(10, 0.008), (-5, -0.004)]);
+        assert!(suggest_rate(&tool(1.0), &points, &offsets, 5, 0).is_none());
+
+        // 측정 변화가 옵셋과 반대면 제안하지 않는다
+        let (points, offsets) = series(&[(10, -0.008), (-5, 0.004), (8, -0.006)]);
+        assert!(suggest_rate(&tool(1.0), &points, &offsets, 5, 0).is_none());
+    }
+
+    #[test]
+    fn test_suggest_rate_significance() {
+        // 이득 약 0.8 → 제안 보정률 약 1.25
+        let (points, offsets) = series(&[
+            (10, 0.0081),
+            (-5, -0.0039),
+            (8, 0.0064),
+            (4, 0.0033),
+            (-6, -0.0048),
+        ]);
+        let s = suggest_rate(&tool(1.0), &points, &offsets, 5, 0).unwrap();
+        assert_eq!(s.samples, 5);
+        assert!((s.suggested_rate - 1.25).abs() < 0.03);
+        assert!(s.rate_lower < s.suggested_rate);
+        assert!(s.rate_upper.is_some_and(|upper| upper > s.suggested_rate));
+        assert!(s.significant);
+
+        // 현재 보정률이 신뢰구간 안이면 알람 대상이 아니다
+        let s = suggest_rate(&tool(1.25), &points, &offsets, 5, 0).unwrap();
+        assert!(!s.significant);
+    }
+}
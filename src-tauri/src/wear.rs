@@ -0,0 +1,151 @@
+use std::collections::VecDeque;
+
+/// 공구 카운트(rdcount)에 대한 마모율 회귀. 공구 교체 시 초기화된다.
+///
+/// 측정 평균에서 공구 교체 후 쓴 보정량을 빼서 보정이 없었을 때의 치수를 추정하고,
+/// 그 치수를 공구 카운트에 대해 선형 회귀한 기울기를 마모율로 쓴다.
+/// 기계 전체 가공 수량과 달리 공구 카운트는 교체 시 다시 시작하므로 공구별 사용량이 된다.
+#[derive(Debug, Clone, Default)]
+pub struct WearModel {
+    samples: VecDeque<(f64, f64)>, // (공구 카운트, 보정 제외 치수 mm)
+    applied: f64,                  // 공구 교체 후 쓴 보정량 합 (mm)
+    pending_size: Option<f64>,     // 다음 카운트와 짝지을 배치 결과
+    reference_count: Option<i64>,  // 마지막 예측 보정 기준 카운트
+    pub rate: Option<f64>,         // mm/part
+}
+
+/// 공구 카운트 증가분에 대한 예측 보정
+pub struct Feedforward {
+    pub correction: f64, // mm
+    pub rate: f64,       // mm/part
+    pub parts: i64,
+}
+
+// 기울기를 믿기 위한 최소 표본 수
+const MIN_SAMPLES: usize = 3;
+
+impl WearModel {
+    /// 새 배치 평균(mm). 다음에 읽은 공구 카운트와 함께 표본이 된다.
+    pub fn on_batch(&mut self, avg_gauge: f64) {
+        self.pending_size = Some(avg_gauge - self.applied);
+    }
+
+    pub fn on_offset_written(&mut self, offset_diff: f64) {
+        self.applied += offset_diff;
+    }
+
+    /// 공구 카운트를 반영하고, 기준 카운트 이후 예측 마모량이 step(mm) 이상이면
+    /// 그만큼의 보정을 반환하고 기준 카운트를 옮긴다.
+    pub fn feedforward(&mut self, count: i64, window: usize, step: f64) -> Option<Feedforward> {
+        if count < 0 {
+            return None;
+        }
+        if let Some(size) = self.pending_size.take() {
+            self.samples.push_back((count as f64, size));
+            while self.samples.len() > window.max(MIN_SAMPLES) {
+                self.samples.pop_front();
+            }
+            self.rate = fit_slope(&self.samples);
+        }
+        let reference = *self.reference_count.get_or_insert(count);
+        let rate = self.rate?;
+        let parts = count - reference;
+        let correction = -rate * parts as f64;
+        if parts <= 0 || correction.abs() < step.max(0.001) {
+            return None;
+        }
+        self.reference_count = Some(count);
+        Some(Feedforward {
+            correction,
+            rate,
+            parts,
+        })
+    }
+}
+
+fn fit_slope(samples: &VecDeque<(f64, f64)>) -> Option<f64> {
+    if samples.len() < MIN_SAMPLES {
+        return None;
+    }
+    let n = samples.len() as f64;
+    let mean_x = samples.iter().map(|s| s.0).sum::<f64>() / n;
+    let mean_y = samples.iter().map(|s| s.1).sum::<f64>() / n;
+    let sxx: f64 = samples.iter().map(|s| (s.0 - mean_x).powi(2)).sum();
+    let sxy: f64 = samples
+        .iter()
+        .map(|s| (s.0 - mean_x) * (s.1 - mean_y))
+        .sum();
+    (sxx > 0.0).then(|| sxy / sxx)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 10개마다 0.001mm씩 커지는 치수 (마모율 0.0001mm/part)
+    fn size_at(count: i64) -> f64 {
+        50.0 + 0.0001 * count as f64
+    }
+
+    #[test]
+    fn test_needs_min_samples() {
+        let mut wear = WearModel::default();
+        for count in [10, 20] {
+            wear.on_batch(size_at(count));
+            assert!(wear.feedforward(count, 10, 0.001).is_none());
+        }
+        assert!(wear.rate.is_none());
+        assert!(wear.feedforward(-1, 10, 0.001).is_none());
+    }
+
+    #[test]
+    fn test_feedforward_step() {
+        let mut wear = WearModel::default();
+        for count in [10, 20] {
+            wear.on_batch(size_at(count));
+            wear.feedforward(count, 10, 0.001);
+        }
+        wear.on_batch(size_at(30));
+        // 처음 본 수량 10부터 20개분: -0.002mm, 기준을 30으로 옮김
+        let ff = wear.feedforward(30, 10, 0.001).unwrap();
+        assert!((ff.rate - 0.0001).abs() < 1e-9);
+        assert_eq!(ff.parts, 20);
+        assert!((ff.correction + 0.002).abs() < 1e-9);
+        // step보다 작으면 기준을 옮기지 않고 쌓는다
+        assert!(wear.feedforward(35, 10, 0.001).is_none());
+        let ff = wear.feedforward(40, 10, 0.001).unwrap();
+        assert_eq!(ff.parts, 10);
+        // 같은 수량에서는 다시 보정하지 않는다
+        assert!(wear.feedforward(40, 10, 0.001).is_none());
+    }
+
+    #[test]
+    fn test_excludes_applied_offsets() {
+        let mut wear = WearModel::default();
+        wear.on_batch(size_at(10));
+        wear.feedforward(10, 10, 0.001);
+        // 0.002mm 줄이는 보정을 쓴 뒤의 측정값은 그만큼 작지만 마모율은 그대로
+        wear.on_offset_written(-0.002);
+        for count in [20, 30] {
+            wear.on_batch(size_at(count) - 0.002);
+            wear.feedforward(count, 10, 0.001);
+        }
+        assert!((wear.rate.unwrap() - 0.0001).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_window() {
+        let mut wear = WearModel::default();
+        // 처음엔 마모가 빠르다가 (0.001mm/part) 안정됨 (0.0001mm/part)
+        for (count, size) in [(10, 50.0), (20, 50.01), (30, 50.02)] {
+            wear.on_batch(size);
+            wear.feedforward(count, 3, 0.1);
+        }
+        for count in [40, 50, 60] {
+            wear.on_batch(50.02 + 0.0001 * (count - 30) as f64);
+            wear.feedforward(count, 3, 0.1);
+        }
+        // 최근 3개 표본만으로 회귀
+        assert!((wear.rate.unwrap() - 0.0001).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,207 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::anyhow;
+use focas_rs::FocasClient;
+use tokio::sync::oneshot;
+
+use crate::cnc::ChannelKey;
+
+type FocasJob = Box<dyn FnOnce(&dyn CncBackend) + Send>;
+
+/// 워커 큐에 쌓을 수 있는 최대 작업 수. 넘치면 기다리지 않고 busy 에러
+const QUEUE_CAPACITY: usize = 32;
+
+// 작업 상태: 큐에서 대기 → 실행 중 또는 취소(시간 초과로 실행하지 않음)
+const JOB_QUEUED: u8 = 0;
+const JOB_RUNNING: u8 = 1;
+const JOB_CANCELLED: u8 = 2;
+
+/// 워커 작업이 쓰는 CNC 기능. 실제 기계는 FocasClient, 시뮬레이션은 SimulatedCnc
+pub trait CncBackend: Send {
+    fn set_path(&self, path: i16) -> anyhow::Result<()>;
+    fn read_tool_offset(&self, tool_num: i16) -> anyhow::Result<i32>; // µm
+    fn write_tool_offset(&self, tool_num: i16, value: i32) -> anyhow::Result<()>;
+    fn read_macro(&self, number: i16) -> anyhow::Result<(i32, i16)>; // (값, 소수 자리수)
+    fn write_macro(&self, number: i16, value: i32, dec_val: i16) -> anyhow::Result<()>;
+    fn read_tool_life(&self, tool_num: i16) -> anyhow::Result<i32>;
+    fn read_tool_count(&self, tool_num: i16) -> anyhow::Result<i32>;
+    fn write_pmc(&self, adr_type: i16, address: u16, data: &[u8]) -> anyhow::Result<()>;
+}
+
+impl CncBackend for FocasClient {
+    fn set_path(&self, path: i16) -> anyhow::Result<()> {
+        self.setpath(path)?;
+        Ok(())
+    }
+
+    fn read_tool_offset(&self, tool_num: i16) -> anyhow::Result<i32> {
+        Ok(self.rdtofs(tool_num, 0)?.data as i32)
+    }
+
+    fn write_tool_offset(&self, tool_num: i16, value: i32) -> anyhow::Result<()> {
+        self.wrtofs(tool_num, 0, value)?;
+        Ok(())
+    }
+
+    fn read_macro(&self, number: i16) -> anyhow::Result<(i32, i16)> {
+        let macro_val = self.rdmacro(number)?;
+        Ok((macro_val.mcr_val as i32, macro_val.dec_val))
+    }
+
+    fn write_macro(&self, number: i16, value: i32, dec_val: i16) -> anyhow::Result<()> {
+        self.wrmacro(number, value as _, dec_val)?;
+        Ok(())
+    }
+
+    fn read_tool_life(&self, tool_num: i16) -> anyhow::Result<i32> {
+        Ok(self.rdlife(tool_num)?.data)
+    }
+
+    fn read_tool_count(&self, tool_num: i16) -> anyhow::Result<i32> {
+        Ok(self.rdcount(tool_num)?.data)
+    }
+
+    fn write_pmc(&self, adr_type: i16, address: u16, data: &[u8]) -> anyhow::Result<()> {
+        self.wrpmcrng(adr_type, address, data)?;
+        Ok(())
+    }
+}
+
+/// 기계 한 대의 CNC 연결(FocasClient 또는 시뮬레이션)을 전용 스레드에서 돌리는 핸들.
+/// FOCAS 호출은 블로킹 FFI이므로 tokio 워커 스레드에서 직접 부르지 않고,
+/// 작업 큐에 넣은 뒤 응답을 기다린다. 큐는 순서대로 하나씩 실행되므로
+/// 계통 선택(setpath) + 읽기/쓰기 같은 연속 호출을 한 작업 안에 넣으면 섞이지 않는다.
+pub struct FocasWorker {
+    machine_id: u16,
+    jobs: mpsc::SyncSender<FocasJob>,
+    timeout: Duration,
+    written: Arc<Mutex<HashMap<ChannelKey, i32>>>, // 앱이 쓴 옵셋 값 (옵셋 감시가 가져갈 때까지)
+}
+
+impl FocasWorker {
+    pub fn spawn(machine_id: u16, client: impl CncBackend + 'static, timeout: Duration) -> Self {
+        let (jobs, queue) = mpsc::sync_channel::<FocasJob>(QUEUE_CAPACITY);
+        std::thread::Builder::new()
+            .name(format!("focas-{}", machine_id))
+            .spawn(move || {
+                for job in queue {
+                    job(&client);
+                }
+                println!("FOCAS worker for machine {} stopped", machine_id);
+            })
+            .expect("Failed to spawn FOCAS worker thread");
+        Self {
+            machine_id,
+            jobs,
+            timeout,
+            written: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// 앱이 쓴 옵셋 값 기록. 쓰기 작업 안에서 채우고 옵셋 감시 작업 안에서 비우므로
+    /// 같은 큐 순서로 보이고, 감시 쪽은 앱이 쓴 값을 외부 변경으로 착각하지 않는다.
+    pub fn written_offsets(&self) -> Arc<Mutex<HashMap<ChannelKey, i32>>> {
+        self.written.clone()
+    }
+
+    /// 작업을 큐에 넣고 결과를 기다린다. 큐가 가득 차 있으면 바로 busy 에러.
+    /// 시간 초과 시 아직 큐에 있는 작업은 취소되어 실행되지 않는다. 이미 실행 중인
+    /// 작업은 멈출 수 없으므로 그대로 두고 시간 초과 에러를 돌려준다. 응답하지 않는
+    /// 기계 하나가 기계를 차례로 도는 루프 전체를 붙잡지 않도록 기다리지 않는다.
+    pub async fn call<T, F>(&self, job: F) -> anyhow::Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&dyn CncBackend) -> anyhow::Result<T> + Send + 'static,
+    {
+        let (reply_tx, mut reply_rx) = oneshot::channel();
+        let state = Arc::new(AtomicU8::new(JOB_QUEUED));
+        let job_state = state.clone();
+        self.jobs
+            .try_send(Box::new(move |client| {
+                if job_state
+                    .compare_exchange(JOB_QUEUED, JOB_RUNNING, Ordering::AcqRel, Ordering::Acquire)
+                    .is_ok()
+                {
+                    let _ = reply_tx.send(job(client));
+                }
+            }))
+            .map_err(|e| match e {
+                mpsc::TrySendError::Full(_) => {
+                    anyhow!("FOCAS worker for machine {} is busy", self.machine_id)
+                }
+                mpsc::TrySendError::Disconnected(_) => {
+                    anyhow!("FOCAS worker for machine {} has stopped", self.machine_id)
+                }
+            })?;
+
+        let reply = match tokio::time::timeout(self.timeout, &mut reply_rx).await {
+            Ok(reply) => reply,
+            Err(_) => {
+                if state
+                    .compare_exchange(
+                        JOB_QUEUED,
+                        JOB_CANCELLED,
+                        Ordering::AcqRel,
+                        Ordering::Acquire,
+                    )
+                    .is_ok()
+                {
+                    return Err(anyhow!(
+                        "FOCAS call for machine {} timed out after {:?}",
+                        self.machine_id,
+                        self.timeout
+                    ));
+                }
+                // 끝나기 직전에 시간이 초과됐으면 결과를 그대로 쓴다
+                if let Ok(reply) = reply_rx.try_recv() {
+                    return reply;
+                }
+                log::warn!(
+                    "FOCAS call for machine {} is still running after {:?}; not waiting for it",
+                    self.machine_id,
+                    self.timeout
+                );
+                return Err(anyhow!(
+                    "FOCAS call for machine {} timed out after {:?} while running",
+                    self.machine_id,
+                    self.timeout
+                ));
+            }
+        };
+        reply.unwrap_or_else(|_| {
+            Err(anyhow!(
+                "FOCAS worker for machine {} dropped the request",
+                self.machine_id
+            ))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::SimulatedCnc;
+
+    #[tokio::test]
+    async fn test_running_job_timeout() {
+        let worker = FocasWorker::spawn(0, SimulatedCnc::default(), Duration::from_millis(50));
+        let started = std::time::Instant::now();
+        // 실행 중인 작업이 멈춰도 기다리지 않고 시간 초과
+        let hung = worker.call(|_| {
+            std::thread::sleep(Duration::from_millis(300));
+            Ok(1)
+        });
+        let queued = worker.call(|_| Ok(2));
+        let (hung, queued) = tokio::join!(hung, queued);
+        assert!(hung.unwrap_err().to_string().contains("while running"));
+        assert!(queued.is_err());
+        assert!(started.elapsed() < Duration::from_millis(250));
+
+        // 큐에서 취소된 작업은 실행되지 않고, 워커는 다음 작업을 계속 받는다
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert_eq!(worker.call(|_| Ok(3)).await.unwrap(), 3);
+    }
+}
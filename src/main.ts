@@ -3,6 +3,8 @@ import { listen } from '@tauri-apps/api/event';
 
 // --- 타입 정의 ---
 interface ToolData {
+    name: string;
+    feature: number;
     machine_id: number;
     tool_num: number;
     basic_size: number;
@@ -11,27 +13,130 @@ interface ToolData {
     active: boolean;
     avg_gauge: number | null;
     final_offset: number | null;
+    strategy: { kind: 'mean' | 'median' | 'ewma' | 'moving_window' | 'pi' };
     current_offset: number;
     previous_offset: number;
     life: number;
     count: number;
     max_limit: number;
     min_limit: number;
+    target: OffsetTarget;
+    path: number;
+    life_alarm: 'warning' | 'critical' | null;
+    warmup_parts: number;
+    warmup_remaining: number;
+    cooldown_parts: number;
+    cooldown_secs: number;
+    cooldown_parts_remaining: number;
+    cooldown_until: string | null;
+    in_cooldown: boolean;
+    max_drift: number;
+    drift: number;
+    drift_locked: boolean;
+    shadow: boolean;
+    predictive: boolean;
+    wear_rate: number | null; // mm/EA
+    auto_tune: boolean;
+    rate_suggestion: RateSuggestion | null;
 }
 
+interface RateSuggestion {
+    samples: number;
+    gain: number;
+    gain_lower: number;
+    gain_upper: number;
+    current_rate: number;
+    suggested_rate: number;
+    rate_lower: number;
+    rate_upper: number | null;
+    significant: boolean; // 현재 보정률이 신뢰구간 밖
+}
+
+type OffsetTarget =
+    | { kind: 'tool_offset' }
+    | { kind: 'macro_variable'; number: number };
+
 interface MachineUiState {
     machine_id: number;
-    upper_tool: ToolData;
-    lower_tool: ToolData;
+    tools: ToolData[]; // 보정 채널 (설정 순서)
+    batch_size: number;
+    recipe: string | null; // 활성 레시피
+}
+
+// 부품별 설정 묶음
+interface Recipe {
+    name: string;
+    part_number: string;
+    description: string;
     batch_size: number;
+    tools: ToolData[];
 }
 
 interface OffsetLog {
     timestamp: string;
+    path: number;
     old_value: number;
     change_amount: number;
     new_value: number;
     success: boolean;
+    verified: boolean;
+    decision: OffsetDecision | null;
+    requested_change: number | null;
+    id: number | null;
+    undo_of: number | null;
+    rationale: string | null;
+}
+
+type OffsetDecision = 'applied' | 'clamped' | 'skipped' | 'within_deadband' | 'awaiting_approval' | 'manual' | 'external' | 'restored'
+    | 'approved' | 'rejected' | 'expired' | 'undo' | 'predictive';
+
+const DECISION_LABELS: Record<OffsetDecision, string> = {
+    applied: '자동',
+    clamped: '제한',
+    skipped: '건너뜀',
+    within_deadband: '불감대',
+    awaiting_approval: '승인대기',
+    manual: '수동',
+    external: '외부',
+    restored: '복원',
+    approved: '승인',
+    rejected: '거부',
+    expired: '만료',
+    undo: '되돌림',
+    predictive: '예측',
+};
+
+interface ShadowLog {
+    timestamp: string;
+    avg_gauge: number | null;
+    current_value: number;
+    change_amount: number;
+    requested_change: number;
+    decision: OffsetDecision;
+    rationale: string | null;
+}
+
+interface PendingCorrection {
+    id: number;
+    created_at: string;
+    expires_at: string;
+    machine_id: number;
+    path: number;
+    tool_num: number;
+    offset_diff: number;
+    requested_change: number;
+    avg_gauge: number | null;
+    rationale: string;
+}
+
+interface AlarmLog {
+    timestamp: string;
+    machine_id: number;
+    path: number;
+    tool_num: number;
+    kind: string;
+    level: 'warning' | 'critical';
+    message: string;
 }
 
 interface RawGaugeLog {
@@ -40,7 +145,62 @@ interface RawGaugeLog {
     active_line: number;
     tool_type: number;      // 1: 황삭, 2: 정삭
     measured_value: number;
-    is_used: number;        // 0: 대기중, 1: 사용중, 2: 사용됨
+    is_used: number;        // 0: 대기중, 1: 사용중, 2: 사용됨, 3: 제외됨, 4: 이상치
+    part_number: number | null; // 추정 부품 번호 (CNC 가공 수량 기준)
+    reject_reason: string | null; // 이상치로 제외된 이유
+}
+
+interface ControlLimits {
+    center: number;
+    ucl: number;
+    lcl: number;
+}
+
+interface SpcReport {
+    count: number;
+    mean: number | null;
+    xbar_r: { subgroup_size: number; means: number[]; xbar: ControlLimits; range: ControlLimits } | null;
+    imr: { values: number[]; individuals: ControlLimits; moving_range: ControlLimits } | null;
+    capability: {
+        sigma_within: number;
+        sigma_overall: number;
+        cp: number | null;
+        cpk: number | null;
+        pp: number | null;
+        ppk: number | null;
+    } | null;
+    violations: { chart: 'individuals' | 'xbar'; rule: number; index: number; description: string }[];
+}
+
+interface DeviationStats {
+    mean: number | null;
+    std_dev: number | null;
+    max_abs: number | null;
+    out_of_tolerance: number | null;
+}
+
+interface BacktestReport {
+    readings: number;
+    excluded: number;
+    actual_corrections: number;
+    corrections: {
+        timestamp: string;
+        reading: number;
+        avg_gauge: number | null;
+        requested_change: number;
+        change_amount: number;
+        decision: OffsetDecision;
+        rationale: string;
+    }[];
+    actual: DeviationStats;
+    predicted: DeviationStats;
+    points: { timestamp: string; actual: number; predicted: number }[];
+}
+
+interface SimulationReport {
+    parts: number;
+    offset_writes: number;
+    features: { machine_id: number; feature: number; parts: number; mean: number | null; std_dev: number | null; max_abs: number | null }[];
 }
 
 // --- 상태 관리 ---
@@ -54,23 +214,169 @@ const tableHead = document.getElementById('table-head')!;
 const tableBody = document.getElementById('table-body')!;
 const editModal = document.getElementById('edit-modal')!;
 const historyModal = document.getElementById('history-modal')!;
+const shadowModal = document.getElementById('shadow-modal')!;
+const approvalModal = document.getElementById('approval-modal')!;
+const spcModal = document.getElementById('spc-modal')!;
+let spcContext: { machineId: number; path: number; toolNum: number } | null = null;
+const backtestModal = document.getElementById('backtest-modal')!;
+const recipeModal = document.getElementById('recipe-modal')!;
+let recipeMachineId = 0;
+let pendingApprovals: PendingCorrection[] = [];
+
+// 보정 대상 표시 (계통 + 공구 옵셋: T번호, 매크로 변수: #번호)
+function targetLabel(tool: ToolData): string {
+    const target = tool.target?.kind === 'macro_variable' ? `#${tool.target.number}` : `T${tool.tool_num}`;
+    return `P${tool.path} ${target}`;
+}
+
+// 공구 수명 알람 단계별 강조 (경고: 주황, 한계: 빨강)
+function lifeAlarmClass(tool: ToolData): string {
+    if (tool.life_alarm === 'critical') return 'bg-red-500 text-white animate-pulse';
+    if (tool.life_alarm === 'warning') return 'bg-orange-300';
+    return '';
+}
+
+// 보정 직후 쿨다운 중이면 남은 부품 수/시간 표시
+function cooldownBadge(tool: ToolData): string {
+    if (!tool.in_cooldown) return '';
+    const parts: string[] = [];
+    if (tool.cooldown_parts_remaining > 0) parts.push(`${tool.cooldown_parts_remaining}EA`);
+    if (tool.cooldown_until) {
+        const secs = Math.ceil((new Date(tool.cooldown_until).getTime() - Date.now()) / 1000);
+        if (secs > 0) parts.push(`${secs}s`);
+    }
+    return `<span class="bg-purple-600 text-white text-xs px-1 rounded" title="보정 후 대기 중 (측정값 제외)">대기 ${parts.join(' / ')}</span>`;
+}
+
+// 누적 보정량 표시. 한계 도달로 잠기면 클릭해서 점검 후 리셋
+function driftBadge(m: MachineUiState, tool: ToolData, title: string): string {
+    const cls = tool.drift_locked ? 'bg-red-600 animate-pulse' : 'bg-gray-500';
+    return `<button data-action="reset-drift" data-id="${m.machine_id}" data-path="${tool.path}" data-tool="${tool.tool_num}" data-title="${title}"
+        class="${cls} text-white text-xs px-1 rounded" title="누적 보정량 / 한계 (클릭: 리셋)">
+        Σ${tool.drift.toFixed(3)}${tool.max_drift > 0 ? `/${tool.max_drift.toFixed(3)}` : ''}${tool.drift_locked ? ' 잠금' : ''}
+    </button>`;
+}
+
+// 모의 운전 중인 공구 표시. 클릭하면 쓰지 않은 보정 이력을 연다
+// 예측 보정 중인 공구의 마모율 (um/EA)
+function wearBadge(tool: ToolData): string {
+    if (!tool.predictive) return '';
+    const rate = tool.wear_rate !== null ? `${(tool.wear_rate * 1000).toFixed(2)}um/EA` : '학습 중';
+    return `<span class="bg-teal-600 text-white text-xs px-1 rounded" title="마모율 회귀로 배치 사이에 예측 보정">예측 ${rate}</span>`;
+}
+
+function shadowBadge(m: MachineUiState, tool: ToolData): string {
+    if (!tool.shadow) return '';
+    return `<button data-action="shadow-history" data-id="${m.machine_id}" data-path="${tool.path}" data-tool="${tool.tool_num}"
+        class="bg-indigo-600 text-white text-xs px-1 rounded" title="모의 운전: CNC에 쓰지 않음">모의</button>`;
+}
 
 // --- 데이터 폴링 및 렌더링 ---
 async function fetchState() {
     try {
         machines = await invoke('get_all_machine_states');
         renderTable();
+        pendingApprovals = await invoke('get_pending_approvals');
+        renderApprovals();
+        renderSimulation(await invoke('get_simulation_report'));
     } catch (e) {
         console.error("상태 갱신 실패:", e);
     }
 }
 
+// 시뮬레이션 모드 표시: 가공 수, 옵셋 쓰기 수, 항목별 실제 치수 편차
+function renderSimulation(report: SimulationReport | null) {
+    const banner = document.getElementById('sim-banner')!;
+    if (!report) return;
+    const num = (v: number | null) => v === null ? '-' : v.toFixed(4);
+    banner.classList.remove('hidden');
+    banner.textContent = `시뮬레이션: ${report.parts}EA 가공, 옵셋 쓰기 ${report.offset_writes}회 | ` +
+        report.features.map(f => `${f.machine_id + 1}호기 #${f.feature} 평균 ${num(f.mean)} σ ${num(f.std_dev)} 최대 ${num(f.max_abs)}`).join(' | ');
+}
+
+// 승인 대기 보정: 개수 버튼과 목록 모달
+function renderApprovals() {
+    // 신뢰구간이 현재 보정률을 벗어난 제안만 승인 대상으로 보여줌
+    const suggestions = machines.flatMap(m => m.tools.filter(t => t.rate_suggestion?.significant));
+    const count = pendingApprovals.length + suggestions.length;
+    const button = document.getElementById('btn-approvals')!;
+    document.getElementById('approval-count')!.textContent = String(count);
+    button.classList.toggle('hidden', count === 0);
+
+    const pct = (v: number | null) => v === null ? '∞' : `${(v * 100).toFixed(0)}%`;
+    document.getElementById('rate-suggestion-body')!.innerHTML = suggestions.map(t => {
+        const s = t.rate_suggestion!;
+        return `
+        <tr class="border-b hover:bg-gray-100">
+            <td class="p-1">${t.machine_id + 1}호기</td>
+            <td class="p-1">${t.name} T${t.tool_num}</td>
+            <td class="p-1">${s.samples}</td>
+            <td class="p-1">${s.gain.toFixed(2)} (${s.gain_lower.toFixed(2)} ~ ${s.gain_upper.toFixed(2)})</td>
+            <td class="p-1">${pct(s.current_rate)}</td>
+            <td class="p-1 font-bold">${pct(s.suggested_rate)} (${pct(s.rate_lower)} ~ ${pct(s.rate_upper)})</td>
+            <td class="p-1">
+                <button data-action="apply-rate" data-id="${t.machine_id}" data-path="${t.path}" data-tool="${t.tool_num}" class="bg-green-600 text-white px-2 rounded">적용</button>
+            </td>
+        </tr>`;
+    }).join('');
+
+    document.getElementById('approval-body')!.innerHTML = pendingApprovals.map(p => `
+        <tr class="border-b hover:bg-gray-100">
+            <td class="p-1">${new Date(p.created_at).toLocaleTimeString()}</td>
+            <td class="p-1">${p.machine_id + 1}호기</td>
+            <td class="p-1">P${p.path} T${p.tool_num}</td>
+            <td class="p-1">${p.avg_gauge !== null ? p.avg_gauge.toFixed(4) : '-'}</td>
+            <td class="p-1 font-bold ${p.offset_diff > 0 ? 'text-red-600' : 'text-blue-600'}">${(p.offset_diff / 1000).toFixed(3)}</td>
+            <td class="p-1" title="${p.rationale}">${(p.requested_change / 1000).toFixed(3)}</td>
+            <td class="p-1">${new Date(p.expires_at).toLocaleTimeString()}</td>
+            <td class="p-1 flex gap-1 justify-center">
+                <button data-action="approve" data-id="${p.machine_id}" data-path="${p.path}" data-tool="${p.tool_num}" data-approval="${p.id}" class="bg-green-600 text-white px-2 rounded">승인</button>
+                <button data-action="reject" data-id="${p.machine_id}" data-path="${p.path}" data-tool="${p.tool_num}" data-approval="${p.id}" class="bg-red-500 text-white px-2 rounded">거부</button>
+            </td>
+        </tr>
+    `).join('');
+}
+
+// 레시피 목록 (선택한 기계에 적용 / 복제)
+async function renderRecipes() {
+    const machine = machines.find(m => m.machine_id === recipeMachineId);
+    document.getElementById('recipe-title')!.textContent =
+        `레시피 (${recipeMachineId + 1}호기 - 현재: ${machine?.recipe ?? '없음'})`;
+    try {
+        const recipes: Recipe[] = await invoke('list_recipes');
+        document.getElementById('recipe-body')!.innerHTML = recipes.map(r => `
+            <tr class="border-b hover:bg-gray-100 ${r.name === machine?.recipe ? 'bg-blue-50 font-bold' : ''}">
+                <td class="p-1">${r.name}</td>
+                <td class="p-1">${r.part_number}</td>
+                <td class="p-1">${r.description}</td>
+                <td class="p-1">${r.batch_size}</td>
+                <td class="p-1">${r.tools.map(t => `${t.name} T${t.tool_num} ${t.basic_size.toFixed(3)}`).join(', ')}</td>
+                <td class="p-1 flex gap-1 justify-center">
+                    <button data-action="activate-recipe" data-id="${recipeMachineId}" data-recipe="${r.name}" class="bg-green-600 text-white px-2 rounded">적용</button>
+                    <button data-action="clone-recipe" data-id="${recipeMachineId}" data-recipe="${r.name}" class="bg-gray-500 text-white px-2 rounded">복제</button>
+                </td>
+            </tr>
+        `).join('');
+    } catch (err) { alert("레시피 조회 실패: " + err); }
+}
+
+// 기계 한 칸을 보정 채널 수만큼 나눈 그리드
+function channelGrid(m: MachineUiState, cell: (tool: ToolData, channel: number) => string, extraClass = ''): string {
+    return `<div class="grid h-full ${extraClass}" style="grid-template-columns: repeat(${m.tools.length}, minmax(0, 1fr))">
+        ${m.tools.map((tool, channel) => cell(tool, channel)).join('')}
+    </div>`;
+}
+
 function renderTable() {
     // 1. 헤더 렌더링
     let headHtml = `<tr class="bg-[#00B0F0] text-white font-bold h-12 text-lg">
         <th class="border border-gray-300 w-32 bg-[#00B0F0]">항목</th>`;
     machines.forEach(m => {
-        headHtml += `<th class="border border-white w-64">${m.machine_id + 1}호기</th>`;
+        headHtml += `<th class="border border-white w-64">${m.machine_id + 1}호기
+            <button data-action="recipes" data-id="${m.machine_id}" class="bg-white/20 text-xs px-1 rounded font-normal" title="레시피 (부품별 설정)">
+                ${m.recipe ?? '레시피 없음'}
+            </button>
+        </th>`;
     });
     headHtml += `</tr>`;
     tableHead.innerHTML = headHtml;
@@ -81,9 +387,12 @@ function renderTable() {
     // 기준 경치수
     bodyHtml += `<tr class="bg-[#FFC000] h-10"><td class="bg-[#00B0F0] text-white font-bold border border-white">기준 경치수</td>`;
     machines.forEach(m => {
-        bodyHtml += `<td class="border border-gray-400 cursor-pointer hover:bg-yellow-300 transition" 
-            data-action="edit" data-id="${m.machine_id}" data-upper="true" data-field="basic_size" data-title="기준 경치수">
-            ${m.upper_tool.basic_size.toFixed(3)}
+        bodyHtml += `<td class="border border-gray-400 p-0">
+            ${channelGrid(m, (tool, channel) => `
+                <div class="border-r border-gray-400 last:border-r-0 flex items-center justify-center cursor-pointer hover:bg-yellow-300 transition"
+                    data-action="edit" data-id="${m.machine_id}" data-channel="${channel}" data-field="basic_size" data-title="${tool.name} 기준 경치수">
+                    ${tool.basic_size.toFixed(3)}
+                </div>`)}
         </td>`;
     });
     bodyHtml += `</tr>`;
@@ -91,9 +400,13 @@ function renderTable() {
     // 평균 경치수
     bodyHtml += `<tr class="bg-[#FFC000] h-10"><td class="bg-[#00B0F0] text-white font-bold border border-white">평균 경치수</td>`;
     machines.forEach(m => {
-        bodyHtml += `<td class="border border-gray-400 cursor-pointer hover:bg-yellow-300 transition" 
+        bodyHtml += `<td class="border border-gray-400 p-0 cursor-pointer hover:bg-yellow-300 transition" 
             data-action="view-raw-gauge" data-id="${m.machine_id}">
-            ${(m.upper_tool.avg_gauge || 0).toFixed(3)}
+            ${channelGrid(m, tool => `
+                <div class="border-r border-gray-400 last:border-r-0 flex items-center justify-center gap-1">${(tool.avg_gauge || 0).toFixed(3)}
+                    <button data-action="spc" data-id="${m.machine_id}" data-path="${tool.path}" data-tool="${tool.tool_num}"
+                        class="bg-teal-600 text-white text-xs px-1 rounded" title="관리도 / 공정능력">SPC</button>
+                </div>`)}
         </td>`;
     });
     bodyHtml += `</tr>`;
@@ -101,9 +414,9 @@ function renderTable() {
     // 보정 치수 헤더
     bodyHtml += `<tr class="bg-[#00B0F0] text-white font-bold h-8 text-xs align-middle">
         <td rowspan="3" class="border border-white text-sm">보정 치수</td>`;
-    machines.forEach(() => {
+    machines.forEach(m => {
         bodyHtml += `<td class="border border-white p-0">
-            <div class="grid grid-cols-2 h-full align-middle"><div class="border-r border-white/30">자동보정값</div><div>수동보정값</div></div>
+            ${channelGrid(m, tool => `<div class="border-r border-white/30 last:border-r-0">${tool.name}<br>자동 / 수동</div>`, 'align-middle')}
         </td>`;
     });
     bodyHtml += `</tr>`;
@@ -111,15 +424,15 @@ function renderTable() {
     // 보정 치수 데이터
     bodyHtml += `<tr class="bg-[#FFC000] h-12">`;
     machines.forEach(m => {
-        const autoOffset = (m.upper_tool.basic_size - (m.upper_tool.avg_gauge || 0)).toFixed(3);
         bodyHtml += `<td class="border border-gray-400 p-0">
-            <div class="grid grid-cols-2 h-full items-center">
-                <div class="border-r border-gray-400 h-full flex items-center justify-center">${autoOffset}</div>
-                <div class="h-full flex items-center justify-center cursor-pointer hover:bg-yellow-300 font-bold transition"
-                    data-action="edit" data-id="${m.machine_id}" data-upper="true" data-field="manual_offset" data-title="수동 보정값">
-                    ${m.upper_tool.manual_offset.toFixed(3)}
-                </div>
-            </div>
+            ${channelGrid(m, (tool, channel) => `
+                <div class="border-r border-gray-400 last:border-r-0 grid grid-cols-2 h-full items-center">
+                    <div class="h-full flex items-center justify-center">${(tool.basic_size - (tool.avg_gauge || 0)).toFixed(3)}</div>
+                    <div class="h-full flex items-center justify-center cursor-pointer hover:bg-yellow-300 font-bold transition"
+                        data-action="edit" data-id="${m.machine_id}" data-channel="${channel}" data-field="manual_offset" data-title="${tool.name} 수동 보정값">
+                        ${tool.manual_offset.toFixed(3)}
+                    </div>
+                </div>`, 'items-center')}
         </td>`;
     });
     bodyHtml += `</tr>`;
@@ -127,8 +440,12 @@ function renderTable() {
     // 최종 보정값
     bodyHtml += `<tr class="bg-[#FFC000] h-8">`;
     machines.forEach(m => {
-        const finalVal = (m.upper_tool.basic_size - (m.upper_tool.avg_gauge || 0) + m.upper_tool.manual_offset).toFixed(3);
-        bodyHtml += `<td class="border border-gray-400 text-xs font-bold">최종 보정값: ${finalVal}</td>`;
+        bodyHtml += `<td class="border border-gray-400 p-0 text-xs font-bold">
+            ${channelGrid(m, tool => `
+                <div class="border-r border-gray-400 last:border-r-0 flex items-center justify-center">
+                    최종: ${(tool.basic_size - (tool.avg_gauge || 0) + tool.manual_offset).toFixed(3)}
+                </div>`)}
+        </td>`;
     });
     bodyHtml += `</tr>`;
 
@@ -145,36 +462,30 @@ function renderTable() {
     // 보정 옵셋 NO
     bodyHtml += `<tr class="bg-[#FFC000]"><td class="bg-[#00B0F0] text-white font-bold border border-white">보정 옵셋 NO.</td>`;
     machines.forEach(m => {
-        const upActive = m.upper_tool.active;
-        const dnActive = m.lower_tool.active;
         bodyHtml += `<td class="border border-gray-400 p-1">
-            <div class="flex justify-center items-center gap-2 mb-1 bg-yellow-200 p-1 rounded">
+            ${m.tools.map((tool, channel) => `
+            <div class="flex flex-wrap justify-center items-center gap-2 mb-1 last:mb-0 bg-yellow-200 p-1 rounded">
                 <span class="text-xs font-bold cursor-pointer hover:bg-yellow-400 p-0.5 rounded transition"
-                      data-action="edit" data-id="${m.machine_id}" data-upper="true" data-field="tool_num" data-title="황삭 툴 번호">
-                    황삭(T${m.upper_tool.tool_num})
+                      data-action="edit" data-id="${m.machine_id}" data-channel="${channel}" data-field="tool_num" data-title="${tool.name} 툴 번호">
+                    ${tool.name}(${targetLabel(tool)})
                 </span>
-                <button data-action="toggle" data-id="${m.machine_id}" data-upper="true" 
-                    class="${upActive ? 'bg-green-600' : 'bg-red-500'} text-white text-xs px-2 py-0.5 rounded shadow">
-                    ${upActive ? 'ON' : 'OFF'}
+                <button data-action="toggle" data-id="${m.machine_id}" data-channel="${channel}" 
+                    class="${tool.active ? 'bg-green-600' : 'bg-red-500'} text-white text-xs px-2 py-0.5 rounded shadow">
+                    ${tool.active ? 'ON' : 'OFF'}
                 </button>
-                <button data-action="edit" data-id="${m.machine_id}" data-upper="true" data-field="offset_rate" data-title="황삭 보정률"
+                <button data-action="edit" data-id="${m.machine_id}" data-channel="${channel}" data-field="offset_rate" data-title="${tool.name} 보정률"
                     class="bg-blue-800 text-white text-xs px-1 rounded">
-                    ${(m.upper_tool.offset_rate * 100).toFixed(0)}%
+                    ${(tool.offset_rate * 100).toFixed(0)}%
                 </button>
-            </div>
-            <div class="flex justify-center items-center gap-2 bg-yellow-200 p-1 rounded">
-                <span class="text-xs font-bold cursor-pointer hover:bg-yellow-400 p-0.5 rounded transition"
-                      data-action="edit" data-id="${m.machine_id}" data-upper="false" data-field="tool_num" data-title="정삭 툴 번호">
-                    정삭(T${m.lower_tool.tool_num})
-                <button data-action="toggle" data-id="${m.machine_id}" data-upper="false" 
-                    class="${dnActive ? 'bg-green-600' : 'bg-red-500'} text-white text-xs px-2 py-0.5 rounded shadow">
-                    ${dnActive ? 'ON' : 'OFF'}
-                </button>
-                <button data-action="edit" data-id="${m.machine_id}" data-upper="false" data-field="offset_rate" data-title="정삭 보정률"
-                    class="bg-blue-800 text-white text-xs px-1 rounded">
-                    ${(m.lower_tool.offset_rate * 100).toFixed(0)}%
+                <button data-action="tool-change" data-id="${m.machine_id}" data-path="${tool.path}" data-tool="${tool.tool_num}" data-title="${tool.name}"
+                    class="bg-gray-600 text-white text-xs px-1 rounded">
+                    교체${tool.warmup_remaining > 0 ? ` (${tool.warmup_remaining})` : ''}
                 </button>
-            </div>
+                ${shadowBadge(m, tool)}
+                ${cooldownBadge(tool)}
+                ${wearBadge(tool)}
+                ${driftBadge(m, tool, tool.name)}
+            </div>`).join('')}
         </td>`;
     });
     bodyHtml += `</tr>`;
@@ -182,19 +493,16 @@ function renderTable() {
     // 실 보정값
     bodyHtml += `<tr class="bg-[#FFC000] h-10"><td class="bg-[#00B0F0] text-white font-bold border border-white">실 보정값</td>`;
     machines.forEach(m => {
-        const upperReal = m.upper_tool.avg_gauge 
-            ? (m.upper_tool.basic_size - m.upper_tool.avg_gauge + m.upper_tool.manual_offset) * m.upper_tool.offset_rate 
-            : 0;
-
-        const lowerReal = m.lower_tool.avg_gauge 
-            ? (m.lower_tool.basic_size - m.lower_tool.avg_gauge + m.lower_tool.manual_offset) * m.lower_tool.offset_rate 
-            : 0;
-
         bodyHtml += `<td class="border border-gray-400">
-            <div class="grid grid-cols-2 gap-1 text-xs font-bold">
-                <div class="text-blue-800">황: ${upperReal.toFixed(3)}</div>
-                <div class="text-blue-800">정: ${lowerReal.toFixed(3)}</div>
-            </div>
+            ${channelGrid(m, tool => {
+                // PI 제어는 적분항이 있어 마지막 배치에서 계산한 값을 그대로 표시
+                const real = tool.strategy.kind === 'pi'
+                    ? (tool.final_offset || 0)
+                    : tool.avg_gauge
+                        ? (tool.basic_size - tool.avg_gauge + tool.manual_offset) * tool.offset_rate
+                        : 0;
+                return `<div class="text-blue-800">${tool.name}: ${real.toFixed(3)}</div>`;
+            }, 'gap-1 text-xs font-bold')}
         </td>`;
     });
     bodyHtml += `</tr>`;
@@ -203,16 +511,11 @@ function renderTable() {
     bodyHtml += `<tr class="bg-[#FFC000] h-10"><td class="bg-[#00B0F0] text-white font-bold border border-white">이전 옵셋</td>`;
     machines.forEach(m => {
         bodyHtml += `<td class="border border-gray-400 p-0">
-            <div class="grid grid-cols-2 h-full text-xs">
-                <div class="border-r border-gray-400 flex items-center justify-center cursor-pointer hover:bg-yellow-300" 
-                     data-action="history" data-id="${m.machine_id}" data-tool="${m.upper_tool.tool_num}">
-                    ${m.upper_tool.previous_offset.toFixed(3)}
-                </div>
-                <div class="flex items-center justify-center cursor-pointer hover:bg-yellow-300" 
-                     data-action="history" data-id="${m.machine_id}" data-tool="${m.lower_tool.tool_num}">
-                    ${m.lower_tool.previous_offset.toFixed(3)}
-                </div>
-            </div>
+            ${channelGrid(m, tool => `
+                <div class="border-r border-gray-400 last:border-r-0 flex items-center justify-center cursor-pointer hover:bg-yellow-300" 
+                     data-action="history" data-id="${m.machine_id}" data-path="${tool.path}" data-tool="${tool.tool_num}">
+                    ${tool.previous_offset.toFixed(3)}
+                </div>`, 'text-xs')}
         </td>`;
     });
     bodyHtml += `</tr>`;
@@ -222,16 +525,17 @@ function renderTable() {
     
     machines.forEach(m => {
         bodyHtml += `<td class="border border-gray-400 p-0">
-            <div class="grid grid-cols-2 h-full text-xs font-bold">
-                <div class="border-r border-gray-400 flex items-center justify-center cursor-pointer hover:bg-yellow-300 text-red-600"
-                     data-action="edit" data-id="${m.machine_id}" data-upper="true" data-field="upper_limit" data-title="1회 변화량 상한(+)">
-                    ▲ ${m.upper_tool.max_limit.toFixed(3)}
-                </div>
-                <div class="flex items-center justify-center cursor-pointer hover:bg-yellow-300 text-blue-700"
-                     data-action="edit" data-id="${m.machine_id}" data-upper="true" data-field="lower_limit" data-title="1회 변화량 하한(-)">
-                    ▼ ${m.upper_tool.min_limit.toFixed(3)}
-                </div>
-            </div>
+            ${channelGrid(m, (tool, channel) => `
+                <div class="border-r border-gray-400 last:border-r-0 flex flex-col">
+                    <div class="flex-1 flex items-center justify-center cursor-pointer hover:bg-yellow-300 text-red-600"
+                         data-action="edit" data-id="${m.machine_id}" data-channel="${channel}" data-field="upper_limit" data-title="${tool.name} 1회 변화량 상한(+)">
+                        ▲ ${tool.max_limit.toFixed(3)}
+                    </div>
+                    <div class="flex-1 flex items-center justify-center cursor-pointer hover:bg-yellow-300 text-blue-700"
+                         data-action="edit" data-id="${m.machine_id}" data-channel="${channel}" data-field="lower_limit" data-title="${tool.name} 1회 변화량 하한(-)">
+                        ▼ ${tool.min_limit.toFixed(3)}
+                    </div>
+                </div>`, 'text-xs font-bold')}
         </td>`;
     });
     bodyHtml += `</tr>`;
@@ -240,16 +544,11 @@ function renderTable() {
     bodyHtml += `<tr class="bg-[#FFC000] h-10"><td class="bg-[#00B0F0] text-white font-bold border border-white">직접 입력</td>`;
     machines.forEach(m => {
         bodyHtml += `<td class="border border-gray-400 p-0">
-            <div class="grid grid-cols-2 h-full text-xs">
+            ${channelGrid(m, tool => `
                 <div class="flex items-center justify-center bg-[#00B050] text-white font-bold cursor-pointer hover:bg-green-600 m-1 rounded shadow transition"
-                     data-action="write-offset" data-id="${m.machine_id}" data-tool="${m.upper_tool.tool_num}" data-title="황삭 옵셋 쓰기">
-                    ${m.upper_tool.current_offset.toFixed(3)} <br> (INPUT)
-</div>
-                <div class="flex items-center justify-center bg-[#00B050] text-white font-bold cursor-pointer hover:bg-green-600 m-1 rounded shadow transition"
-                     data-action="write-offset" data-id="${m.machine_id}" data-tool="${m.lower_tool.tool_num}" data-title="정삭 옵셋 쓰기">
-                    ${m.lower_tool.current_offset.toFixed(3)} <br> (INPUT)
-                </div>
-            </div>
+                     data-action="write-offset" data-id="${m.machine_id}" data-path="${tool.path}" data-tool="${tool.tool_num}" data-title="${tool.name} 옵셋 쓰기">
+                    ${tool.current_offset.toFixed(3)} <br> (INPUT)
+                </div>`, 'text-xs')}
         </td>`;
     });
     bodyHtml += `</tr>`;
@@ -258,10 +557,8 @@ function renderTable() {
     bodyHtml += `<tr class="bg-[#FFC000] h-10"><td class="bg-[#00B0F0] text-white font-bold border border-white">설정 수명</td>`;
     machines.forEach(m => {
         bodyHtml += `<td class="border border-gray-400 p-0">
-            <div class="grid grid-cols-2 h-full font-bold">
-                <div class="border-r border-gray-400 flex items-center justify-center text-blue-800">${m.upper_tool.life} EA</div>
-                <div class="flex items-center justify-center text-blue-800">${m.lower_tool.life} EA</div>
-            </div>
+            ${channelGrid(m, tool => `
+                <div class="border-r border-gray-400 last:border-r-0 flex items-center justify-center text-blue-800">${tool.life} EA</div>`, 'font-bold')}
         </td>`;
     });
     bodyHtml += `</tr>`;
@@ -270,10 +567,8 @@ function renderTable() {
     bodyHtml += `<tr class="bg-[#FFC000] h-10"><td class="bg-[#00B0F0] text-white font-bold border border-white">사용 수명</td>`;
     machines.forEach(m => {
         bodyHtml += `<td class="border border-gray-400 p-0">
-            <div class="grid grid-cols-2 h-full font-bold">
-                <div class="border-r border-gray-400 flex items-center justify-center text-red-700">${m.upper_tool.count} EA</div>
-                <div class="flex items-center justify-center text-red-700">${m.lower_tool.count} EA</div>
-            </div>
+            ${channelGrid(m, tool => `
+                <div class="border-r border-gray-400 last:border-r-0 flex items-center justify-center text-red-700 ${lifeAlarmClass(tool)}">${tool.count} EA</div>`, 'font-bold')}
         </td>`;
     });
     bodyHtml += `</tr>`;
@@ -300,6 +595,17 @@ listen<string>('sys-error', (event) => {
     }
 });
 
+// 공구 알람 이벤트 → 같은 경고 팝업으로 표시
+listen<AlarmLog>('tool-alarm', (event) => {
+    const alarm = event.payload;
+    const errorModal = document.getElementById('error-modal')!;
+    const errorMsg = document.getElementById('error-message')!;
+
+    errorMsg.textContent = `${alarm.machine_id + 1}호기 T${alarm.tool_num}: ${alarm.message}`;
+    errorModal.classList.remove('hidden');
+    errorModal.classList.add('flex');
+});
+
 // --- 이벤트 위임 (Event Delegation) ---
 document.addEventListener('click', async (e) => {
     const target = (e.target as HTMLElement);
@@ -348,7 +654,8 @@ document.addEventListener('click', async (e) => {
     const action = actionTarget.getAttribute('data-action');
     const machineId = Number(actionTarget.getAttribute('data-id'));
     
-    const isUpper = target.getAttribute('data-upper') === 'true';
+    const channel = Number(actionTarget.getAttribute('data-channel') ?? 0);
+    const path = Number(actionTarget.getAttribute('data-path') ?? 1);
     const machine = machines.find(m => m.machine_id === machineId);
     
     if (!machine) return;
@@ -356,7 +663,7 @@ document.addEventListener('click', async (e) => {
     if (action === 'edit' || action === 'edit-batch') {
         const field = target.getAttribute('data-field') || 'batch_size';
         const title = target.getAttribute('data-title') || '산출 데이터수';
-        const tool = isUpper ? machine.upper_tool : machine.lower_tool;
+        const tool = machine.tools[channel];
         
         let val = 0;
         if (field === 'batch_size') val = machine.batch_size;
@@ -364,7 +671,7 @@ document.addEventListener('click', async (e) => {
         else if (field === 'manual_offset') val = Math.round((tool as any)[field]);
         else val = (tool as any)[field];
 
-        editContext = { machineId, isUpper, field };
+        editContext = { machineId, path: tool?.path, toolNum: tool?.tool_num, field };
         document.getElementById('edit-title')!.textContent = `${machineId}호기 ${title}`;
         (document.getElementById('edit-input') as HTMLInputElement).value = val.toString();
         
@@ -372,30 +679,57 @@ document.addEventListener('click', async (e) => {
         editModal.classList.add('flex');
     } 
     else if (action === 'toggle') {
-        const tool = isUpper ? machine.upper_tool : machine.lower_tool;
+        const tool = machine.tools[channel];
         try {
             await invoke('update_tool_settings', {
-                machineId, isUpper, active: !tool.active,
-                basicSize: null, manualOffset: null, offsetRate: null
+                machineId, path: tool.path, toolNum: tool.tool_num,
+                settings: { active: !tool.active }
             });
             fetchState();
         } catch (err) { alert(err); }
     }
+    else if (action === 'tool-change') {
+        const toolNum = Number(actionTarget.getAttribute('data-tool'));
+        const title = actionTarget.getAttribute('data-title') || '';
+        if (!confirm(`${machineId + 1}호기 ${title}(T${toolNum}) 공구 교체를 기록할까요?\n대기중인 측정값은 버려집니다.`)) return;
+        try {
+            await invoke('mark_tool_change', { machineId, path, toolNum });
+            fetchState();
+        } catch (err) { alert("공구 교체 처리 실패: " + err); }
+    }
+    else if (action === 'reset-drift') {
+        const toolNum = Number(actionTarget.getAttribute('data-tool'));
+        const title = actionTarget.getAttribute('data-title') || '';
+        if (!confirm(`${machineId + 1}호기 ${title}(T${toolNum}) 누적 보정량을 리셋할까요?\n공구 점검 후에만 리셋하세요.`)) return;
+        const password = prompt('관리자 비밀번호');
+        if (password === null) return;
+        try {
+            await invoke('reset_tool_drift', { machineId, path, toolNum, password });
+            fetchState();
+        } catch (err) { alert("누적 보정량 리셋 실패: " + err); }
+    }
     else if (action === 'history') {
         const toolNum = Number(actionTarget.getAttribute('data-tool'));
         try {
-            const logs: OffsetLog[] = await invoke('get_offset_history', { machineId, toolNum, limit: 100 });
+            const logs: OffsetLog[] = await invoke('get_offset_history', { machineId, path, toolNum, limit: 100 });
             document.getElementById('history-title')!.textContent = `오프셋 수정 이력 (${machineId + 1}호기 - 공구 ${toolNum})`;
+            const undoButton = document.getElementById('btn-undo')!;
+            undoButton.setAttribute('data-id', String(machineId));
+            undoButton.setAttribute('data-path', String(path));
+            undoButton.setAttribute('data-tool', String(toolNum));
             
             const historyBody = document.getElementById('history-body')!;
             // 값을 1000으로 나누어 소수점 3자리 형태로 표시
             historyBody.innerHTML = logs.map(log => `
                 <tr class="border-b hover:bg-gray-100">
-                    <td class="p-1">${new Date(log.timestamp).toLocaleString()}</td>
+                    <td class="p-1"><span class="text-gray-400">#${log.id}</span> ${new Date(log.timestamp).toLocaleString()}</td>
                     <td class="p-1">${(log.old_value / 1000).toFixed(3)}</td>
                     <td class="p-1 font-bold ${log.change_amount > 0 ? 'text-red-600' : 'text-blue-600'}">${(log.change_amount / 1000).toFixed(3)}</td>
                     <td class="p-1">${(log.new_value / 1000).toFixed(3)}</td>
                     <td class="p-1">${log.success ? 'O' : 'X'}</td>
+                    <td class="p-1 ${log.verified ? '' : 'text-red-600 font-bold'}">${log.verified ? 'O' : 'X'}</td>
+                    <td class="p-1">${log.decision ? DECISION_LABELS[log.decision] : '-'}${log.undo_of !== null ? ` (#${log.undo_of})` : ''}</td>
+                    <td class="p-1" title="${log.rationale ?? ''}">${log.requested_change !== null ? (log.requested_change / 1000).toFixed(3) : '-'}</td>
                 </tr>
             `).join('');
 
@@ -403,12 +737,102 @@ document.addEventListener('click', async (e) => {
             historyModal.classList.add('flex');
         } catch (err) { alert("로그 조회 실패: " + err); }
     }
+    else if (action === 'undo') {
+        const toolNum = Number(actionTarget.getAttribute('data-tool'));
+        if (!confirm(`${machineId + 1}호기 공구 ${toolNum}의 마지막 옵셋 변경을 되돌릴까요?`)) return;
+        try {
+            await invoke('undo_last_offset', { machineId, path, toolNum });
+            historyModal.classList.add('hidden');
+            historyModal.classList.remove('flex');
+            fetchState();
+        } catch (err) { alert("되돌리기 실패: " + err); }
+    }
+    else if (action === 'approve') {
+        const id = Number(actionTarget.getAttribute('data-approval'));
+        const toolNum = Number(actionTarget.getAttribute('data-tool'));
+        const passwordInput = document.getElementById('approval-password') as HTMLInputElement;
+        try {
+            await invoke('approve_correction', { machineId, path, toolNum, id, password: passwordInput.value });
+            fetchState();
+        } catch (err) { alert("승인 실패: " + err); }
+    }
+    else if (action === 'apply-rate') {
+        const toolNum = Number(actionTarget.getAttribute('data-tool'));
+        const passwordInput = document.getElementById('approval-password') as HTMLInputElement;
+        try {
+            await invoke('apply_rate_suggestion', { machineId, path, toolNum, password: passwordInput.value });
+            fetchState();
+        } catch (err) { alert("보정률 적용 실패: " + err); }
+    }
+    else if (action === 'recipes') {
+        recipeMachineId = machineId;
+        await renderRecipes();
+        recipeModal.classList.remove('hidden');
+        recipeModal.classList.add('flex');
+    }
+    else if (action === 'activate-recipe') {
+        const name = actionTarget.getAttribute('data-recipe')!;
+        if (!confirm(`${machineId + 1}호기에 레시피 '${name}'를 적용할까요?\n모든 보정 채널 설정이 바뀌고 대기 중인 측정값은 버려집니다.`)) return;
+        const passwordInput = document.getElementById('recipe-password') as HTMLInputElement;
+        try {
+            await invoke('activate_recipe', { machineId, name, password: passwordInput.value });
+            passwordInput.value = '';
+            await fetchState();
+            await renderRecipes();
+        } catch (err) { alert("레시피 적용 실패: " + err); }
+    }
+    else if (action === 'clone-recipe') {
+        const source = actionTarget.getAttribute('data-recipe')!;
+        const name = prompt(`'${source}' 복제 - 새 이름`);
+        if (!name) return;
+        const partNumber = prompt('품번 (비우면 그대로)') || null;
+        const passwordInput = document.getElementById('recipe-password') as HTMLInputElement;
+        try {
+            await invoke('clone_recipe', { source, name, partNumber, password: passwordInput.value });
+            passwordInput.value = '';
+            await renderRecipes();
+        } catch (err) { alert("레시피 복제 실패: " + err); }
+    }
+    else if (action === 'reject') {
+        const id = Number(actionTarget.getAttribute('data-approval'));
+        const toolNum = Number(actionTarget.getAttribute('data-tool'));
+        if (!confirm(`보정 요청 #${id}을 거부할까요?`)) return;
+        try {
+            await invoke('reject_correction', { machineId, path, toolNum, id });
+            fetchState();
+        } catch (err) { alert("거부 실패: " + err); }
+    }
+    else if (action === 'shadow-history') {
+        const toolNum = Number(actionTarget.getAttribute('data-tool'));
+        try {
+            const logs: ShadowLog[] = await invoke('get_shadow_history', { machineId, path, toolNum, limit: 100 });
+            document.getElementById('shadow-title')!.textContent = `모의 보정 이력 (${machineId + 1}호기 - 공구 ${toolNum})`;
+            document.getElementById('shadow-body')!.innerHTML = logs.map(log => `
+                <tr class="border-b hover:bg-gray-100">
+                    <td class="p-1">${new Date(log.timestamp).toLocaleString()}</td>
+                    <td class="p-1">${log.avg_gauge !== null ? log.avg_gauge.toFixed(4) : '-'}</td>
+                    <td class="p-1">${(log.current_value / 1000).toFixed(3)}</td>
+                    <td class="p-1 font-bold ${log.change_amount > 0 ? 'text-red-600' : 'text-blue-600'}">${(log.change_amount / 1000).toFixed(3)}</td>
+                    <td class="p-1" title="${log.rationale ?? ''}">${(log.requested_change / 1000).toFixed(3)}</td>
+                    <td class="p-1">${DECISION_LABELS[log.decision]}</td>
+                </tr>
+            `).join('');
+            shadowModal.classList.remove('hidden');
+            shadowModal.classList.add('flex');
+        } catch (err) { alert("모의 이력 조회 실패: " + err); }
+    }
+    else if (action === 'spc') {
+        spcContext = { machineId, path, toolNum: Number(actionTarget.getAttribute('data-tool')) };
+        await renderSpc();
+        spcModal.classList.remove('hidden');
+        spcModal.classList.add('flex');
+    }
     else if (action === 'write-offset') {
         const toolNum = Number(actionTarget.getAttribute('data-tool'));
         const title = actionTarget.getAttribute('data-title') || '옵셋 변경';
         
         // 현재 클릭한 기계 ID, 어떤 액션인지, 툴 번호를 context에 저장
-        editContext = { machineId, field: 'write_offset', toolNum };
+        editContext = { machineId, field: 'write_offset', path, toolNum };
         
         document.getElementById('edit-title')!.textContent = `${machineId + 1}호기 ${title} (변화량 입력)`;
         (document.getElementById('edit-input') as HTMLInputElement).value = '0'; // 기본값 0으로 초기화
@@ -446,6 +870,22 @@ document.getElementById('btn-history-close')!.addEventListener('click', () => {
     historyModal.classList.remove('flex');
 });
 
+document.getElementById('btn-approvals')!.addEventListener('click', () => {
+    (document.getElementById('approval-password') as HTMLInputElement).value = '';
+    approvalModal.classList.remove('hidden');
+    approvalModal.classList.add('flex');
+});
+
+document.getElementById('btn-approval-close')!.addEventListener('click', () => {
+    approvalModal.classList.add('hidden');
+    approvalModal.classList.remove('flex');
+});
+
+document.getElementById('btn-shadow-close')!.addEventListener('click', () => {
+    shadowModal.classList.add('hidden');
+    shadowModal.classList.remove('flex');
+});
+
 document.getElementById('btn-raw-gauge-close')!.addEventListener('click', () => {
     const rawModal = document.getElementById('raw-gauge-modal')!;
     rawModal.classList.add('hidden');
@@ -473,23 +913,25 @@ document.getElementById('btn-edit-save')!.addEventListener('click', async (e) =>
             const offsetDiff = Math.round(inputVal * 1000); // 소수점이 아니라 정수 형태의 단위(예: 10, -5)를 그대로 넘긴다고 가정
             await invoke('force_write_offset', {
                 machineId: editContext.machineId,
+                path: editContext.path,
                 toolNum: editContext.toolNum,
                 offsetDiff: offsetDiff
             });
         } else {
             const finalVal = editContext.field === 'offset_rate' ? inputVal / 100.0 : inputVal;
-            const args: any = {
-                machineId: editContext.machineId,
-                isUpper: editContext.isUpper,
-                basicSize: null, manualOffset: null, offsetRate: null, active: null, toolNum: null
-            };
+            const settings: any = {};
 
-            if (editContext.field === 'basic_size') args.basicSize = finalVal;
-            if (editContext.field === 'manual_offset') args.manualOffset = finalVal;
-            if (editContext.field === 'offset_rate') args.offsetRate = finalVal;
-            if (editContext.field === 'tool_num') args.toolNum = Math.floor(finalVal);
+            if (editContext.field === 'basic_size') settings.basic_size = finalVal;
+            if (editContext.field === 'manual_offset') settings.manual_offset = finalVal;
+            if (editContext.field === 'offset_rate') settings.offset_rate = finalVal;
+            if (editContext.field === 'tool_num') settings.tool_num = Math.floor(finalVal);
 
-            await invoke('update_tool_settings', args);
+            await invoke('update_tool_settings', {
+                machineId: editContext.machineId,
+                path: editContext.path,
+                toolNum: editContext.toolNum,
+                settings
+            });
         }
         await fetchState();
         editModal.classList.add('hidden');
@@ -499,6 +941,112 @@ document.getElementById('btn-edit-save')!.addEventListener('click', async (e) =>
     }
 });
 
+// SPC 보고서: 선택한 기간(시간)의 관리한계, 공정능력, 런 규칙 위반
+async function renderSpc() {
+    if (!spcContext) return;
+    const { machineId, path, toolNum } = spcContext;
+    const hours = Number((document.getElementById('spc-window') as HTMLSelectElement).value);
+    const from = hours > 0 ? new Date(Date.now() - hours * 3600 * 1000).toISOString() : null;
+    document.getElementById('spc-title')!.textContent = `SPC (${machineId + 1}호기 - 공구 ${toolNum})`;
+    const summary = document.getElementById('spc-summary')!;
+    const body = document.getElementById('spc-body')!;
+    try {
+        const report: SpcReport = await invoke('get_spc_report', { machineId, path, toolNum, from, to: null });
+        const num = (v: number | null | undefined, digits = 4) => v === null || v === undefined ? '-' : v.toFixed(digits);
+        const limits = (l: ControlLimits | undefined) => l ? `${num(l.lcl)} / ${num(l.center)} / ${num(l.ucl)}` : '-';
+        const cap = report.capability;
+        summary.innerHTML = `
+            <div>측정 수: <b>${report.count}</b>, 평균: <b>${num(report.mean)}</b></div>
+            <div>I 관리도 (LCL / CL / UCL): ${limits(report.imr?.individuals)}</div>
+            <div>MR 관리도: ${limits(report.imr?.moving_range)}</div>
+            <div>X̄ 관리도 (n=${report.xbar_r?.subgroup_size ?? '-'}): ${limits(report.xbar_r?.xbar)}</div>
+            <div>R 관리도: ${limits(report.xbar_r?.range)}</div>
+            <div>σ(군내): ${num(cap?.sigma_within)}, σ(전체): ${num(cap?.sigma_overall)}</div>
+            <div class="font-bold">Cp ${num(cap?.cp, 2)} / Cpk ${num(cap?.cpk, 2)} / Pp ${num(cap?.pp, 2)} / Ppk ${num(cap?.ppk, 2)}</div>`;
+        body.innerHTML = report.violations.map(v => {
+            const value = v.chart === 'individuals' ? report.imr?.values[v.index] : report.xbar_r?.means[v.index];
+            return `
+                <tr class="border-b hover:bg-gray-100">
+                    <td class="p-1">${v.chart === 'individuals' ? 'I' : 'X̄'}</td>
+                    <td class="p-1">${v.index + 1}</td>
+                    <td class="p-1">${num(value)}</td>
+                    <td class="p-1">${v.rule}</td>
+                    <td class="p-1 text-left">${v.description}</td>
+                </tr>`;
+        }).join('');
+    } catch (err) {
+        summary.textContent = "SPC 계산 실패: " + err;
+        body.innerHTML = '';
+    }
+}
+
+document.getElementById('spc-window')!.addEventListener('change', () => renderSpc());
+
+document.getElementById('btn-spc-close')!.addEventListener('click', () => {
+    spcModal.classList.add('hidden');
+    spcModal.classList.remove('flex');
+    spcContext = null;
+});
+
+// 백테스트: SPC 창에서 고른 기간의 측정값을 후보 설정으로 다시 계산 (비운 항목은 현재 설정)
+document.getElementById('btn-backtest')!.addEventListener('click', () => {
+    if (!spcContext) return;
+    document.getElementById('backtest-title')!.textContent =
+        `백테스트 (${spcContext.machineId + 1}호기 - 공구 ${spcContext.toolNum})`;
+    document.getElementById('backtest-summary')!.innerHTML = '';
+    document.getElementById('backtest-body')!.innerHTML = '';
+    backtestModal.classList.remove('hidden');
+    backtestModal.classList.add('flex');
+});
+
+document.getElementById('btn-backtest-run')!.addEventListener('click', async () => {
+    if (!spcContext) return;
+    const { machineId, path, toolNum } = spcContext;
+    const hours = Number((document.getElementById('spc-window') as HTMLSelectElement).value);
+    const from = hours > 0 ? new Date(Date.now() - hours * 3600 * 1000).toISOString() : null;
+    const input = (id: string) => (document.getElementById(id) as HTMLInputElement).value;
+    const optNum = (id: string) => input(id) === '' ? null : Number(input(id));
+    const optJson = (id: string) => input(id) === '' ? null : JSON.parse(input(id));
+    const candidate = {
+        offset_rate: optNum('bt-offset-rate'),
+        batch_size: optNum('bt-batch-size'),
+        deadband: optNum('bt-deadband'),
+        parts_in_transit: optNum('bt-transit'),
+        outlier_rule: optJson('bt-outlier'),
+        strategy: optJson('bt-strategy'),
+    };
+    const summary = document.getElementById('backtest-summary')!;
+    const body = document.getElementById('backtest-body')!;
+    try {
+        const report: BacktestReport = await invoke('run_backtest', { machineId, path, toolNum, from, to: null, candidate });
+        const num = (v: number | null, digits = 4) => v === null ? '-' : v.toFixed(digits);
+        const stats = (s: DeviationStats) =>
+            `평균 ${num(s.mean)}, σ ${num(s.std_dev)}, 최대 ${num(s.max_abs)}${s.out_of_tolerance !== null ? `, 규격 밖 ${s.out_of_tolerance}개` : ''}`;
+        const written = report.corrections.filter(c => c.change_amount !== 0).length;
+        summary.innerHTML = `
+            <div>측정 수: <b>${report.readings}</b> (쿨다운 제외 ${report.excluded}), 보정: 실제 <b>${report.actual_corrections}</b>회 / 후보 <b>${written}</b>회</div>
+            <div>실제 편차: ${stats(report.actual)}</div>
+            <div class="font-bold">후보 편차 (예측): ${stats(report.predicted)}</div>`;
+        body.innerHTML = report.corrections.map(c => `
+            <tr class="border-b hover:bg-gray-100" title="${c.rationale}">
+                <td class="p-1">${c.timestamp}</td>
+                <td class="p-1">${c.reading + 1}</td>
+                <td class="p-1">${num(c.avg_gauge)}</td>
+                <td class="p-1">${(c.requested_change / 1000).toFixed(3)}</td>
+                <td class="p-1">${(c.change_amount / 1000).toFixed(3)}</td>
+                <td class="p-1">${DECISION_LABELS[c.decision]}</td>
+            </tr>`).join('');
+    } catch (err) {
+        summary.textContent = "백테스트 실패: " + err;
+        body.innerHTML = '';
+    }
+});
+
+document.getElementById('btn-backtest-close')!.addEventListener('click', () => {
+    backtestModal.classList.add('hidden');
+    backtestModal.classList.remove('flex');
+});
+
 function renderRawGaugeTable() {
     const tbody = document.getElementById('raw-gauge-body')!;
     
@@ -513,7 +1061,13 @@ function renderRawGaugeTable() {
         let rowClass = 'text-gray-400 hover:bg-gray-50'; // 기본: 사용됨(2)
         let statusBadge = '<span class="bg-gray-200 text-gray-600 px-2 py-1 rounded-full text-xs">사용됨</span>';
 
-        if (log.is_used === 0) {
+        if (log.is_used === 3) {
+            rowClass = 'text-gray-400 line-through hover:bg-gray-50';
+            statusBadge = '<span class="bg-red-100 text-red-600 px-2 py-1 rounded-full text-xs">제외됨</span>';
+        } else if (log.is_used === 4) {
+            rowClass = 'text-purple-400 line-through hover:bg-gray-50';
+            statusBadge = `<span class="bg-purple-100 text-purple-700 px-2 py-1 rounded-full text-xs" title="${log.reject_reason ?? ''}">이상치</span>`;
+        } else if (log.is_used === 0) {
             rowClass = 'bg-green-100 text-green-900 font-bold border-l-4 border-green-600';
             statusBadge = '<span class="bg-green-200 text-green-800 px-2 py-1 rounded-full text-xs shadow-sm">대기중</span>';
         } else if (log.is_used === 1) {
@@ -527,6 +1081,7 @@ function renderRawGaugeTable() {
             <tr class="border-b transition-colors ${rowClass}">
                 <td class="p-2 text-center">${new Date(log.timestamp).toLocaleString()}</td>
                 <td class="p-2 text-center">${log.active_line}호기</td>
+                <td class="p-2 text-center font-mono">${log.part_number ?? '-'}</td>
                 <td class="p-2 text-center"><span class="bg-blue-100 text-blue-800 px-1 rounded text-xs font-bold">${typeLabel}</span></td>
                 <td class="p-2 text-right font-mono text-lg pr-4">${log.measured_value.toFixed(4)}</td>
                 <td class="p-2 text-center">${statusBadge}</td>
@@ -559,3 +1114,23 @@ async function initApp() {
 }
 
 initApp();
+
+document.getElementById('btn-recipe-create')!.addEventListener('click', async () => {
+    const input = (id: string) => document.getElementById(id) as HTMLInputElement;
+    try {
+        await invoke('create_recipe', {
+            machineId: recipeMachineId,
+            name: input('recipe-name').value,
+            partNumber: input('recipe-part').value,
+            description: input('recipe-description').value || null,
+            password: input('recipe-password').value,
+        });
+        ['recipe-name', 'recipe-part', 'recipe-description', 'recipe-password'].forEach(id => input(id).value = '');
+        await renderRecipes();
+    } catch (err) { alert("레시피 저장 실패: " + err); }
+});
+
+document.getElementById('btn-recipe-close')!.addEventListener('click', () => {
+    recipeModal.classList.add('hidden');
+    recipeModal.classList.remove('flex');
+});